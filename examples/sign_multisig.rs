@@ -78,14 +78,9 @@ fn main() {
         elements::EcdsaSigHashType::All,
     );
 
-    let descriptor_str = format!(
-        "elwsh(multi(2,{},{},{}))",
-        public_keys[0], public_keys[1], public_keys[2],
-    );
-
-    // Descriptor for the output being spent
+    // Descriptor for the output being spent: elwsh(multi(2,A,B,C))
     let my_descriptor =
-        BitcoinDescriptor::from_str(&descriptor_str[..]).expect("parse descriptor string");
+        BitcoinDescriptor::new_wsh_multi(2, public_keys.clone()).expect("build descriptor");
 
     // Check weight for witness satisfaction cost ahead of time.
     // 106 (serialized witnessScript)
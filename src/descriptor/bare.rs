@@ -10,15 +10,15 @@
 
 use core::fmt;
 
-use elements::{self, script, secp256k1_zkp, Script};
+use elements::{self, secp256k1_zkp, Script};
 
 use super::checksum::verify_checksum;
-use super::ELMTS_STR;
+use super::{StructuredSatisfaction, WitnessItem, WitnessRole, ELMTS_STR};
 use crate::descriptor::checksum;
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
 use crate::policy::{semantic, Liftable};
-use crate::util::{varint_len, witness_to_scriptsig};
+use crate::util::{satisfaction_weight, varint_len, witness_to_scriptsig};
 use crate::{
     elementssig_to_rawsig, BareCtx, Error, ForEachKey, Miniscript, MiniscriptKey, Satisfier,
     ToPublicKey, TranslatePk, Translator,
@@ -34,9 +34,13 @@ pub struct Bare<Pk: MiniscriptKey> {
 
 impl<Pk: MiniscriptKey> Bare<Pk> {
     /// Create a new raw descriptor
+    ///
+    /// Only the consensus top level rules are checked. Standardness rules,
+    /// such as bare multisig having at most 3 keys, are checked by
+    /// [`Bare::sanity_check`].
     pub fn new(ms: Miniscript<Pk, BareCtx>) -> Result<Self, Error> {
         // do the top-level checks
-        BareCtx::top_level_checks(&ms)?;
+        BareCtx::top_level_consensus_checks(&ms)?;
         Ok(Self { ms })
     }
 
@@ -52,6 +56,7 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
 
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
+        BareCtx::top_level_policy_checks(&self.ms)?;
         self.ms.sanity_check()?;
         Ok(())
     }
@@ -70,10 +75,14 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
-        let scriptsig_size = self.ms.max_satisfaction_size()?;
-        // scriptSig varint difference between non-satisfied (0) and satisfied
-        let scriptsig_varint_diff = varint_len(scriptsig_size) - varint_len(0);
-        Ok(4 * (scriptsig_varint_diff + scriptsig_size))
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components()?;
+        Ok(satisfaction_weight(scriptsig_size, witness_weight))
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Bare::max_weight_to_satisfy`] is made up of.
+    pub(super) fn max_weight_components(&self) -> Result<(usize, usize, usize), Error> {
+        Ok((self.ms.max_satisfaction_size()?, 0, 0))
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
@@ -115,10 +124,21 @@ impl<Pk: MiniscriptKey + ToPublicKey> Bare<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        let ms = self.ms.satisfy(satisfier)?;
-        let script_sig = witness_to_scriptsig(&ms);
-        let witness = vec![];
-        Ok((witness, script_sig))
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Bare::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let ms = self.ms.satisfy_items(satisfier, false /* allow_mall */)?;
+        Ok(StructuredSatisfaction::from_script_sig(ms))
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
@@ -166,7 +186,6 @@ impl_from_tree!(
                 args: top.args.clone(),
             };
             let sub = Miniscript::<Pk, BareCtx>::from_tree(&new_tree)?;
-            BareCtx::top_level_checks(&sub)?;
             Bare::new(sub)
         } else {
             Err(Error::Unexpected("Not an elements descriptor".to_string()))
@@ -242,11 +261,16 @@ impl<Pk: MiniscriptKey> Pkh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_weight_to_satisfy(&self) -> usize {
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components();
+        satisfaction_weight(scriptsig_size, witness_weight)
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Pkh::max_weight_to_satisfy`] is made up of.
+    pub(super) fn max_weight_components(&self) -> (usize, usize, usize) {
         // OP_72 + <sig(71)+sigHash(1)> + OP_33 + <pubkey>
-        let scriptsig_size = 73 + BareCtx::pk_len(&self.pk);
-        // scriptSig varint different between non-satisfied (0) and satisfied
-        let scriptsig_varint_diff = varint_len(scriptsig_size) - varint_len(0);
-        4 * (scriptsig_varint_diff + scriptsig_size)
+        let scriptsig_size = BareCtx::max_sig_size() + BareCtx::pk_len(&self.pk);
+        (scriptsig_size, 0, 0)
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
@@ -296,17 +320,27 @@ impl<Pk: MiniscriptKey + ToPublicKey> Pkh<Pk> {
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
     pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Pkh::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
     where
         S: Satisfier<Pk>,
     {
         if let Some(sig) = satisfier.lookup_ecdsa_sig(&self.pk) {
-            let sig_vec = elementssig_to_rawsig(&sig);
-            let script_sig = script::Builder::new()
-                .push_slice(&sig_vec[..])
-                .push_key(&self.pk.to_public_key())
-                .into_script();
-            let witness = vec![];
-            Ok((witness, script_sig))
+            Ok(StructuredSatisfaction::from_script_sig(vec![
+                WitnessItem::new(WitnessRole::Signature, elementssig_to_rawsig(&sig)),
+                WitnessItem::new(WitnessRole::PublicKey, self.pk.to_public_key().to_bytes()),
+            ]))
         } else {
             Err(Error::MissingSig(self.pk.to_public_key()))
         }
@@ -48,11 +48,11 @@ use elements::hashes::{sha256d, Hash};
 use elements::{self, script, secp256k1_zkp, Script};
 
 use super::super::checksum::{desc_checksum, verify_checksum};
-use super::super::ELMTS_STR;
+use super::super::{StructuredSatisfaction, WitnessItem, WitnessRole, ELMTS_STR};
 use super::{CovError, CovOperations};
 use crate::expression::{self, FromTree};
 use crate::extensions::ParseableExt;
-use crate::miniscript::lex::{lex, Token as Tk, TokenIter};
+use crate::miniscript::lex::{lex_with_max_size, Token as Tk, TokenIter};
 use crate::miniscript::limits::{
     MAX_OPS_PER_SCRIPT, MAX_SCRIPT_SIZE, MAX_STANDARD_P2WSH_SCRIPT_SIZE,
 };
@@ -138,6 +138,21 @@ impl<Pk: MiniscriptKey, Ext: Extension> LegacyCSFSCov<Pk, Ext> {
 
     /// Create a satisfaction for the Covenant Descriptor
     pub fn satisfy<S: Satisfier<Pk>>(&self, s: S, allow_mall: bool) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+        Ext: ParseableExt,
+    {
+        let wit = self.satisfy_items(s, allow_mall)?;
+        Ok(wit.into_iter().map(|item| item.data).collect())
+    }
+
+    /// Create a satisfaction for the Covenant Descriptor as
+    /// [`LegacyCSFSCov::satisfy`], with every item tagged with its role
+    pub(crate) fn satisfy_items<S: Satisfier<Pk>>(
+        &self,
+        s: S,
+        allow_mall: bool,
+    ) -> Result<Vec<WitnessItem>, Error>
     where
         Pk: ToPublicKey,
         Ext: ParseableExt,
@@ -166,27 +181,33 @@ impl<Pk: MiniscriptKey, Ext: Extension> LegacyCSFSCov<Pk, Ext> {
                 return Err(CovError::CovenantSighashTypeMismatch)?;
             }
 
-            vec![
-                Vec::from(sig.serialize_der().as_ref()), // The covenant sig
-                serialize(&sighash_ty),                  // item 10(11)
-                serialize(&n_locktime),                  // item 9(10)
-                serialize(&hash_outputs),                // item 8(9)
-                serialize(&n_sequence),                  // item 7(8)
-                serialize(&value),                       // item 6(7)
-                serialize(script_code),                  // item 5(6)
-                serialize(&outpoint),                    // item 4(5)
-                serialize(&hash_issuances),              // ELEMENTS EXTRA: item 3b(4)
-                serialize(&hash_sequence),               // item 3
-                serialize(&hash_prevouts),               // item 2
-                serialize(&n_version),                   // item 1
-            ]
+            let sighash_items = vec![
+                serialize(&sighash_ty),     // item 10(11)
+                serialize(&n_locktime),     // item 9(10)
+                serialize(&hash_outputs),   // item 8(9)
+                serialize(&n_sequence),     // item 7(8)
+                serialize(&value),          // item 6(7)
+                serialize(script_code),     // item 5(6)
+                serialize(&outpoint),       // item 4(5)
+                serialize(&hash_issuances), // ELEMENTS EXTRA: item 3b(4)
+                serialize(&hash_sequence),  // item 3
+                serialize(&hash_prevouts),  // item 2
+                serialize(&n_version),      // item 1
+            ];
+            // The covenant sig
+            let mut wit = vec![WitnessItem::new(
+                WitnessRole::Signature,
+                Vec::from(sig.serialize_der().as_ref()),
+            )];
+            wit.extend(
+                sighash_items
+                    .into_iter()
+                    .map(|item| WitnessItem::new(WitnessRole::Other, item)),
+            );
+            wit
         };
 
-        let ms_wit = if !allow_mall {
-            self.ms.satisfy(s)?
-        } else {
-            self.ms.satisfy_malleable(s)?
-        };
+        let ms_wit = self.ms.satisfy_items(s, allow_mall)?;
         wit.extend(ms_wit);
         Ok(wit)
     }
@@ -261,7 +282,7 @@ impl<Ext: ParseableExt> LegacyCSFSCov<bitcoin::PublicKey, Ext> {
     where
         Ext: ParseableExt,
     {
-        let tokens = lex(script)?;
+        let tokens = lex_with_max_size(script, Segwitv0::max_script_size())?;
         let mut iter = TokenIter::new(tokens);
 
         let pk = LegacyCSFSCov::<bitcoin::PublicKey, Ext>::check_cov_script(&mut iter)?;
@@ -295,7 +316,7 @@ impl_from_tree!(
             let pk = expression::terminal(&top.args[0], |pk| Pk::from_str(pk))?;
             let top = &top.args[1];
             let sub = Miniscript::from_tree(top)?;
-            Segwitv0::top_level_checks(&sub)?;
+            Segwitv0::top_level_consensus_checks(&sub)?;
             Ok(LegacyCSFSCov { pk, ms: sub })
         } else {
             Err(Error::Unexpected(format!(
@@ -347,6 +368,7 @@ where
 {
     /// Sanity checks for this covenant descriptor
     pub fn sanity_check(&self) -> Result<(), Error> {
+        Segwitv0::top_level_policy_checks(&self.ms)?;
         self.ms.sanity_check()?;
         // Additional local check for p2wsh script size
         let ss = COV_SCRIPT_SIZE - if self.ms.ext.has_free_verify { 1 } else { 0 };
@@ -406,25 +428,52 @@ where
         S: Satisfier<Pk>,
         Ext: ParseableExt,
     {
-        let mut witness = self.satisfy(satisfier, /*allow_mall*/ false)?;
-        witness.push(self.encode().into_bytes());
-        let script_sig = Script::new();
-        Ok((witness, script_sig))
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`LegacyCSFSCov::get_satisfaction`] with
+    /// every item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+        Ext: ParseableExt,
+    {
+        let mut witness = self.satisfy_items(satisfier, /*allow_mall*/ false)?;
+        witness.push(WitnessItem::new(
+            WitnessRole::Script,
+            self.encode().into_bytes(),
+        ));
+        Ok(StructuredSatisfaction::from_witness(witness))
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
     /// transaction.
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        let (_, witness_weight, _) = self.max_weight_components()?;
+        Ok(witness_weight)
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`LegacyCSFSCov::max_satisfaction_weight`] is made up
+    /// of. The weight of the scriptSig length byte is counted in the witness
+    /// weight.
+    pub(crate) fn max_weight_components(&self) -> Result<(usize, usize, usize), Error> {
         let script_size =
             self.ms.script_size() + 58 - if self.ms.ext.has_free_verify { 1 } else { 0 };
         let max_sat_elems = self.ms.max_satisfaction_witness_elements()? + 12;
         let max_sat_size = self.ms.max_satisfaction_size()? + 275;
 
-        Ok(4 +  // scriptSig length byte
+        let witness_weight = 4 +  // scriptSig length byte
             varint_len(script_size) +
             script_size +
             varint_len(max_sat_elems) +
-            max_sat_size)
+            max_sat_size;
+        Ok((0, witness_weight, max_sat_elems))
     }
 
     /// This returns the entire explicit script as the script code.
@@ -12,6 +12,7 @@
 //! these with BIP32 paths, pay-to-contract instructions, etc.
 //!
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::Range;
@@ -22,16 +23,18 @@ pub mod pegin;
 
 use bitcoin::util::address::WitnessVersion;
 use elements::hashes::{hash160, ripemd160, sha256};
+use elements::taproot::{TapLeafHash, TaprootMerkleBranch};
 use elements::{secp256k1_zkp as secp256k1, secp256k1_zkp, Script, TxIn};
 use {bitcoin, elements};
 
 use self::checksum::verify_checksum;
 use crate::extensions::{CovExtArgs, ExtParam, ParseableExt};
-use crate::miniscript::{Legacy, Miniscript, Segwitv0};
+use crate::miniscript::{Legacy, Miniscript, Segwitv0, Tap};
+use crate::util::{satisfaction_weight, varint_len, witness_to_scriptsig};
 use crate::{
     expression, hash256, miniscript, BareCtx, CovenantExt, Error, ExtTranslator, Extension,
-    ForEachKey, MiniscriptKey, NoExt, Satisfier, ToPublicKey, TranslateExt, TranslatePk,
-    Translator,
+    ForEachKey, MiniscriptKey, NoExt, Satisfier, ScriptContext, SigType, ToPublicKey, TranslateExt,
+    TranslatePk, Translator,
 };
 
 mod bare;
@@ -58,6 +61,7 @@ pub use self::key::{
     SinglePriv, SinglePub, SinglePubKey, Wildcard,
 };
 pub use self::tr::{TapTree, Tr};
+pub use crate::miniscript::satisfy::{WitnessItem, WitnessRole};
 /// Alias type for a map of public key to secret key
 ///
 /// This map is returned whenever a descriptor that contains secrets is parsed using
@@ -213,6 +217,95 @@ impl DescriptorInfo {
     }
 }
 
+/// Breakdown of the worst-case weight needed to satisfy a descriptor, as
+/// returned by [`Descriptor::satisfaction_weight_breakdown`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WeightBreakdown<Pk: MiniscriptKey> {
+    /// Size in bytes of the scriptSig, excluding its length prefix
+    pub script_sig_size: usize,
+    /// Weight of the witness, including the length prefix of the stack and
+    /// of each of its elements
+    pub witness_weight: usize,
+    /// Number of elements on the witness stack, including the witness
+    /// script and control block, if any
+    pub witness_elements: usize,
+    /// Size in bytes assumed for every signature, including push opcode
+    /// and sighash suffix
+    pub sig_size: usize,
+    /// Weight contributed by the signature of each key of the descriptor,
+    /// in the order the keys appear in it. A signature weighs four times
+    /// its size in the scriptSig and its size in the witness.
+    pub key_sig_weights: Vec<(Pk, usize)>,
+}
+
+impl<Pk: MiniscriptKey> WeightBreakdown<Pk> {
+    /// The total weight, as returned by [`Descriptor::max_weight_to_satisfy`]
+    pub fn weight(&self) -> usize {
+        satisfaction_weight(self.script_sig_size, self.witness_weight)
+    }
+}
+
+/// A satisfaction for a descriptor, returned by
+/// [`Descriptor::get_structured_satisfaction`] so that it can be
+/// inspected before being applied to a [`TxIn`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructuredSatisfaction {
+    /// The items of the witness stack, bottom first
+    pub witness: Vec<WitnessItem>,
+    /// The pushes of the scriptSig, in script order
+    pub script_sig_items: Vec<WitnessItem>,
+    /// The scriptSig
+    pub script_sig: Script,
+}
+
+impl StructuredSatisfaction {
+    // A satisfaction made of the witness items `witness` only
+    pub(crate) fn from_witness(witness: Vec<WitnessItem>) -> Self {
+        StructuredSatisfaction {
+            witness,
+            script_sig_items: vec![],
+            script_sig: Script::new(),
+        }
+    }
+
+    // A satisfaction made of the scriptSig pushes `script_sig_items` only
+    pub(crate) fn from_script_sig(script_sig_items: Vec<WitnessItem>) -> Self {
+        let pushes: Vec<_> = script_sig_items
+            .iter()
+            .map(|item| item.data.clone())
+            .collect();
+        StructuredSatisfaction {
+            witness: vec![],
+            script_sig: witness_to_scriptsig(&pushes),
+            script_sig_items,
+        }
+    }
+
+    /// The weight of the satisfaction: the scriptSig and the witness,
+    /// including their length prefixes
+    pub fn weight(&self) -> usize {
+        let script_sig_size = varint_len(self.script_sig.len()) + self.script_sig.len();
+        let witness_size = varint_len(self.witness.len())
+            + self
+                .witness
+                .iter()
+                .map(|item| varint_len(item.data.len()) + item.data.len())
+                .sum::<usize>();
+        4 * script_sig_size + witness_size
+    }
+
+    /// The raw witness stack
+    pub fn witness_stack(&self) -> Vec<Vec<u8>> {
+        self.witness.iter().map(|item| item.data.clone()).collect()
+    }
+
+    /// Sets the witness and scriptSig of the given `TxIn`
+    pub fn apply(&self, txin: &mut TxIn) {
+        txin.witness.script_witness = self.witness_stack();
+        txin.script_sig = self.script_sig.clone();
+    }
+}
+
 /// Script descriptor
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Descriptor<Pk: MiniscriptKey, T: Extension = CovenantExt<CovExtArgs>> {
@@ -401,6 +494,17 @@ impl<Pk: MiniscriptKey, Ext: Extension> Descriptor<Pk, Ext> {
         Ok(Descriptor::Wsh(Wsh::new_sortedmulti(k, pks)?))
     }
 
+    /// Create a new wsh multi descriptor with threshold `k` and Vec of `pks`,
+    /// keeping the keys in the given order.
+    /// Errors when `k` is not in `1..=pks.len()` or when the miniscript
+    /// exceeds resource limits under segwit context (at most 20 keys)
+    pub fn new_wsh_multi(k: usize, pks: Vec<Pk>) -> Result<Self, Error> {
+        let ms: Miniscript<Pk, Segwitv0> =
+            Miniscript::from_ast(miniscript::decode::Terminal::Multi(k, pks))?;
+        Segwitv0::check_local_validity(&ms)?;
+        Self::new_wsh(ms)
+    }
+
     /// Create new tr descriptor
     /// Errors when miniscript exceeds resource limits under Tap context
     pub fn new_tr(key: Pk, script: Option<tr::TapTree<Pk, NoExt>>) -> Result<Self, Error> {
@@ -438,10 +542,63 @@ impl<Pk: MiniscriptKey, Ext: Extension> Descriptor<Pk, Ext> {
         }
     }
 
+    /// Get the [`SigType`] expected by the script context of the
+    /// [Descriptor]: [`SigType::Schnorr`] for taproot descriptors,
+    /// [`SigType::Ecdsa`] otherwise
+    pub fn sig_type(&self) -> SigType {
+        match *self {
+            Descriptor::Bare(ref _bare) => BareCtx::sig_type(),
+            Descriptor::Pkh(ref _pkh) => Legacy::sig_type(),
+            Descriptor::Wpkh(ref _wpkh) => Segwitv0::sig_type(),
+            Descriptor::Sh(ref sh) => match sh.as_inner() {
+                ShInner::Wsh(..) | ShInner::Wpkh(..) => Segwitv0::sig_type(),
+                ShInner::SortedMulti(..) | ShInner::Ms(..) => Legacy::sig_type(),
+            },
+            Descriptor::Wsh(ref _wsh) => Segwitv0::sig_type(),
+            Descriptor::LegacyCSFSCov(ref _cov) => Segwitv0::sig_type(),
+            Descriptor::Tr(ref _tr) => Tap::sig_type(),
+            Descriptor::TrExt(ref _tr) => Tap::sig_type(),
+        }
+    }
+
     /// Return a string without the checksum
     pub fn to_string_no_chksum(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// Returns the internal key of a taproot descriptor, or `None` for
+    /// non-taproot descriptors.
+    pub fn internal_key(&self) -> Option<&Pk> {
+        match *self {
+            Descriptor::Tr(ref tr) => Some(tr.internal_key()),
+            Descriptor::TrExt(ref tr) => Some(tr.internal_key()),
+            _ => None,
+        }
+    }
+
+    /// Returns the leaf miniscripts of a taproot descriptor, in depth first
+    /// order. Non-taproot descriptors have no leaves.
+    ///
+    /// Descriptors without extensions store their leaves without the
+    /// extension type; those are returned as owned copies.
+    pub fn tap_leaves(&self) -> Vec<Cow<'_, Miniscript<Pk, Tap, Ext>>> {
+        match *self {
+            Descriptor::Tr(ref tr) => tr
+                .iter_scripts()
+                .map(|(_depth, ms)| {
+                    let ms = ms
+                        .map_ext(|e: NoExt| match e {})
+                        .expect("leaf was already checked in the Tap context");
+                    Cow::Owned(ms)
+                })
+                .collect(),
+            Descriptor::TrExt(ref tr) => tr
+                .iter_scripts()
+                .map(|(_depth, ms)| Cow::Borrowed(ms))
+                .collect(),
+            _ => vec![],
+        }
+    }
     /// Checks whether the descriptor is safe.
     ///
     /// Checks whether all the spend paths in the descriptor are possible on the
@@ -464,6 +621,60 @@ impl<Pk: MiniscriptKey, Ext: Extension> Descriptor<Pk, Ext> {
         }
     }
 
+    /// Runs all the checks of [`Descriptor::sanity_check`] and reports every
+    /// failure instead of only the first one.
+    ///
+    /// Every miniscript of the descriptor, including each tapscript leaf,
+    /// is checked with [`Miniscript::sanity_check_all`]: context consensus
+    /// and policy rules, context malleability rules, spend paths without
+    /// signatures, malleability, resource limits, repeated keys and
+    /// timelock mixing. Top level miniscripts are also checked against the
+    /// top level policy rules of their context. Keys of `wpkh` and
+    /// `sortedmulti` descriptors are checked as by [`Descriptor::sanity_check`].
+    pub fn sanity_check_all(&self) -> Result<(), Vec<Error>> {
+        fn ms_check_all<Pk: MiniscriptKey, Ctx: ScriptContext>(
+            ms: &Miniscript<Pk, Ctx>,
+        ) -> Result<(), Vec<Error>> {
+            let mut errors = vec![];
+            if let Err(e) = Ctx::top_level_policy_checks(ms) {
+                errors.push(e);
+            }
+            errors.extend(ms.sanity_check_all().err().unwrap_or_default());
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        }
+
+        fn wsh_check_all<Pk: MiniscriptKey>(wsh: &Wsh<Pk>) -> Result<(), Vec<Error>> {
+            match *wsh.as_inner() {
+                WshInner::SortedMulti(ref smv) => smv.sanity_check().map_err(|e| vec![e]),
+                WshInner::Ms(ref ms) => ms_check_all(ms),
+            }
+        }
+
+        match *self {
+            Descriptor::Bare(ref bare) => ms_check_all(bare.as_inner()),
+            Descriptor::Pkh(_) => Ok(()),
+            Descriptor::Wpkh(ref wpkh) => wpkh.sanity_check().map_err(|e| vec![e]),
+            Descriptor::Wsh(ref wsh) => wsh_check_all(wsh),
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Wsh(ref wsh) => wsh_check_all(wsh),
+                ShInner::Wpkh(ref wpkh) => wpkh.sanity_check().map_err(|e| vec![e]),
+                ShInner::SortedMulti(ref smv) => smv.sanity_check().map_err(|e| vec![e]),
+                ShInner::Ms(ref ms) => ms_check_all(ms),
+            },
+            Descriptor::LegacyCSFSCov(ref cov) => {
+                cov.to_ms().sanity_check_all()?;
+                // Checks the size of the covenant script on top
+                cov.sanity_check().map_err(|e| vec![e])
+            }
+            Descriptor::Tr(ref tr) => tr.sanity_check_all(),
+            Descriptor::TrExt(ref tr) => tr.sanity_check_all(),
+        }
+    }
+
     /// Computes an upper bound on the difference between a non-satisfied
     /// `TxIn`'s `segwit_weight` and a satisfied `TxIn`'s `segwit_weight`
     ///
@@ -516,6 +727,58 @@ impl<Pk: MiniscriptKey, Ext: Extension> Descriptor<Pk, Ext> {
         Ok(weight)
     }
 
+    /// Splits [`Descriptor::max_weight_to_satisfy`] into its components:
+    /// the scriptSig size, the witness weight, the number of witness
+    /// elements, the assumed size of each signature and the weight each
+    /// key's signature contributes.
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
+    pub fn satisfaction_weight_breakdown(&self) -> Result<WeightBreakdown<Pk>, Error> {
+        let (script_sig_size, witness_weight, witness_elements) = match *self {
+            Descriptor::Bare(ref bare) => bare.max_weight_components()?,
+            Descriptor::Pkh(ref pkh) => pkh.max_weight_components(),
+            Descriptor::Wpkh(ref wpkh) => wpkh.max_weight_components(),
+            Descriptor::Wsh(ref wsh) => wsh.max_weight_components()?,
+            Descriptor::Sh(ref sh) => sh.max_weight_components()?,
+            Descriptor::Tr(ref tr) => tr.max_weight_components()?,
+            Descriptor::TrExt(ref tr) => tr.max_weight_components()?,
+            Descriptor::LegacyCSFSCov(ref cov) => cov.max_weight_components()?,
+        };
+        // Signatures are pushed in the scriptSig unless there is a witness
+        let (sig_size, sig_weight) = match *self {
+            Descriptor::Bare(..) | Descriptor::Pkh(..) => {
+                (BareCtx::max_sig_size(), 4 * BareCtx::max_sig_size())
+            }
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Ms(..) | ShInner::SortedMulti(..) => {
+                    (Legacy::max_sig_size(), 4 * Legacy::max_sig_size())
+                }
+                ShInner::Wsh(..) | ShInner::Wpkh(..) => {
+                    (Segwitv0::max_sig_size(), Segwitv0::max_sig_size())
+                }
+            },
+            Descriptor::Wpkh(..) | Descriptor::Wsh(..) | Descriptor::LegacyCSFSCov(..) => {
+                (Segwitv0::max_sig_size(), Segwitv0::max_sig_size())
+            }
+            Descriptor::Tr(..) | Descriptor::TrExt(..) => {
+                (Tap::max_sig_size(), Tap::max_sig_size())
+            }
+        };
+        let mut key_sig_weights = vec![];
+        self.for_each_key(|pk| {
+            key_sig_weights.push((pk.clone(), sig_weight));
+            true
+        });
+        Ok(WeightBreakdown {
+            script_sig_size,
+            witness_weight,
+            witness_elements,
+            sig_size,
+            key_sig_weights,
+        })
+    }
+
     /// Computes an upper bound on the weight of a satisfying witness to the
     /// transaction.
     ///
@@ -564,6 +827,31 @@ impl<Pk: MiniscriptKey, Arg: ExtParam> Descriptor<Pk, CovenantExt<Arg>> {
 }
 
 impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<Pk, Ext> {
+    /// Obtains the address for this descriptor, confidential if a `blinder`
+    /// is given and explicit otherwise.
+    ///
+    /// # Errors
+    /// For raw/bare descriptors that don't have an address.
+    pub fn address_with_blinder(
+        &self,
+        blinder: Option<secp256k1_zkp::PublicKey>,
+        params: &'static elements::AddressParams,
+    ) -> Result<elements::Address, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        match *self {
+            Descriptor::Bare(_) => Err(Error::BareDescriptorAddr),
+            Descriptor::Pkh(ref pkh) => Ok(pkh.address(blinder, params)),
+            Descriptor::Wpkh(ref wpkh) => Ok(wpkh.address(blinder, params)),
+            Descriptor::Wsh(ref wsh) => Ok(wsh.address(blinder, params)),
+            Descriptor::Sh(ref sh) => Ok(sh.address(blinder, params)),
+            Descriptor::LegacyCSFSCov(ref cov) => Ok(cov.address(blinder, params)),
+            Descriptor::Tr(ref tr) => Ok(tr.address(blinder, params)),
+            Descriptor::TrExt(ref tr) => Ok(tr.address(blinder, params)),
+        }
+    }
+
     ///
     /// Obtains the blinded address for this descriptor
     ///
@@ -579,16 +867,7 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
     where
         Pk: ToPublicKey,
     {
-        match *self {
-            Descriptor::Bare(_) => Err(Error::BareDescriptorAddr),
-            Descriptor::Pkh(ref pkh) => Ok(pkh.address(Some(blinder), params)),
-            Descriptor::Wpkh(ref wpkh) => Ok(wpkh.address(Some(blinder), params)),
-            Descriptor::Wsh(ref wsh) => Ok(wsh.address(Some(blinder), params)),
-            Descriptor::Sh(ref sh) => Ok(sh.address(Some(blinder), params)),
-            Descriptor::LegacyCSFSCov(ref cov) => Ok(cov.address(Some(blinder), params)),
-            Descriptor::Tr(ref tr) => Ok(tr.address(Some(blinder), params)),
-            Descriptor::TrExt(ref tr) => Ok(tr.address(Some(blinder), params)),
-        }
+        self.address_with_blinder(Some(blinder), params)
     }
 
     /// Obtains an address for this descriptor. For blinding see [`Descriptor::blinded_address`]
@@ -599,16 +878,7 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
     where
         Pk: ToPublicKey,
     {
-        match *self {
-            Descriptor::Bare(_) => Err(Error::BareDescriptorAddr),
-            Descriptor::Pkh(ref pkh) => Ok(pkh.address(None, params)),
-            Descriptor::Wpkh(ref wpkh) => Ok(wpkh.address(None, params)),
-            Descriptor::Wsh(ref wsh) => Ok(wsh.address(None, params)),
-            Descriptor::Sh(ref sh) => Ok(sh.address(None, params)),
-            Descriptor::LegacyCSFSCov(ref cov) => Ok(cov.address(None, params)),
-            Descriptor::Tr(ref tr) => Ok(tr.address(None, params)),
-            Descriptor::TrExt(ref tr) => Ok(tr.address(None, params)),
-        }
+        self.address_with_blinder(None, params)
     }
 
     /// Computes the scriptpubkey of the descriptor.
@@ -664,6 +934,76 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
         }
     }
 
+    /// Computes the redeemScript of a P2SH descriptor. For `ShWsh` and
+    /// `ShWpkh` this is the P2WSH or P2WPKH witness program pushed by the
+    /// scriptSig, and for `Sh` it is the script itself.
+    ///
+    /// Returns `None` for descriptors which are not wrapped in P2SH.
+    pub fn redeem_script(&self) -> Option<Script> {
+        match *self {
+            Descriptor::Sh(ref sh) => Some(sh.redeem_script()),
+            _ => None,
+        }
+    }
+
+    /// Computes the witness script of a P2WSH descriptor, whether it is
+    /// wrapped in P2SH or not. This is the script whose hash is the witness
+    /// program and which is pushed last on the witness stack.
+    ///
+    /// Returns `None` for descriptors without a witness script, including
+    /// `Wpkh` and taproot descriptors.
+    pub fn witness_script(&self) -> Option<Script> {
+        match *self {
+            Descriptor::Wsh(ref wsh) => Some(wsh.inner_script()),
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Wsh(ref wsh) => Some(wsh.inner_script()),
+                ShInner::Wpkh(..) | ShInner::SortedMulti(..) | ShInner::Ms(..) => None,
+            },
+            Descriptor::LegacyCSFSCov(ref cov) => Some(cov.inner_script()),
+            Descriptor::Bare(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::Tr(..)
+            | Descriptor::TrExt(..) => None,
+        }
+    }
+
+    /// Computes the leaf script, its [`TapLeafHash`] and the merkle path of
+    /// the taproot leaf at `leaf_index`, counting leaves in depth first order.
+    /// These are the parts needed to assemble a script path spend.
+    ///
+    /// # Errors
+    /// If the descriptor is not a taproot descriptor, or if it has no leaf at
+    /// `leaf_index`.
+    pub fn tap_leaf_script(
+        &self,
+        leaf_index: usize,
+    ) -> Result<(Script, TapLeafHash, TaprootMerkleBranch), Error> {
+        let leaf = match *self {
+            Descriptor::Tr(ref tr) => tr.tap_leaf_script(leaf_index),
+            Descriptor::TrExt(ref tr) => tr.tap_leaf_script(leaf_index),
+            _ => {
+                return Err(Error::BadDescriptor(String::from(
+                    "tap leaf scripts only exist in taproot descriptors",
+                )))
+            }
+        };
+        leaf.ok_or_else(|| Error::BadDescriptor(format!("no tap leaf at index {}", leaf_index)))
+    }
+
+    /// Returns the leaf miniscripts of a taproot descriptor along with their
+    /// merkle path, in the same order as [`Descriptor::tap_leaves`].
+    pub fn tap_leaves_with_merkle_branch(
+        &self,
+    ) -> Vec<(Cow<'_, Miniscript<Pk, Tap, Ext>>, TaprootMerkleBranch)> {
+        let merkle_branches = match *self {
+            Descriptor::Tr(ref tr) => tr.leaf_merkle_branches(),
+            Descriptor::TrExt(ref tr) => tr.leaf_merkle_branches(),
+            _ => vec![],
+        };
+        self.tap_leaves().into_iter().zip(merkle_branches).collect()
+    }
+
     /// Computes the `scriptCode` of a transaction output.
     ///
     /// The `scriptCode` is the Script of the previous transaction output being
@@ -687,6 +1027,10 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
     /// Returns satisfying non-malleable witness and scriptSig to spend an
     /// output controlled by the given descriptor if it possible to
     /// construct one using the satisfier S.
+    ///
+    /// When several spending paths are available, the one with the smallest
+    /// witness is used, see [`Miniscript::satisfy`] for the tie-breaking
+    /// rule.
     pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
     where
         S: Satisfier<Pk>,
@@ -722,6 +1066,31 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
         }
     }
 
+    /// Returns the same satisfaction as [`Descriptor::get_satisfaction`],
+    /// with every witness item and scriptSig push tagged with its role.
+    ///
+    /// Roles are assigned to each item as it is pushed while building the
+    /// chosen satisfaction, so items with the same bytes may have different
+    /// roles.
+    pub fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.get_structured_satisfaction(satisfier),
+            Descriptor::Pkh(ref pkh) => pkh.get_structured_satisfaction(satisfier),
+            Descriptor::Wpkh(ref wpkh) => wpkh.get_structured_satisfaction(satisfier),
+            Descriptor::Wsh(ref wsh) => wsh.get_structured_satisfaction(satisfier),
+            Descriptor::Sh(ref sh) => sh.get_structured_satisfaction(satisfier),
+            Descriptor::LegacyCSFSCov(ref cov) => cov.get_structured_satisfaction(satisfier),
+            Descriptor::Tr(ref tr) => tr.get_structured_satisfaction(satisfier),
+            Descriptor::TrExt(ref tr) => tr.get_structured_satisfaction(satisfier),
+        }
+    }
+
     /// Attempts to produce a non-malleable satisfying witness and scriptSig to spend an
     /// output controlled by the given descriptor; add the data to a given
     /// `TxIn` output.
@@ -729,9 +1098,7 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: Extension + ParseableExt> Descriptor<
     where
         S: Satisfier<Pk>,
     {
-        let (witness, script_sig) = self.get_satisfaction(satisfier)?;
-        txin.witness.script_witness = witness;
-        txin.script_sig = script_sig;
+        self.get_structured_satisfaction(satisfier)?.apply(txin);
         Ok(())
     }
 }
@@ -894,6 +1261,28 @@ impl<Ext: Extension + ParseableExt> Descriptor<DescriptorPublicKey, Ext> {
         self.at_derivation_index(index)?.derived_descriptor(secp)
     }
 
+    /// Like [`Descriptor::derived_descriptor`], but also accepts multipath
+    /// descriptors (`/<0;1>/*`). These are split with
+    /// [`Descriptor::into_single_descriptors`] and every path is derived at
+    /// `index`, in order. Single-path descriptors derive to a single entry.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if hardened derivation is attempted.
+    pub fn derived_descriptors<C: secp256k1_zkp::Verification>(
+        &self,
+        secp: &secp256k1_zkp::Secp256k1<C>,
+        index: u32,
+    ) -> Result<Vec<Descriptor<bitcoin::PublicKey, Ext>>, ConversionError> {
+        self.clone()
+            .into_single_descriptors()
+            // Only multipath keys of different lengths fail to split
+            .map_err(|_| ConversionError::MultiKey)?
+            .iter()
+            .map(|desc| desc.derived_descriptor(secp, index))
+            .collect()
+    }
+
     /// Parse a descriptor that may contain secret keys
     ///
     /// Internally turns every secret key found into the corresponding public key and then returns a
@@ -1282,7 +1671,7 @@ mod tests {
     use bitcoin::util::bip32;
     use bitcoin::PublicKey;
     use elements::hashes::hex::{FromHex, ToHex};
-    use elements::hashes::{hash160, sha256};
+    use elements::hashes::{hash160, sha256, Hash};
     use elements::opcodes::all::{OP_CLTV, OP_CSV};
     use elements::script::Instruction;
     use elements::{opcodes, script, Sequence};
@@ -1335,6 +1724,14 @@ mod tests {
         roundtrip_descriptor("elwsh(pk())");
         roundtrip_descriptor("elwsh(c:pk_k())");
         roundtrip_descriptor("elc:pk_h()");
+        roundtrip_descriptor("elpkh(A)");
+        roundtrip_descriptor("elwpkh(A)");
+        roundtrip_descriptor("elsh(wpkh(A))");
+        roundtrip_descriptor("elsh(multi(2,A,B))");
+        roundtrip_descriptor("elwsh(multi(2,A,B))");
+        roundtrip_descriptor("elsh(wsh(multi(2,A,B)))");
+        roundtrip_descriptor("eltr(A)");
+        roundtrip_descriptor("eltr(A,{pk(B),multi_a(1,C,D)})");
     }
     #[test]
     fn parse_descriptor() {
@@ -1688,6 +2085,84 @@ mod tests {
             )
         );
         assert_eq!(shwsh.unsigned_script_sig(), expected_ssig);
+
+        let sat = shwsh.get_structured_satisfaction(&satisfier).unwrap();
+        let roles: Vec<_> = sat.witness.iter().map(|item| item.role).collect();
+        assert_eq!(roles, vec![WitnessRole::Signature, WitnessRole::Script]);
+        assert_eq!(sat.script_sig_items[0].role, WitnessRole::Script);
+        assert_eq!(sat.script_sig, expected_ssig);
+        assert_eq!(
+            sat.weight(),
+            4 * (1 + expected_ssig.len()) + 1 + 1 + sigser.len() + 1 + ms.encode().len()
+        );
+
+        let sat = pkh.get_structured_satisfaction(&satisfier).unwrap();
+        assert!(sat.witness.is_empty());
+        let roles: Vec<_> = sat.script_sig_items.iter().map(|item| item.role).collect();
+        assert_eq!(roles, vec![WitnessRole::Signature, WitnessRole::PublicKey]);
+        sat.apply(&mut txin);
+        assert_eq!(
+            txin,
+            elements_txin(
+                script::Builder::new()
+                    .push_slice(&sigser[..])
+                    .push_key(&pk)
+                    .into_script(),
+                vec![]
+            )
+        );
+
+        // A 32-byte preimage is tagged by where it came from, not by its size
+        let preimage = [0x42; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let ms = ms_str!("and_v(v:pk({}),sha256({}))", pk, hash);
+        let wsh: Descriptor<_, NoExt> = Descriptor::new_wsh(ms.clone()).unwrap();
+        let fn_sat = crate::FnSatisfier::new()
+            .with_ecdsa_sig(|_: &bitcoin::PublicKey| Some((sig, elements::EcdsaSigHashType::All)))
+            .with_sha256(|h: &sha256::Hash| if *h == hash { Some(preimage) } else { None });
+        let sat = wsh.get_structured_satisfaction(&fn_sat).unwrap();
+        let roles: Vec<_> = sat.witness.iter().map(|item| item.role).collect();
+        assert_eq!(
+            roles,
+            vec![
+                WitnessRole::Preimage,
+                WitnessRole::Signature,
+                WitnessRole::Script
+            ]
+        );
+        assert_eq!(sat.witness[0].data, preimage.to_vec());
+        assert_eq!(sat.witness[2].data, ms.encode().into_bytes());
+
+        // Items with the same bytes are tagged by their position: here the
+        // preimage is the x-only key pushed for `pkh`
+        let preimage = pk.to_x_only_pubkey().serialize();
+        let hash = sha256::Hash::hash(&preimage);
+        let tr = Descriptor::<bitcoin::PublicKey, NoExt>::from_str(&format!(
+            "eltr({},and_v(v:pkh({}),sha256({})))",
+            pk, pk, hash
+        ))
+        .unwrap();
+        let schnorr_sig = elements::SchnorrSig {
+            sig: secp256k1_zkp::schnorr::Signature::from_slice(&[1; 64]).unwrap(),
+            hash_ty: elements::SchnorrSigHashType::Default,
+        };
+        let fn_sat = crate::FnSatisfier::new()
+            .with_tap_leaf_script_sig(|_, _| Some(schnorr_sig))
+            .with_sha256(|h: &sha256::Hash| if *h == hash { Some(preimage) } else { None });
+        let sat = tr.get_structured_satisfaction(&fn_sat).unwrap();
+        let roles: Vec<_> = sat.witness.iter().map(|item| item.role).collect();
+        assert_eq!(
+            roles,
+            vec![
+                WitnessRole::Preimage,
+                WitnessRole::Signature,
+                WitnessRole::PublicKey,
+                WitnessRole::Script,
+                WitnessRole::ControlBlock
+            ]
+        );
+        assert_eq!(sat.witness[0].data, sat.witness[2].data);
+        assert_eq!(sat.witness_stack(), tr.get_satisfaction(&fn_sat).unwrap().0);
     }
 
     #[test]
@@ -1732,6 +2207,36 @@ mod tests {
         assert_eq!(descriptor, "eltr(A,{pk(B),pk(C)})#cx98s50f");
     }
 
+    #[test]
+    fn tr_roundtrip_substr_eq() {
+        let desc = "eltr(A,{pk(B),and_v(v:pk(C),substr_eq(2,3,aabbcc))})";
+        let output = Descriptor::<String>::from_str(desc).unwrap().to_string();
+        assert_eq!(output, format!("{}#{}", desc, desc_checksum(desc).unwrap()));
+
+        let p1 = "020000000000000000000000000000000000000000000000000000000000000001";
+        let p2 = "020000000000000000000000000000000000000000000000000000000000000002";
+        for frag in &[
+            "substr_eq(2,3,aabbcc)",
+            "left_eq(4,deadbeef)",
+            "right_eq(1,01)",
+        ] {
+            let desc =
+                StdDescriptor::from_str(&format!("eltr({},and_v(v:pk({}),{}))", p1, p2, frag))
+                    .unwrap();
+            let (script, _leaf_hash, _path) = desc.tap_leaf_script(0).unwrap();
+            let ms =
+                Miniscript::<bitcoin::XOnlyPublicKey, Tap, CovenantExt<CovExtArgs>>::parse_insane(
+                    &script,
+                )
+                .unwrap();
+            assert_eq!(
+                ms.to_string(),
+                format!("and_v(v:pk({}),{})", &p2[2..], frag)
+            );
+            assert_eq!(*desc.tap_leaves()[0], ms);
+        }
+    }
+
     #[test]
     fn tr_roundtrip_tree() {
         let p1 = "020000000000000000000000000000000000000000000000000000000000000001";
@@ -1768,6 +2273,234 @@ mod tests {
         )
     }
 
+    #[test]
+    fn tr_leaf_script() {
+        use elements::taproot::{LeafVersion, TapLeafHash};
+
+        let p1 = "020000000000000000000000000000000000000000000000000000000000000001";
+        let p2 = "020000000000000000000000000000000000000000000000000000000000000002";
+        let p3 = "020000000000000000000000000000000000000000000000000000000000000003";
+        let p4 = "020000000000000000000000000000000000000000000000000000000000000004";
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "eltr({},{{pk({}),{{pk({}),pk({})}}}})",
+            p1, p2, p3, p4
+        ))
+        .unwrap();
+        let tr = match desc {
+            Descriptor::Tr(ref tr) => tr,
+            _ => unreachable!(),
+        };
+        let leaves: Vec<_> = tr.iter_scripts().collect();
+
+        for (i, (depth, ms)) in leaves.iter().enumerate() {
+            let (script, leaf_hash, path) = desc.tap_leaf_script(i).unwrap();
+            assert_eq!(script, ms.encode());
+            assert_eq!(
+                leaf_hash,
+                TapLeafHash::from_script(&script, LeafVersion::default())
+            );
+            assert_eq!(path.as_inner().len(), *depth);
+        }
+        assert!(desc.tap_leaf_script(leaves.len()).is_err());
+
+        let wsh =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("elwsh(pk({}))", p1)).unwrap();
+        assert!(wsh.tap_leaf_script(0).is_err());
+    }
+
+    #[test]
+    fn new_wsh_multi() {
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let pks: Vec<PublicKey> = (0..21)
+            .map(|i| {
+                let sk = secp256k1_zkp::SecretKey::from_slice(&[i + 1; 32]).unwrap();
+                PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(&secp, &sk))
+            })
+            .collect();
+
+        let desc = Descriptor::<PublicKey>::new_wsh_multi(2, pks[..3].to_vec()).unwrap();
+        let expected = format!("elwsh(multi(2,{},{},{}))", pks[0], pks[1], pks[2]);
+        assert_eq!(desc, Descriptor::from_str(&expected).unwrap());
+
+        assert!(Descriptor::<PublicKey>::new_wsh_multi(0, pks[..3].to_vec()).is_err());
+        assert!(Descriptor::<PublicKey>::new_wsh_multi(4, pks[..3].to_vec()).is_err());
+        assert!(Descriptor::<PublicKey>::new_wsh_multi(20, pks[..20].to_vec()).is_ok());
+        assert!(Descriptor::<PublicKey>::new_wsh_multi(2, pks).is_err());
+    }
+
+    #[test]
+    fn address_with_blinder() {
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let sk = secp256k1_zkp::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = secp256k1_zkp::PublicKey::from_secret_key(&secp, &sk);
+        let blind_sk = secp256k1_zkp::SecretKey::from_slice(&[2; 32]).unwrap();
+        let blinder = secp256k1_zkp::PublicKey::from_secret_key(&secp, &blind_sk);
+        let params = &elements::AddressParams::ELEMENTS;
+
+        for desc in &[
+            format!("elpkh({})", pk),
+            format!("elwpkh({})", pk),
+            format!("elsh(wpkh({}))", pk),
+            format!("elwsh(pk({}))", pk),
+            format!("eltr({})", pk),
+        ] {
+            let desc = Descriptor::<PublicKey>::from_str(desc).unwrap();
+            let explicit = desc.address_with_blinder(None, params).unwrap();
+            assert_eq!(explicit, desc.address(params).unwrap());
+            assert!(explicit.blinding_pubkey.is_none());
+            assert_eq!(explicit.script_pubkey(), desc.script_pubkey());
+
+            let conf = desc.address_with_blinder(Some(blinder), params).unwrap();
+            assert_eq!(conf, desc.blinded_address(blinder, params).unwrap());
+            assert_eq!(conf.blinding_pubkey, Some(blinder));
+            assert_eq!(conf.script_pubkey(), desc.script_pubkey());
+        }
+
+        let bare = Descriptor::<PublicKey>::from_str(&format!("elpk({})", pk)).unwrap();
+        assert_eq!(
+            bare.address_with_blinder(Some(blinder), params),
+            Err(Error::BareDescriptorAddr)
+        );
+    }
+
+    #[test]
+    fn sig_type() {
+        use crate::SigType;
+
+        for (desc, sig_type) in &[
+            ("elpkh(A)", SigType::Ecdsa),
+            ("elwpkh(A)", SigType::Ecdsa),
+            ("elsh(wsh(pk(A)))", SigType::Ecdsa),
+            ("elsh(pk(A))", SigType::Ecdsa),
+            ("elwsh(pk(A))", SigType::Ecdsa),
+            ("eltr(A,pk(B))", SigType::Schnorr),
+        ] {
+            let desc = Descriptor::<String>::from_str(desc).unwrap();
+            assert_eq!(desc.sig_type(), *sig_type);
+        }
+    }
+
+    #[test]
+    fn redeem_and_witness_script() {
+        type Desc = Descriptor<bitcoin::PublicKey>;
+
+        let witness_script = Desc::from_str("elwsh(after(1000))")
+            .unwrap()
+            .explicit_script()
+            .unwrap();
+        let program = witness_script.to_v0_p2wsh();
+
+        let desc = Desc::from_str("elsh(elwsh(after(1000)))").unwrap();
+        assert_eq!(desc.witness_script(), Some(witness_script.clone()));
+        assert_eq!(desc.redeem_script(), Some(program.clone()));
+        assert_eq!(
+            desc.unsigned_script_sig(),
+            script::Builder::new()
+                .push_slice(&program[..])
+                .into_script()
+        );
+
+        let desc = Desc::from_str("elwsh(after(1000))").unwrap();
+        assert_eq!(desc.witness_script(), Some(witness_script));
+        assert_eq!(desc.redeem_script(), None);
+
+        let desc = Desc::from_str("elsh(after(1000))").unwrap();
+        assert_eq!(desc.witness_script(), None);
+        assert_eq!(desc.redeem_script(), desc.explicit_script().ok());
+
+        let desc = Desc::from_str(
+            "elsh(elwpkh(020000000000000000000000000000000000000000000000000000000000000002))",
+        )
+        .unwrap();
+        assert_eq!(desc.witness_script(), None);
+        assert_eq!(desc.redeem_script(), desc.explicit_script().ok());
+    }
+
+    #[test]
+    fn bare_multi_key_limit_checked_by_sanity_check() {
+        // A bare multisig with 4 keys is valid by consensus, but not standard
+        let desc = Descriptor::<String>::from_str("elmulti(1,A,B,C,D)").unwrap();
+        assert_eq!(desc.sanity_check(), Err(Error::NonStandardBareScript));
+        assert_eq!(
+            desc.sanity_check_all(),
+            Err(vec![Error::NonStandardBareScript])
+        );
+
+        let desc = Descriptor::<String>::from_str("elmulti(1,A,B,C)").unwrap();
+        assert_eq!(desc.sanity_check(), Ok(()));
+        // Other shapes are still rejected when parsing
+        assert!(Descriptor::<String>::from_str("elor_b(pk(A),s:pk(B))").is_err());
+    }
+
+    #[test]
+    fn sanity_check_all() {
+        use crate::{AnalysisError, ScriptContextError};
+
+        let desc = Descriptor::<String>::from_str("elwsh(and_v(v:pk(A),pk(B)))").unwrap();
+        assert_eq!(desc.sanity_check_all(), Ok(()));
+
+        // A keyless branch which also mixes heights and times
+        let desc = Descriptor::<String>::from_str(
+            "elwsh(or_i(pk(A),and_v(v:after(100),after(500000001))))",
+        )
+        .unwrap();
+        let errors = desc.sanity_check_all().unwrap_err();
+        assert!(errors.contains(&Error::AnalysisError(AnalysisError::SiglessBranch)));
+        assert!(errors.contains(&Error::AnalysisError(
+            AnalysisError::HeightTimelockCombination
+        )));
+        assert_eq!(desc.sanity_check().unwrap_err(), errors[0]);
+
+        // `or_i` is malleable under Legacy rules
+        let desc = Descriptor::<String>::from_str("elsh(or_i(pk(A),pk(B)))").unwrap();
+        assert_eq!(
+            desc.sanity_check_all(),
+            Err(vec![Error::ContextError(ScriptContextError::MalleableOrI)])
+        );
+
+        // Failures of every tapscript leaf are reported
+        let desc = Descriptor::<String>::from_str("eltr(X,{after(100),older(10)})").unwrap();
+        assert_eq!(
+            desc.sanity_check_all(),
+            Err(vec![
+                Error::AnalysisError(AnalysisError::SiglessBranch),
+                Error::AnalysisError(AnalysisError::SiglessBranch),
+            ])
+        );
+    }
+
+    #[test]
+    fn tap_leaves() {
+        let desc = Descriptor::<String>::from_str("eltr(A,{pk(B),{pk(C),pk(D)}})").unwrap();
+        assert_eq!(desc.internal_key(), Some(&String::from("A")));
+        let leaves: Vec<_> = desc.tap_leaves().iter().map(|ms| ms.to_string()).collect();
+        assert_eq!(leaves, vec!["pk(B)", "pk(C)", "pk(D)"]);
+
+        let desc =
+            Descriptor::<String>::from_str("eltr(A,{pk(B),and_v(v:pk(C),num64_gt(8,7))})").unwrap();
+        let leaves: Vec<_> = desc.tap_leaves().iter().map(|ms| ms.to_string()).collect();
+        assert_eq!(leaves, vec!["pk(B)", "and_v(v:pk(C),num64_gt(8,7))"]);
+
+        let p1 = "020000000000000000000000000000000000000000000000000000000000000001";
+        let p2 = "020000000000000000000000000000000000000000000000000000000000000002";
+        let p3 = "020000000000000000000000000000000000000000000000000000000000000003";
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "eltr({},{{pk({}),{{pk({}),pk({})}}}})",
+            p1, p2, p3, p1
+        ))
+        .unwrap();
+        let depths: Vec<_> = desc
+            .tap_leaves_with_merkle_branch()
+            .iter()
+            .map(|(_ms, path)| path.as_inner().len())
+            .collect();
+        assert_eq!(depths, vec![1, 2, 2]);
+
+        let wsh = Descriptor::<String>::from_str("elwsh(pk(A))").unwrap();
+        assert_eq!(wsh.internal_key(), None);
+        assert!(wsh.tap_leaves().is_empty());
+    }
+
     #[test]
     fn roundtrip_tests() {
         let descriptor = Descriptor::<bitcoin::PublicKey>::from_str("elmulti");
@@ -2257,6 +2990,43 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         let _ = Descriptor::<String>::from_str("eltr(,thresh(1,spk_eq(,00)))");
     }
 
+    #[test]
+    fn derived_descriptors() {
+        let secp = secp256k1_zkp::Secp256k1::verification_only();
+        let xpub = "tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV";
+        let desc = format!("eltr({}/<0;1>/*,pk({}/2/<0;1>/*))", xpub, xpub);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&desc).unwrap();
+        assert_eq!(
+            desc.derived_descriptor(&secp, 7),
+            Err(ConversionError::MultiKey)
+        );
+
+        let derived = desc.derived_descriptors(&secp, 7).unwrap();
+        assert_eq!(derived.len(), 2);
+        for (i, derived) in derived.iter().enumerate() {
+            let single = format!("eltr({}/{}/*,pk({}/2/{}/*))", xpub, i, xpub, i);
+            let single = Descriptor::<DescriptorPublicKey>::from_str(&single).unwrap();
+            assert_eq!(*derived, single.derived_descriptor(&secp, 7).unwrap());
+        }
+        assert_ne!(derived[0].script_pubkey(), derived[1].script_pubkey());
+
+        // Single-path descriptors derive to themselves
+        let single = format!("elwpkh({}/*)", xpub);
+        let single = Descriptor::<DescriptorPublicKey>::from_str(&single).unwrap();
+        assert_eq!(
+            single.derived_descriptors(&secp, 3).unwrap(),
+            vec![single.derived_descriptor(&secp, 3).unwrap()]
+        );
+
+        // Hardened derivation is not possible from an xpub
+        let hardened = format!("elwpkh({}/*h)", xpub);
+        let hardened = Descriptor::<DescriptorPublicKey>::from_str(&hardened).unwrap();
+        assert_eq!(
+            hardened.derived_descriptors(&secp, 3),
+            Err(ConversionError::HardenedChild)
+        );
+    }
+
     #[test]
     fn multipath_descriptors() {
         // We can parse a multipath descriptors, and make it into separate single-path descriptors.
@@ -2291,4 +3061,52 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         Descriptor::<DescriptorPublicKey>::from_str("elwsh(andor(pk(tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/0'/<0;1>/*),older(10000),pk(tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/8/<0;1;2;3;4>/*)))").unwrap_err();
         Descriptor::<DescriptorPublicKey>::from_str("elwsh(andor(pk(tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/0'/<0;1;2;3>/*),older(10000),pk(tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/8/<0;1;2>/*)))").unwrap_err();
     }
+
+    #[test]
+    fn weight_breakdown() {
+        let k1 = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let k2 = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let pk1 = PublicKey::from_str(k1).unwrap();
+        let pk2 = PublicKey::from_str(k2).unwrap();
+        for desc in &[
+            format!("elpk({})", k1),
+            format!("elsh(multi(1,{},{}))", k1, k2),
+            format!("elpkh({})", k1),
+            format!("elwpkh({})", k1),
+            format!("elsh(wpkh({}))", k1),
+            format!("elwsh(multi(1,{},{}))", k1, k2),
+            format!("elsh(wsh(multi(1,{},{})))", k1, k2),
+            format!("elsh(sortedmulti(1,{},{}))", k1, k2),
+            format!("eltr({})", k1),
+            format!("eltr({},pk({}))", k1, k2),
+        ] {
+            let desc = StdDescriptor::from_str(desc).unwrap();
+            let breakdown = desc.satisfaction_weight_breakdown().unwrap();
+            assert_eq!(breakdown.weight(), desc.max_weight_to_satisfy().unwrap());
+        }
+
+        let desc = StdDescriptor::from_str(&format!("elwsh(multi(1,{},{}))", k1, k2)).unwrap();
+        let breakdown = desc.satisfaction_weight_breakdown().unwrap();
+        assert_eq!(breakdown.script_sig_size, 0);
+        // dummy element, signature and witness script
+        assert_eq!(breakdown.witness_elements, 3);
+        assert_eq!(breakdown.sig_size, 73);
+        assert_eq!(breakdown.key_sig_weights, vec![(pk1, 73), (pk2, 73)]);
+
+        let desc = StdDescriptor::from_str(&format!("eltr({})", k1)).unwrap();
+        let breakdown = desc.satisfaction_weight_breakdown().unwrap();
+        assert_eq!(breakdown.witness_elements, 1);
+        assert_eq!(breakdown.sig_size, 66);
+        assert_eq!(breakdown.key_sig_weights, vec![(pk1, 66)]);
+
+        let desc = StdDescriptor::from_str(&format!("elpk({})", k1)).unwrap();
+        let breakdown = desc.satisfaction_weight_breakdown().unwrap();
+        // OP_72 <sig(71)+sigHash(1)>
+        assert_eq!(breakdown.script_sig_size, 73);
+        assert_eq!(breakdown.witness_weight, 0);
+        assert_eq!(breakdown.witness_elements, 0);
+        assert_eq!(breakdown.sig_size, 73);
+        assert_eq!(breakdown.key_sig_weights, vec![(pk1, 4 * 73)]);
+        assert_eq!(breakdown.weight(), 4 * (1 + 73));
+    }
 }
@@ -13,12 +13,12 @@ use std::str::FromStr;
 use elements::{self, secp256k1_zkp, Address, Script};
 
 use super::checksum::verify_checksum;
-use super::{SortedMultiVec, ELMTS_STR};
+use super::{SortedMultiVec, StructuredSatisfaction, WitnessItem, WitnessRole, ELMTS_STR};
 use crate::descriptor::checksum;
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::{ScriptContext, ScriptContextError};
 use crate::policy::{semantic, Liftable};
-use crate::util::varint_len;
+use crate::util::{satisfaction_weight, varint_len};
 use crate::{
     elementssig_to_rawsig, Error, ForEachKey, Miniscript, MiniscriptKey, Satisfier, Segwitv0,
     ToPublicKey, TranslatePk, Translator,
@@ -42,9 +42,12 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
     }
 
     /// Create a new wsh descriptor
+    ///
+    /// Only the consensus top level rules are checked, the standardness
+    /// rules are checked by [`Wsh::sanity_check`].
     pub fn new(ms: Miniscript<Pk, Segwitv0>) -> Result<Self, Error> {
         // do the top-level checks
-        Segwitv0::top_level_checks(&ms)?;
+        Segwitv0::top_level_consensus_checks(&ms)?;
         Ok(Self {
             inner: WshInner::Ms(ms),
         })
@@ -71,7 +74,10 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
     pub fn sanity_check(&self) -> Result<(), Error> {
         match self.inner {
             WshInner::SortedMulti(ref smv) => smv.sanity_check()?,
-            WshInner::Ms(ref ms) => ms.sanity_check()?,
+            WshInner::Ms(ref ms) => {
+                Segwitv0::top_level_policy_checks(ms)?;
+                ms.sanity_check()?
+            }
         }
         Ok(())
     }
@@ -85,6 +91,13 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components()?;
+        Ok(satisfaction_weight(scriptsig_size, witness_weight))
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Wsh::max_weight_to_satisfy`] is made up of.
+    pub(super) fn max_weight_components(&self) -> Result<(usize, usize, usize), Error> {
         let (redeem_script_size, max_sat_elems, max_sat_size) = match self.inner {
             WshInner::SortedMulti(ref smv) => (
                 smv.script_size(),
@@ -101,7 +114,9 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
         // `max_sat_elems` is inclusive of the "witness script" (redeem script)
         let stack_varint_diff = varint_len(max_sat_elems) - varint_len(0);
 
-        Ok(stack_varint_diff + varint_len(redeem_script_size) + redeem_script_size + max_sat_size)
+        let witness_weight =
+            stack_varint_diff + varint_len(redeem_script_size) + redeem_script_size + max_sat_size;
+        Ok((0, witness_weight, max_sat_elems))
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
@@ -149,7 +164,7 @@ impl_block_str!(
                 });
             }
             let sub = Miniscript::from_tree(top)?;
-            Segwitv0::top_level_checks(&sub)?;
+            Segwitv0::top_level_consensus_checks(&sub)?;
             Ok(Wsh {
                 inner: WshInner::Ms(sub),
             })
@@ -200,17 +215,32 @@ impl<Pk: MiniscriptKey + ToPublicKey> Wsh<Pk> {
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
     pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Wsh::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
     where
         S: Satisfier<Pk>,
     {
         let mut witness = match self.inner {
-            WshInner::SortedMulti(ref smv) => smv.satisfy(satisfier)?,
-            WshInner::Ms(ref ms) => ms.satisfy(satisfier)?,
+            WshInner::SortedMulti(ref smv) => smv.satisfy_items(satisfier)?,
+            WshInner::Ms(ref ms) => ms.satisfy_items(satisfier, false /* allow_mall */)?,
         };
         let witness_script = self.inner_script();
-        witness.push(witness_script.into_bytes());
-        let script_sig = Script::new();
-        Ok((witness, script_sig))
+        witness.push(WitnessItem::new(
+            WitnessRole::Script,
+            witness_script.into_bytes(),
+        ));
+        Ok(StructuredSatisfaction::from_witness(witness))
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
@@ -261,7 +291,7 @@ impl_from_tree!(
                 });
             }
             let sub = Miniscript::from_tree(top)?;
-            Segwitv0::top_level_checks(&sub)?;
+            Segwitv0::top_level_consensus_checks(&sub)?;
             Ok(Wsh {
                 inner: WshInner::Ms(sub),
             })
@@ -384,11 +414,18 @@ impl<Pk: MiniscriptKey> Wpkh<Pk> {
     /// Assumes all ec-signatures are 73 bytes, including push opcode and
     /// sighash suffix.
     pub fn max_weight_to_satisfy(&self) -> usize {
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components();
+        satisfaction_weight(scriptsig_size, witness_weight)
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Wpkh::max_weight_to_satisfy`] is made up of.
+    pub(super) fn max_weight_components(&self) -> (usize, usize, usize) {
         // stack items: <varint(sig+sigHash)> <sig(71)+sigHash(1)> <varint(pubkey)> <pubkey>
-        let stack_items_size = 73 + Segwitv0::pk_len(&self.pk);
+        let stack_items_size = Segwitv0::max_sig_size() + Segwitv0::pk_len(&self.pk);
         // stackLen varint difference between non-satisfied (0) and satisfied
         let stack_varint_diff = varint_len(2) - varint_len(0);
-        stack_varint_diff + stack_items_size
+        (0, stack_varint_diff + stack_items_size, 2)
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
@@ -462,14 +499,27 @@ impl<Pk: MiniscriptKey + ToPublicKey> Wpkh<Pk> {
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
     pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Wpkh::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
     where
         S: Satisfier<Pk>,
     {
         if let Some(sig) = satisfier.lookup_ecdsa_sig(&self.pk) {
-            let sig_vec = elementssig_to_rawsig(&sig);
-            let script_sig = Script::new();
-            let witness = vec![sig_vec, self.pk.to_public_key().to_bytes()];
-            Ok((witness, script_sig))
+            Ok(StructuredSatisfaction::from_witness(vec![
+                WitnessItem::new(WitnessRole::Signature, elementssig_to_rawsig(&sig)),
+                WitnessItem::new(WitnessRole::PublicKey, self.pk.to_public_key().to_bytes()),
+            ]))
         } else {
             Err(Error::MissingSig(self.pk.to_public_key()))
         }
@@ -13,12 +13,14 @@ use core::fmt;
 use elements::{self, script, secp256k1_zkp, Script};
 
 use super::checksum::verify_checksum;
-use super::{SortedMultiVec, Wpkh, Wsh, ELMTS_STR};
+use super::{
+    SortedMultiVec, StructuredSatisfaction, WitnessItem, WitnessRole, Wpkh, Wsh, ELMTS_STR,
+};
 use crate::descriptor::checksum;
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
 use crate::policy::{semantic, Liftable};
-use crate::util::{varint_len, witness_to_scriptsig};
+use crate::util::{satisfaction_weight, varint_len, witness_to_scriptsig};
 use crate::{
     push_opcode_size, Error, ForEachKey, Legacy, Miniscript, MiniscriptKey, Satisfier, Segwitv0,
     ToPublicKey, TranslatePk, Translator,
@@ -100,7 +102,7 @@ impl_from_tree!(
                 "sortedmulti" => ShInner::SortedMulti(SortedMultiVec::from_tree(top)?),
                 _ => {
                     let sub = Miniscript::from_tree(top)?;
-                    Legacy::top_level_checks(&sub)?;
+                    Legacy::top_level_consensus_checks(&sub)?;
                     ShInner::Ms(sub)
                 }
             };
@@ -137,9 +139,12 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
     }
 
     /// Create a new p2sh descriptor with the raw miniscript
+    ///
+    /// Only the consensus top level rules are checked, the standardness
+    /// rules are checked by [`Sh::sanity_check`].
     pub fn new(ms: Miniscript<Pk, Legacy>) -> Result<Self, Error> {
         // do the top-level checks
-        Legacy::top_level_checks(&ms)?;
+        Legacy::top_level_consensus_checks(&ms)?;
         Ok(Self {
             inner: ShInner::Ms(ms),
         })
@@ -175,7 +180,10 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
             ShInner::Wsh(ref wsh) => wsh.sanity_check()?,
             ShInner::Wpkh(ref wpkh) => wpkh.sanity_check()?,
             ShInner::SortedMulti(ref smv) => smv.sanity_check()?,
-            ShInner::Ms(ref ms) => ms.sanity_check()?,
+            ShInner::Ms(ref ms) => {
+                Legacy::top_level_policy_checks(ms)?;
+                ms.sanity_check()?
+            }
         }
         Ok(())
     }
@@ -218,39 +226,41 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
-        let (scriptsig_size, witness_size) = match self.inner {
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components()?;
+        Ok(satisfaction_weight(scriptsig_size, witness_weight))
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Sh::max_weight_to_satisfy`] is made up of.
+    pub(super) fn max_weight_components(&self) -> Result<(usize, usize, usize), Error> {
+        Ok(match self.inner {
             // add weighted script sig, len byte stays the same
             ShInner::Wsh(ref wsh) => {
                 // scriptSig: OP_34 <OP_0 OP_32 <32-byte-hash>>
                 let scriptsig_size = 1 + 1 + 1 + 32;
-                let witness_size = wsh.max_weight_to_satisfy()?;
-                (scriptsig_size, witness_size)
+                let (_, witness_weight, witness_elements) = wsh.max_weight_components()?;
+                (scriptsig_size, witness_weight, witness_elements)
             }
             ShInner::SortedMulti(ref smv) => {
                 let ss = smv.script_size();
                 let ps = push_opcode_size(ss);
                 let scriptsig_size = ps + ss + smv.max_satisfaction_size();
-                (scriptsig_size, 0)
+                (scriptsig_size, 0, 0)
             }
             // add weighted script sig, len byte stays the same
             ShInner::Wpkh(ref wpkh) => {
                 // scriptSig: OP_22 <OP_0 OP_20 <20-byte-hash>>
                 let scriptsig_size = 1 + 1 + 1 + 20;
-                let witness_size = wpkh.max_weight_to_satisfy();
-                (scriptsig_size, witness_size)
+                let (_, witness_weight, witness_elements) = wpkh.max_weight_components();
+                (scriptsig_size, witness_weight, witness_elements)
             }
             ShInner::Ms(ref ms) => {
                 let ss = ms.script_size();
                 let ps = push_opcode_size(ss);
                 let scriptsig_size = ps + ss + ms.max_satisfaction_size()?;
-                (scriptsig_size, 0)
+                (scriptsig_size, 0, 0)
             }
-        };
-
-        // scriptSigLen varint difference between non-satisfied (0) and satisfied
-        let scriptsig_varint_diff = varint_len(scriptsig_size) - varint_len(0);
-
-        Ok(4 * (scriptsig_varint_diff + scriptsig_size) + witness_size)
+        })
     }
 
     /// Computes an upper bound on the weight of a satisfying witness to the
@@ -315,6 +325,18 @@ impl<Pk: MiniscriptKey + ToPublicKey> Sh<Pk> {
         }
     }
 
+    /// Obtains the redeemScript for this descriptor, the script hashed into
+    /// the scriptPubkey. For wrapped segwit descriptors this is the witness
+    /// program, not the witness script.
+    pub fn redeem_script(&self) -> Script {
+        match self.inner {
+            ShInner::Wsh(ref wsh) => wsh.script_pubkey(),
+            ShInner::Wpkh(ref wpkh) => wpkh.script_pubkey(),
+            ShInner::SortedMulti(ref smv) => smv.encode(),
+            ShInner::Ms(ref ms) => ms.encode(),
+        }
+    }
+
     /// Obtain the underlying miniscript for this descriptor
     pub fn inner_script(&self) -> Script {
         match self.inner {
@@ -371,29 +393,58 @@ impl<Pk: MiniscriptKey + ToPublicKey> Sh<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        let script_sig = self.unsigned_script_sig();
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Sh::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
+    where
+        S: Satisfier<Pk>,
+    {
         match self.inner {
             ShInner::Wsh(ref wsh) => {
-                let (witness, _) = wsh.get_satisfaction(satisfier)?;
-                Ok((witness, script_sig))
+                let redeem_script = wsh.inner_script().to_v0_p2wsh();
+                Ok(StructuredSatisfaction {
+                    witness: wsh.get_structured_satisfaction(satisfier)?.witness,
+                    script_sig_items: vec![WitnessItem::new(
+                        WitnessRole::Script,
+                        redeem_script.into_bytes(),
+                    )],
+                    script_sig: self.unsigned_script_sig(),
+                })
             }
             ShInner::Wpkh(ref wpkh) => {
-                let (witness, _) = wpkh.get_satisfaction(satisfier)?;
-                Ok((witness, script_sig))
+                let redeem_script = wpkh.script_pubkey();
+                Ok(StructuredSatisfaction {
+                    witness: wpkh.get_structured_satisfaction(satisfier)?.witness,
+                    script_sig_items: vec![WitnessItem::new(
+                        WitnessRole::Script,
+                        redeem_script.into_bytes(),
+                    )],
+                    script_sig: self.unsigned_script_sig(),
+                })
             }
             ShInner::SortedMulti(ref smv) => {
-                let mut script_witness = smv.satisfy(satisfier)?;
-                script_witness.push(smv.encode().into_bytes());
-                let script_sig = witness_to_scriptsig(&script_witness);
-                let witness = vec![];
-                Ok((witness, script_sig))
+                let mut script_witness = smv.satisfy_items(satisfier)?;
+                script_witness.push(WitnessItem::new(
+                    WitnessRole::Script,
+                    smv.encode().into_bytes(),
+                ));
+                Ok(StructuredSatisfaction::from_script_sig(script_witness))
             }
             ShInner::Ms(ref ms) => {
-                let mut script_witness = ms.satisfy(satisfier)?;
-                script_witness.push(ms.encode().into_bytes());
-                let script_sig = witness_to_scriptsig(&script_witness);
-                let witness = vec![];
-                Ok((witness, script_sig))
+                let mut script_witness =
+                    ms.satisfy_items(satisfier, false /* allow_mall */)?;
+                script_witness.push(WitnessItem::new(
+                    WitnessRole::Script,
+                    ms.encode().into_bytes(),
+                ));
+                Ok(StructuredSatisfaction::from_script_sig(script_witness))
             }
         }
     }
@@ -5,20 +5,21 @@ use std::sync::{Arc, Mutex};
 use std::{fmt, hash};
 
 use elements::taproot::{
-    LeafVersion, TaprootBuilder, TaprootSpendInfo, TAPROOT_CONTROL_BASE_SIZE,
-    TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
+    LeafVersion, TapLeafHash, TaprootBuilder, TaprootMerkleBranch, TaprootSpendInfo,
+    TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
 };
 use elements::{self, opcodes, secp256k1_zkp, Script};
 
 use super::checksum::verify_checksum;
-use super::ELMTS_STR;
+use super::{StructuredSatisfaction, WitnessItem, WitnessRole, ELMTS_STR};
 use crate::descriptor::checksum;
 use crate::expression::{self, FromTree};
 use crate::extensions::ParseableExt;
+use crate::miniscript::context::ScriptContext;
 use crate::miniscript::Miniscript;
 use crate::policy::semantic::Policy;
 use crate::policy::Liftable;
-use crate::util::{varint_len, witness_size};
+use crate::util::{satisfaction_weight, varint_len};
 use crate::{
     errstr, Error, Extension, ForEachKey, MiniscriptKey, NoExt, Satisfier, Tap, ToPublicKey,
     TranslateExt, TranslatePk, Translator,
@@ -268,24 +269,52 @@ impl<Pk: MiniscriptKey, Ext: Extension> Tr<Pk, Ext> {
         Ok(())
     }
 
+    /// Checks whether every leaf of the descriptor is safe, reporting all
+    /// the failures of all the leaves
+    pub fn sanity_check_all(&self) -> Result<(), Vec<Error>> {
+        let errors: Vec<_> = self
+            .iter_scripts()
+            .filter_map(|(_depth, ms)| ms.sanity_check_all().err())
+            .flatten()
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Computes an upper bound on the difference between a non-satisfied
     /// `TxIn`'s `segwit_weight` and a satisfied `TxIn`'s `segwit_weight`
     ///
     /// Assumes all Schnorr signatures are 66 bytes, including push opcode and
     /// sighash suffix.
     ///
+    /// Script paths are spent with the elements tapscript leaf version
+    /// ([`LeafVersion::default`], `0xc4`). The leaf version is carried by the
+    /// first byte of the control block along with the parity of the output
+    /// key, so the weight is the same for any leaf version.
+    ///
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
+        let (scriptsig_size, witness_weight, _) = self.max_weight_components()?;
+        Ok(satisfaction_weight(scriptsig_size, witness_weight))
+    }
+
+    /// Computes the scriptSig size, witness weight and number of witness
+    /// elements that [`Tr::max_weight_to_satisfy`] is made up of, for the
+    /// spend path it was computed for.
+    pub(super) fn max_weight_components(&self) -> Result<(usize, usize, usize), Error> {
         let tree = match self.taptree() {
             None => {
                 // key spend path
                 // item: varint(sig+sigHash) + <sig(64)+sigHash(1)>
-                let item_sig_size = 1 + 65;
+                let item_sig_size = Tap::max_sig_size();
                 // 1 stack item
                 let stack_varint_diff = varint_len(1) - varint_len(0);
 
-                return Ok(stack_varint_diff + item_sig_size);
+                return Ok((0, stack_varint_diff + item_sig_size, 1));
             }
             // script path spend..
             Some(tree) => tree,
@@ -301,7 +330,8 @@ impl<Pk: MiniscriptKey, Ext: Extension> Tr<Pk, Ext> {
                 // stack varint difference (+1 for ctrl block, witness script already included)
                 let stack_varint_diff = varint_len(max_sat_elems + 1) - varint_len(0);
 
-                Some(
+                Some((
+                    0,
                     stack_varint_diff +
                     // size of elements to satisfy script
                     max_sat_size +
@@ -311,7 +341,8 @@ impl<Pk: MiniscriptKey, Ext: Extension> Tr<Pk, Ext> {
                     // last element: control block
                     varint_len(control_block_size) +
                     control_block_size,
-                )
+                    max_sat_elems + 1,
+                ))
             })
             .max()
             .ok_or(Error::ImpossibleSatisfaction)
@@ -383,6 +414,41 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: ParseableExt> Tr<Pk, Ext> {
         elements::Address::p2tr_tweaked(spend_info.output_key(), blinder, params)
     }
 
+    /// Obtains the script, leaf hash and merkle path of the leaf at
+    /// `leaf_index` in the depth first order of [`Tr::iter_scripts`].
+    /// Returns [`None`] if there is no such leaf.
+    ///
+    /// If the same script appears in several leaves, the shortest merkle
+    /// path among them is returned.
+    pub fn tap_leaf_script(
+        &self,
+        leaf_index: usize,
+    ) -> Option<(Script, TapLeafHash, TaprootMerkleBranch)> {
+        let (_depth, ms) = self.iter_scripts().nth(leaf_index)?;
+        let leaf_script = (ms.encode(), LeafVersion::default());
+        let control_block = self
+            .spend_info()
+            .control_block(&leaf_script)
+            .expect("Control block must exist in script map for every known leaf");
+        let leaf_hash = TapLeafHash::from_script(&leaf_script.0, leaf_script.1);
+        Some((leaf_script.0, leaf_hash, control_block.merkle_branch))
+    }
+
+    /// Returns the merkle path of every leaf, in the depth first order of
+    /// [`Tr::iter_scripts`].
+    pub(crate) fn leaf_merkle_branches(&self) -> Vec<TaprootMerkleBranch> {
+        let spend_info = self.spend_info();
+        self.iter_scripts()
+            .map(|(_depth, ms)| {
+                let leaf_script = (ms.encode(), LeafVersion::default());
+                spend_info
+                    .control_block(&leaf_script)
+                    .expect("Control block must exist in script map for every known leaf")
+                    .merkle_branch
+            })
+            .collect()
+    }
+
     /// Returns satisfying non-malleable witness and scriptSig with minimum
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
@@ -390,7 +456,21 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: ParseableExt> Tr<Pk, Ext> {
     where
         S: Satisfier<Pk>,
     {
-        best_tap_spend(self, satisfier, false /* allow_mall */)
+        let sat = self.get_structured_satisfaction(satisfier)?;
+        Ok((sat.witness_stack(), sat.script_sig))
+    }
+
+    /// Returns the satisfaction of [`Tr::get_satisfaction`] with every
+    /// item tagged with its role.
+    pub(crate) fn get_structured_satisfaction<S>(
+        &self,
+        satisfier: S,
+    ) -> Result<StructuredSatisfaction, Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let witness = best_tap_spend(self, satisfier, false /* allow_mall */)?;
+        Ok(StructuredSatisfaction::from_witness(witness))
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
@@ -400,7 +480,9 @@ impl<Pk: MiniscriptKey + ToPublicKey, Ext: ParseableExt> Tr<Pk, Ext> {
     where
         S: Satisfier<Pk>,
     {
-        best_tap_spend(self, satisfier, true /* allow_mall */)
+        let witness = best_tap_spend(self, satisfier, true /* allow_mall */)?;
+        let sat = StructuredSatisfaction::from_witness(witness);
+        Ok((sat.witness_stack(), sat.script_sig))
     }
 }
 
@@ -706,7 +788,8 @@ where
     }
 }
 
-// Helper function to compute the len of control block at a given depth
+// Helper function to compute the len of control block at a given depth.
+// The base size counts the leaf version/parity byte and the internal key.
 fn control_block_len(depth: usize) -> usize {
     TAPROOT_CONTROL_BASE_SIZE + depth * TAPROOT_CONTROL_NODE_SIZE
 }
@@ -717,7 +800,7 @@ fn best_tap_spend<Pk, S, Ext>(
     desc: &Tr<Pk, Ext>,
     satisfier: S,
     allow_mall: bool,
-) -> Result<(Vec<Vec<u8>>, Script), Error>
+) -> Result<Vec<WitnessItem>, Error>
 where
     Pk: ToPublicKey,
     S: Satisfier<Pk>,
@@ -726,27 +809,21 @@ where
     let spend_info = desc.spend_info();
     // First try the key spend path
     if let Some(sig) = satisfier.lookup_tap_key_spend_sig() {
-        Ok((vec![sig.to_vec()], Script::new()))
+        Ok(vec![WitnessItem::new(WitnessRole::Signature, sig.to_vec())])
     } else {
         // Since we have the complete descriptor we can ignore the satisfier. We don't use the control block
         // map (lookup_control_block) from the satisfier here.
         let (mut min_wit, mut min_wit_len) = (None, None);
         for (depth, ms) in desc.iter_scripts() {
-            let mut wit = if allow_mall {
-                match ms.satisfy_malleable(&satisfier) {
-                    Ok(wit) => wit,
-                    Err(..) => continue, // No witness for this script in tr descriptor, look for next one
-                }
-            } else {
-                match ms.satisfy(&satisfier) {
-                    Ok(wit) => wit,
-                    Err(..) => continue, // No witness for this script in tr descriptor, look for next one
-                }
+            let mut wit = match ms.satisfy_items(&satisfier, allow_mall) {
+                Ok(wit) => wit,
+                Err(..) => continue, // No witness for this script in tr descriptor, look for next one
             };
             // Compute the final witness size
             // Control block len + script len + witnesssize + varint(wit.len + 2)
             // The extra +2 elements are control block and script itself
-            let wit_size = witness_size(&wit)
+            let wit_size = wit.iter().map(|item| item.data.len()).sum::<usize>()
+                + varint_len(wit.len())
                 + control_block_len(depth)
                 + ms.script_size()
                 + varint_len(ms.script_size());
@@ -757,17 +834,24 @@ where
                 let control_block = spend_info
                     .control_block(&leaf_script)
                     .expect("Control block must exist in script map for every known leaf");
-                wit.push(leaf_script.0.into_bytes()); // Push the leaf script
-                                                      // There can be multiple control blocks for a (script, ver) pair
-                                                      // Find the smallest one amongst those
-                wit.push(control_block.serialize());
+                // Push the leaf script
+                wit.push(WitnessItem::new(
+                    WitnessRole::Script,
+                    leaf_script.0.into_bytes(),
+                ));
+                // There can be multiple control blocks for a (script, ver) pair
+                // Find the smallest one amongst those
+                wit.push(WitnessItem::new(
+                    WitnessRole::ControlBlock,
+                    control_block.serialize(),
+                ));
                 // Finally, save the minimum
                 min_wit = Some(wit);
                 min_wit_len = Some(wit_size);
             }
         }
         match min_wit {
-            Some(wit) => Ok((wit, Script::new())),
+            Some(wit) => Ok(wit),
             None => Err(Error::CouldNotSatisfy), // Could not satisfy all miniscripts inside Tr
         }
     }
@@ -797,4 +881,29 @@ mod tests {
         // Note the last ac12 only has ac and fails the predicate
         assert!(!tr.for_each_key(|k| k.starts_with("acc")));
     }
+
+    #[test]
+    fn control_block_len_matches_spend_info() {
+        let p1 = "020000000000000000000000000000000000000000000000000000000000000001";
+        let p2 = "020000000000000000000000000000000000000000000000000000000000000002";
+        let p3 = "020000000000000000000000000000000000000000000000000000000000000003";
+        let desc = format!("eltr({},{{pk({}),{{pk({}),pk({})}}}})", p1, p2, p3, p1);
+        let tr = Tr::<bitcoin::PublicKey, NoExt>::from_str(&desc).unwrap();
+
+        let depths: Vec<_> = tr.iter_scripts().map(|(depth, _ms)| depth).collect();
+        let spend_info = tr.spend_info();
+        let control_blocks: Vec<_> = tr
+            .iter_scripts()
+            .map(|(_depth, ms)| {
+                let leaf_script = (ms.encode(), LeafVersion::default());
+                spend_info.control_block(&leaf_script).unwrap().serialize()
+            })
+            .collect();
+        assert_eq!(depths, vec![1, 2, 2]);
+        for (control_block, depth) in control_blocks.iter().zip(depths) {
+            let parsed = elements::taproot::ControlBlock::from_slice(control_block).unwrap();
+            assert_eq!(parsed.leaf_version, LeafVersion::default());
+            assert_eq!(control_block.len(), control_block_len(depth));
+        }
+    }
 }
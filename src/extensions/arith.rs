@@ -17,7 +17,7 @@ use crate::extensions::check_sig_price_oracle_1;
 use crate::miniscript::context::ScriptContextError;
 use crate::miniscript::lex::{Token as Tk, TokenIter};
 use crate::miniscript::limits::MAX_STANDARD_P2WSH_STACK_ITEM_SIZE;
-use crate::miniscript::satisfy::{Satisfaction, Witness};
+use crate::miniscript::satisfy::{Satisfaction, Witness, WitnessItem, WitnessRole};
 use crate::miniscript::types::extra_props::{OpLimits, TimelockInfo};
 use crate::miniscript::types::{Base, Correctness, Dissat, ExtData, Input, Malleability};
 use crate::{
@@ -600,9 +600,9 @@ impl Expr<CovExtArgs> {
                 match s.lookup_price_oracle_sig(&pk, *time) {
                     Some((sig, price, time)) => {
                         let wit = Witness::Stack(vec![
-                            sig.as_ref().to_vec(),
-                            time.to_le_bytes().to_vec(),
-                            price.to_le_bytes().to_vec(),
+                            WitnessItem::new(WitnessRole::Signature, sig.as_ref().to_vec()),
+                            WitnessItem::new(WitnessRole::Other, time.to_le_bytes().to_vec()),
+                            WitnessItem::new(WitnessRole::Other, price.to_le_bytes().to_vec()),
                         ]);
                         let sat = Satisfaction {
                             stack: wit,
@@ -1408,7 +1408,7 @@ impl ParseableExt for Arith<CovExtArgs> {
 
     fn from_token_iter(tokens: &mut TokenIter<'_>) -> Result<Self, ()> {
         let len = tokens.len();
-        match Self::from_tokens(&tokens.as_inner_mut()) {
+        match Self::from_tokens(tokens.remaining()) {
             Some((res, last_pos)) => {
                 tokens.advance(len - last_pos).ok_or(())?;
                 Ok(res)
@@ -14,7 +14,7 @@ use super::{ArgFromStr, CovExtArgs, ExtParam, ParseableExt, TxEnv};
 use crate::miniscript::context::ScriptContextError;
 use crate::miniscript::lex::{Token as Tk, TokenIter};
 use crate::miniscript::limits::MAX_STANDARD_P2WSH_STACK_ITEM_SIZE;
-use crate::miniscript::satisfy::{Satisfaction, Witness};
+use crate::miniscript::satisfy::{Satisfaction, Witness, WitnessRole};
 use crate::miniscript::types::extra_props::{OpLimits, TimelockInfo};
 use crate::miniscript::types::{Base, Correctness, Dissat, ExtData, Input, Malleability};
 use crate::{
@@ -70,8 +70,8 @@ impl<T: ExtParam> Extension for CheckSigFromStack<T> {
 
     fn extra_prop(&self) -> ExtData {
         ExtData {
-            pk_cost: 32 + 1 + 1 + 32 + 1, // 1 opcodes, 1 key push, msg, 1 msg push
-            has_free_verify: true,        // free verify form. Checksigfromstack verify
+            pk_cost: self.script_size(),
+            has_free_verify: true, // free verify form. Checksigfromstack verify
             stack_elem_count_sat: Some(1),
             stack_elem_count_dissat: Some(1), // supply empty signature for dissatisfaction
             max_sat_size: Some((64, 64)),
@@ -89,7 +89,7 @@ impl<T: ExtParam> Extension for CheckSigFromStack<T> {
     }
 
     fn script_size(&self) -> usize {
-        1 + 32 + 1 // opcode + key+ push
+        1 + 32 + 1 + 32 + 1 // msg push + msg + key push + key + opcode
     }
 
     fn segwit_ctx_checks(&self) -> Result<(), miniscript::context::ScriptContextError> {
@@ -254,7 +254,7 @@ impl ParseableExt for CheckSigFromStack<CovExtArgs> {
         S: Satisfier<Pk>,
     {
         let wit = match sat.lookup_csfs_sig(self.as_pk(), self.as_msg()) {
-            Some(sig) => Witness::Stack(vec![sig.as_ref().to_vec()]),
+            Some(sig) => Witness::with_role(WitnessRole::Signature, vec![sig.as_ref().to_vec()]),
             None => Witness::Impossible,
         };
         Satisfaction {
@@ -269,7 +269,7 @@ impl ParseableExt for CheckSigFromStack<CovExtArgs> {
         S: Satisfier<Pk>,
     {
         Satisfaction {
-            stack: Witness::Stack(vec![vec![]]), // empty sig
+            stack: Witness::push_0(), // empty sig
             has_sig: false,
         }
     }
@@ -380,6 +380,9 @@ mod tests {
         );
         // script rtt
         assert_eq!(ms, MsExtCsfs::parse_insane(&ms.encode()).unwrap());
+        // script size
+        assert_eq!(ms.script_size(), ms.encode().len());
+        assert_eq!(ms.ext.pk_cost, ms.encode().len());
 
         // Test translate
         // Translation tests to be added in upcoming commits
@@ -1,4 +1,7 @@
-//! Miniscript Arithmetic expressions:
+//! Miniscript Introspection expressions:
+//! Fragments built on the Elements introspection opcodes such as
+//! `OP_INSPECTOUTPUTVALUE` and `OP_INSPECTOUTPUTSCRIPTPUBKEY`, for example
+//! `value_eq(out_value(1),ConfVal)` or `spk_eq(out_spk(1),V1Spk)`.
 //! Note that this fragment is only supported for Tapscript context
 use std::convert::TryFrom;
 use std::fmt;
@@ -1157,7 +1160,7 @@ impl ParseableExt for CovOps<CovExtArgs> {
 
     fn from_token_iter(tokens: &mut TokenIter<'_>) -> Result<Self, ()> {
         let len = tokens.len();
-        match Self::from_tokens(&tokens.as_inner_mut()) {
+        match Self::from_tokens(tokens.remaining()) {
             Some((res, last_pos)) => {
                 tokens.advance(len - last_pos).ok_or(())?;
                 Ok(res)
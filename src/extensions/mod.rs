@@ -8,6 +8,7 @@ use bitcoin::hashes::Hash;
 use elements::script::Builder;
 use elements::{secp256k1_zkp, Transaction, TxOut};
 
+use crate::descriptor::CovError;
 use crate::expression::Tree;
 use crate::interpreter::{self, Stack};
 use crate::miniscript::context::ScriptContextError;
@@ -24,6 +25,7 @@ mod index_ops;
 mod introspect_ops;
 mod outputs_pref;
 pub mod param;
+mod substr;
 mod tx_ver;
 
 pub use arith::{Arith, EvalError, Expr, ExprInner};
@@ -33,6 +35,7 @@ pub use introspect_ops::{AssetExpr, CovOps, Spk, SpkExpr, ValueExpr};
 
 pub use self::outputs_pref::LegacyOutputsPref;
 pub use self::param::{ArgFromStr, CovExtArgs, ExtParam, NoExtParam};
+pub use self::substr::{SliceOp, SubstrEq};
 pub use self::tx_ver::LegacyVerEq;
 
 /// Extensions to elements-miniscript.
@@ -50,7 +53,8 @@ pub trait Extension: Clone + Eq + Ord + fmt::Debug + fmt::Display + hash::Hash {
     /// See current implementation for different fragments in extra_props.rs
     fn extra_prop(&self) -> ExtData;
 
-    /// Get the script size of the current fragment
+    /// Get the script size of the current fragment. This is also the
+    /// fragment's `pk_cost`, whatever [`Extension::extra_prop`] reports
     fn script_size(&self) -> usize;
 
     /// Validity rules for fragment in segwit context
@@ -61,6 +65,30 @@ pub trait Extension: Clone + Eq + Ord + fmt::Debug + fmt::Display + hash::Hash {
         Ok(())
     }
 
+    /// Validity rules for fragment in legacy context. Extensions are not
+    /// allowed in legacy context by default
+    fn legacy_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        Err(ScriptContextError::ExtensionError(String::from(
+            "No Extensions in Legacy context",
+        )))
+    }
+
+    /// Validity rules for fragment in bare context. Extensions are not
+    /// allowed in bare context by default
+    fn bare_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        Err(ScriptContextError::ExtensionError(String::from(
+            "No Extensions in Bare context",
+        )))
+    }
+
+    /// Lift the fragment into an abstract policy, see [`Liftable`].
+    /// Covenant fragments constrain the spending transaction rather than the
+    /// witness and have no semantic policy counterpart, so by default lifting
+    /// a miniscript with an extension fails with [`CovError::CovenantLift`].
+    fn lift<Pk: MiniscriptKey>(&self) -> Result<policy::Semantic<Pk>, Error> {
+        Err(Error::CovError(CovError::CovenantLift))
+    }
+
     /// Create an instance of this object from a Tree with root name and children as
     /// `Vec<Tree>`.
     // Ideally, we would want a FromTree implementation here, but that is not possible
@@ -148,6 +176,10 @@ impl Extension for NoExt {
     fn segwit_ctx_checks(&self) -> Result<(), ScriptContextError> {
         Ok(())
     }
+
+    fn lift<Pk: MiniscriptKey>(&self) -> Result<policy::Semantic<Pk>, Error> {
+        match *self {}
+    }
 }
 
 impl ParseableExt for NoExt {
@@ -225,6 +257,8 @@ pub enum CovenantExt<T: ExtParam> {
     Arith(Arith<T>),
     /// Cov opcodes
     Introspect(CovOps<T>),
+    /// Slice of a witness element equal to a constant
+    SubstrEq(SubstrEq),
 }
 
 // Apply the function on each arm
@@ -236,6 +270,7 @@ macro_rules! all_arms_fn {
             CovenantExt::Csfs(csfs) => csfs.$f($($args, )*),
             CovenantExt::Arith(e) => e.$f($($args, )*),
             CovenantExt::Introspect(e) => e.$f($($args, )*),
+            CovenantExt::SubstrEq(s) => <SubstrEq as $trt>::$f(s, $($args, )*),
         }
     };
 }
@@ -254,7 +289,9 @@ macro_rules! try_from_arms {
             Ok(CovenantExt::Arith(v))
         } else if let Ok(v) = <CovOps<$ext_arg> as $trt>::$f($($args, )*) {
             Ok(CovenantExt::Introspect(v))
-        }else {
+        } else if let Ok(v) = <SubstrEq as $trt>::$f($($args, )*) {
+            Ok(CovenantExt::SubstrEq(v))
+        } else {
             Err(())
         }
     };
@@ -284,6 +321,22 @@ impl<T: ExtParam> Extension for CovenantExt<T> {
     fn segwit_ctx_checks(&self) -> Result<(), ScriptContextError> {
         all_arms_fn!(self, Extension, segwit_ctx_checks,)
     }
+
+    fn tap_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        all_arms_fn!(self, Extension, tap_ctx_checks,)
+    }
+
+    fn legacy_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        all_arms_fn!(self, Extension, legacy_ctx_checks,)
+    }
+
+    fn bare_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        all_arms_fn!(self, Extension, bare_ctx_checks,)
+    }
+
+    fn lift<Pk: MiniscriptKey>(&self) -> Result<policy::Semantic<Pk>, Error> {
+        all_arms_fn!(self, Extension, lift,)
+    }
 }
 
 impl ParseableExt for CovenantExt<CovExtArgs> {
@@ -328,6 +381,7 @@ impl<T: ExtParam> fmt::Display for CovenantExt<T> {
             CovenantExt::Csfs(c) => c.fmt(f),
             CovenantExt::Arith(e) => e.fmt(f),
             CovenantExt::Introspect(e) => e.fmt(f),
+            CovenantExt::SubstrEq(s) => s.fmt(f),
         }
     }
 }
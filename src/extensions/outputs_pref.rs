@@ -14,7 +14,7 @@ use crate::miniscript::astelem::StackCtxOperations;
 use crate::miniscript::context::ScriptContextError;
 use crate::miniscript::lex::{Token as Tk, TokenIter};
 use crate::miniscript::limits::{MAX_SCRIPT_ELEMENT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEM_SIZE};
-use crate::miniscript::satisfy::{Satisfaction, Witness};
+use crate::miniscript::satisfy::{Satisfaction, Witness, WitnessRole};
 use crate::miniscript::types::extra_props::{OpLimits, TimelockInfo};
 use crate::miniscript::types::{Base, Correctness, Dissat, ExtData, Input, Malleability};
 use crate::policy::{self, Liftable};
@@ -25,10 +25,11 @@ use crate::{expression, interpreter, Error, Extension, MiniscriptKey, Satisfier,
 /// is equal to hashOutputs
 /// Since, there is a policy restriction that initial pushes must be
 /// only 80 bytes, we need user to provide suffix in separate items
-/// There can be atmost 7 cats, because the script element must be less
-/// than 520 bytes total in order to compute an hash256 on it.
-/// Even if the witness does not require 7 pushes, the user should push
-/// 7 elements with possibly empty values.
+/// The `num_cats` leading CATs join `num_cats + 1` suffix items. The joined
+/// element must be less than 520 bytes total in order to compute an hash256
+/// on it, so by default 6 CATs (7 items of 80 bytes) are used.
+/// Even if the witness does not require all pushes, the user should push
+/// `num_cats + 1` elements with possibly empty values.
 ///
 /// `CAT CAT CAT CAT CAT CAT <pref> SWAP CAT` /*Now we hashoutputs on stack */
 /// `HASH256`
@@ -37,11 +38,44 @@ use crate::{expression, interpreter, Error, Extension, MiniscriptKey, Satisfier,
 pub struct LegacyOutputsPref {
     /// the version of transaction
     pub pref: Vec<u8>,
+    /// the number of CATs joining the suffix items
+    pub num_cats: usize,
+}
+
+impl LegacyOutputsPref {
+    /// The number of CATs needed to join 80 byte suffix items up to
+    /// the 520 byte element limit
+    pub const DEFAULT_NUM_CATS: usize =
+        MAX_SCRIPT_ELEMENT_SIZE / MAX_STANDARD_P2WSH_STACK_ITEM_SIZE;
+
+    /// Create a new [`LegacyOutputsPref`] with the default number of CATs
+    pub fn new(pref: Vec<u8>) -> Self {
+        Self {
+            pref,
+            num_cats: Self::DEFAULT_NUM_CATS,
+        }
+    }
+
+    // The number of suffix witness elements
+    fn num_wit_elems(&self) -> usize {
+        self.num_cats + 1
+    }
+
+    // The largest number of CATs for a prefix of `pref_len` bytes, such that
+    // every CAT can add at least one byte without the joined element
+    // exceeding the limit
+    fn max_num_cats(pref_len: usize) -> Option<usize> {
+        MAX_SCRIPT_ELEMENT_SIZE.checked_sub(pref_len)
+    }
 }
 
 impl fmt::Display for LegacyOutputsPref {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "outputs_pref({})", self.pref.to_hex())
+        if self.num_cats == Self::DEFAULT_NUM_CATS {
+            write!(f, "outputs_pref({})", self.pref.to_hex())
+        } else {
+            write!(f, "outputs_pref({},{})", self.pref.to_hex(), self.num_cats)
+        }
     }
 }
 
@@ -53,18 +87,17 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for LegacyOutputsPref {
 
 impl Extension for LegacyOutputsPref {
     fn segwit_ctx_checks(&self) -> Result<(), ScriptContextError> {
-        if self.pref.len() > MAX_SCRIPT_ELEMENT_SIZE {
-            Err(ScriptContextError::CovElementSizeExceeded)
-        } else {
-            Ok(())
+        match Self::max_num_cats(self.pref.len()) {
+            Some(max) if self.num_cats <= max => Ok(()),
+            _ => Err(ScriptContextError::CovElementSizeExceeded),
         }
     }
 
     fn corr_prop(&self) -> Correctness {
         Correctness {
             base: Base::B,
-            input: Input::Any,    // 7 outputs
-            dissatisfiable: true, // Any 7 elements that don't cat
+            input: Input::Any,    // num_cats + 1 outputs
+            dissatisfiable: true, // Any num_cats + 1 elements that don't cat
             unit: true,
         }
     }
@@ -81,17 +114,17 @@ impl Extension for LegacyOutputsPref {
         // Assume txouts fill out all the 520 bytes
         let max_wit_sz = MAX_SCRIPT_ELEMENT_SIZE - self.pref.len();
         ExtData {
-            pk_cost: 8 + self.pref.len() + 1 + 6, // See script_size() in astelem.rs
+            pk_cost: self.script_size(),
             has_free_verify: true,
-            stack_elem_count_sat: Some(7),
-            stack_elem_count_dissat: Some(7),
+            stack_elem_count_sat: Some(self.num_wit_elems()),
+            stack_elem_count_dissat: Some(self.num_wit_elems()),
             max_sat_size: Some((max_wit_sz, max_wit_sz)),
             max_dissat_size: Some((0, 0)), // all empty should dissatisfy
             timelock_info: TimelockInfo::default(),
             exec_stack_elem_count_sat: Some(3), // sha2 context, byte slice, target hash
             exec_stack_elem_count_dissat: Some(3),
             ops: OpLimits {
-                count: 13,
+                count: self.num_cats + 7,
                 sat: Some(0),
                 nsat: Some(0),
             },
@@ -101,14 +134,18 @@ impl Extension for LegacyOutputsPref {
     fn script_size(&self) -> usize {
         // CAT CAT CAT CAT CAT CAT <pref> SWAP CAT /*Now we hashoutputs on stack */
         // HASH256 DEPTH <10> SUB PICK EQUAL
-        8 + self.pref.len() + 1 /* line1 opcodes + pref.push */
+        self.num_cats + 2 + self.pref.len() + 1 /* line1 opcodes + pref.push */
                 + 6 /* line 2 */
     }
 
     fn from_name_tree(name: &str, children: &[expression::Tree<'_>]) -> Result<Self, ()> {
-        if children.len() == 1 && name == "outputs_pref" {
+        if (children.len() == 1 || children.len() == 2) && name == "outputs_pref" {
             let pref = expression::terminal(&children[0], Vec::<u8>::from_hex).map_err(|_| ())?;
-            Ok(Self { pref })
+            let num_cats = match children.get(1) {
+                Some(n) => expression::terminal(n, expression::parse_num).map_err(|_| ())?,
+                None => Self::DEFAULT_NUM_CATS,
+            };
+            Ok(Self { pref, num_cats })
         } else {
             // Correct error handling while parsing fromtree
             Err(())
@@ -125,8 +162,7 @@ impl ParseableExt for LegacyOutputsPref {
         let wit = match sat.lookup_outputs() {
             Some(outs) => {
                 let mut ser_out = Vec::new();
-                let num_wit_elems =
-                    MAX_SCRIPT_ELEMENT_SIZE / MAX_STANDARD_P2WSH_STACK_ITEM_SIZE + 1;
+                let num_wit_elems = self.num_wit_elems();
                 let mut witness = Vec::with_capacity(num_wit_elems);
                 for out in outs {
                     ser_out.extend(serialize(out));
@@ -148,7 +184,12 @@ impl ParseableExt for LegacyOutputsPref {
                     while witness.len() < num_wit_elems {
                         witness.push(vec![]);
                     }
-                    Witness::Stack(witness)
+                    if witness.len() > num_wit_elems {
+                        // Not enough cats to join the suffix
+                        Witness::Impossible
+                    } else {
+                        Witness::with_role(WitnessRole::Other, witness)
+                    }
                 } else {
                     Witness::Impossible
                 }
@@ -174,13 +215,13 @@ impl ParseableExt for LegacyOutputsPref {
                 for out in outs {
                     ser_out.extend(serialize(out));
                 }
-                let num_wit_elems = MAX_SCRIPT_ELEMENT_SIZE / MAX_STANDARD_P2WSH_STACK_ITEM_SIZE;
+                let num_wit_elems = self.num_cats;
                 let mut witness = Vec::with_capacity(num_wit_elems);
                 if self.pref != ser_out.as_slice() {
                     while witness.len() < num_wit_elems {
                         witness.push(vec![]);
                     }
-                    Witness::Stack(witness)
+                    Witness::with_role(WitnessRole::Other, witness)
                 } else if self.pref.len() != MAX_SCRIPT_ELEMENT_SIZE {
                     // Case when prefix == ser_out and it is possible
                     // to add more witness
@@ -188,7 +229,7 @@ impl ParseableExt for LegacyOutputsPref {
                     while witness.len() < num_wit_elems {
                         witness.push(vec![]);
                     }
-                    Witness::Stack(witness)
+                    Witness::with_role(WitnessRole::Other, witness)
                 } else {
                     // case when pref == ser_out and len of both is 520
                     Witness::Impossible
@@ -205,28 +246,23 @@ impl ParseableExt for LegacyOutputsPref {
     }
 
     fn push_to_builder(&self, builder: elements::script::Builder) -> elements::script::Builder {
-        builder.check_item_pref(4, &self.pref)
+        builder.check_item_pref(4, &self.pref, self.num_cats)
     }
 
     fn from_token_iter(tokens: &mut TokenIter<'_>) -> Result<Self, ()> {
         let outputs_pref = {
-            let sl = tokens.peek_slice(15).ok_or(())?;
-            if let Tk::Push(pref) = &sl[6] {
-                if sl[0] == Tk::Cat
-                    && sl[1] == Tk::Cat
+            // <pref> SWAP CAT HASH256 DEPTH <4> SUB PICK EQUAL
+            let sl = tokens.peek_slice(9).ok_or(())?;
+            if let Tk::Push(pref) = &sl[0] {
+                if sl[1] == Tk::Swap
                     && sl[2] == Tk::Cat
-                    && sl[3] == Tk::Cat
-                    && sl[4] == Tk::Cat
-                    && sl[5] == Tk::Cat
-                    && sl[7] == Tk::Swap
-                    && sl[8] == Tk::Cat
-                    && sl[9] == Tk::Hash256
-                    && sl[11] == Tk::Num(4)
-                    && sl[12] == Tk::Sub
-                    && sl[13] == Tk::Pick
-                    && sl[14] == Tk::Equal
+                    && sl[3] == Tk::Hash256
+                    && sl[5] == Tk::Num(4)
+                    && sl[6] == Tk::Sub
+                    && sl[7] == Tk::Pick
+                    && sl[8] == Tk::Equal
                 {
-                    Self { pref: pref.clone() }
+                    pref.clone()
                 } else {
                     return Err(());
                 }
@@ -234,8 +270,31 @@ impl ParseableExt for LegacyOutputsPref {
                 return Err(());
             }
         };
-        tokens.advance(15).expect("Size checked previously");
-        Ok(outputs_pref)
+        // Count the CATs joining the suffix. Any CAT beyond the most the
+        // prefix leaves room for is left to the preceding script
+        let max_num_cats = Self::max_num_cats(outputs_pref.len()).ok_or(())?;
+        let num_cats = {
+            let mut num_cats = 0;
+            while num_cats < max_num_cats {
+                let sl = match tokens.peek_slice(9 + num_cats + 1) {
+                    Some(sl) => sl,
+                    None => break,
+                };
+                if sl[0] == Tk::Cat {
+                    num_cats += 1;
+                } else {
+                    break;
+                }
+            }
+            num_cats
+        };
+        tokens
+            .advance(9 + num_cats)
+            .expect("Size checked previously");
+        Ok(Self {
+            pref: outputs_pref,
+            num_cats,
+        })
     }
 
     fn evaluate<'intp, 'txin>(
@@ -247,7 +306,7 @@ impl ParseableExt for LegacyOutputsPref {
         let hash_outputs = stack[3];
         let hash_outputs = hash_outputs.try_push()?;
         // Maximum number of suffix elements
-        let max_elems = MAX_SCRIPT_ELEMENT_SIZE / MAX_STANDARD_P2WSH_STACK_ITEM_SIZE + 1;
+        let max_elems = self.num_wit_elems();
         if hash_outputs.len() == 32 {
             // We want to cat the last 6 elements(5 cats) in suffix
             if stack.len() < max_elems {
@@ -285,7 +344,7 @@ mod tests {
     use bitcoin::PublicKey;
 
     use super::*;
-    use crate::{Miniscript, Segwitv0};
+    use crate::{Miniscript, Segwitv0, Terminal};
 
     #[test]
     fn test_outputs_pref() {
@@ -297,4 +356,38 @@ mod tests {
         // script rtt
         assert_eq!(ms, MsExtVer::parse_insane(&ms.encode()).unwrap())
     }
+
+    #[test]
+    fn outputs_pref_num_cats() {
+        type MsExtVer = Miniscript<PublicKey, Segwitv0, LegacyOutputsPref>;
+
+        let ms = MsExtVer::from_str_insane("outputs_pref(aa,9)").unwrap();
+        assert_eq!(ms.to_string(), "outputs_pref(aa,9)");
+        assert_eq!(ms.encode().len(), ms.script_size());
+        let decoded = MsExtVer::parse_insane(&ms.encode()).unwrap();
+        assert_eq!(decoded, ms);
+        match decoded.node {
+            Terminal::Ext(ref e) => assert_eq!(e.num_cats, 9),
+            ref x => panic!("expected outputs_pref, got {:?}", x),
+        }
+
+        // The default is displayed without the number of cats
+        let ms = MsExtVer::from_str_insane("outputs_pref(aa,6)").unwrap();
+        assert_eq!(ms.to_string(), "outputs_pref(aa)");
+
+        // Each cat must be able to add at least one byte
+        let pref = vec![0xaa; MAX_SCRIPT_ELEMENT_SIZE - 1].to_hex();
+        assert!(MsExtVer::from_str_insane(&format!("outputs_pref({},1)", pref)).is_ok());
+        assert!(MsExtVer::from_str_insane(&format!("outputs_pref({},2)", pref)).is_err());
+
+        // CATs beyond the most the prefix allows belong to the preceding script
+        let ms = MsExtVer::from_str_insane(&format!("outputs_pref({},1)", pref)).unwrap();
+        let mut bytes = vec![elements::opcodes::all::OP_CAT.into_u8(); 2];
+        bytes.extend(ms.encode().as_bytes());
+        let script = elements::Script::from(bytes);
+        let mut tokens = TokenIter::new(crate::miniscript::lex::lex(&script).unwrap());
+        let ext = LegacyOutputsPref::from_token_iter(&mut tokens).unwrap();
+        assert_eq!(ext.num_cats, 1);
+        assert_eq!(tokens.remaining(), &[Tk::Cat, Tk::Cat]);
+    }
 }
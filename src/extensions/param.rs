@@ -207,6 +207,7 @@ where
             CovenantExt::Introspect(ref c) => Ok(CovenantExt::Introspect(
                 TranslateExtParam::translate_ext(c, self)?,
             )),
+            CovenantExt::SubstrEq(ref s) => Ok(CovenantExt::SubstrEq(s.clone())),
         }
     }
 }
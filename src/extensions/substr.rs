@@ -0,0 +1,396 @@
+//! Miniscript extension: substr_eq, left_eq and right_eq
+//! Checks a slice of a witness element, taken with the Elements
+//! `OP_SUBSTR`, `OP_LEFT` or `OP_RIGHT` opcodes, against a constant
+
+use std::fmt;
+
+use elements::hashes::hex::{FromHex, ToHex};
+use elements::script;
+
+use super::{ParseableExt, TxEnv};
+use crate::miniscript::context::ScriptContextError;
+use crate::miniscript::lex::{Token as Tk, TokenIter};
+use crate::miniscript::limits::MAX_SCRIPT_ELEMENT_SIZE;
+use crate::miniscript::satisfy::{Satisfaction, Witness, WitnessRole};
+use crate::miniscript::types::extra_props::{OpLimits, TimelockInfo};
+use crate::miniscript::types::{Base, Correctness, Dissat, ExtData, Input, Malleability};
+use crate::util::{build_scriptint, varint_len};
+use crate::{
+    expression, interpreter, push_opcode_size, script_num_size, Extension, Satisfier, ToPublicKey,
+};
+
+/// The slice of the element taken by a [`SubstrEq`]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub enum SliceOp {
+    /// `<begin> <len> SUBSTR`: the `len` bytes starting at `begin`, or fewer
+    /// if the element ends before
+    Substr {
+        /// Index of the first byte of the slice
+        begin: u32,
+        /// Maximum length of the slice
+        len: u32,
+    },
+    /// `<n> LEFT`: the bytes before index `n`
+    Left(u32),
+    /// `<n> RIGHT`: the bytes from index `n` on
+    Right(u32),
+}
+
+impl SliceOp {
+    /// Apply the opcode to `elem`. Indices past the end of the element are
+    /// clamped to its length, as done by the Elements interpreter
+    pub fn apply<'a>(&self, elem: &'a [u8]) -> &'a [u8] {
+        let clamp = |n: u32| (n as usize).min(elem.len());
+        match *self {
+            SliceOp::Substr { begin, len } => {
+                let begin = clamp(begin);
+                &elem[begin..(begin + len as usize).min(elem.len())]
+            }
+            SliceOp::Left(n) => &elem[..clamp(n)],
+            SliceOp::Right(n) => &elem[clamp(n)..],
+        }
+    }
+
+    // The number of bytes skipped before the slice
+    fn offset(&self) -> usize {
+        match *self {
+            SliceOp::Substr { begin, .. } => begin as usize,
+            SliceOp::Left(_) => 0,
+            SliceOp::Right(n) => n as usize,
+        }
+    }
+
+    // The maximum length of the slice, if bounded
+    fn max_len(&self) -> Option<usize> {
+        match *self {
+            SliceOp::Substr { len, .. } => Some(len as usize),
+            SliceOp::Left(n) => Some(n as usize),
+            SliceOp::Right(_) => None,
+        }
+    }
+}
+
+/// Check that a slice of the top stack element equals a constant
+/// `<begin> <len> SUBSTR <expected> EQUAL`, `<n> LEFT <expected> EQUAL` or
+/// `<n> RIGHT <expected> EQUAL`
+///
+/// The fragment is satisfied by the expected bytes preceded by as many zero
+/// bytes as the slice skips, and dissatisfied by the same element with its
+/// last byte changed.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+pub struct SubstrEq {
+    /// The slice of the element to compare
+    pub op: SliceOp,
+    /// The bytes the slice must be equal to
+    pub expected: Vec<u8>,
+}
+
+impl SubstrEq {
+    // The witness element satisfying the fragment
+    fn sat_elem(&self) -> Vec<u8> {
+        let mut elem = vec![0; self.op.offset()];
+        elem.extend(&self.expected);
+        elem
+    }
+
+    // The witness element dissatisfying the fragment. Only differs from
+    // the satisfaction in the last byte, which is part of the slice
+    fn dissat_elem(&self) -> Vec<u8> {
+        let mut elem = self.sat_elem();
+        if let Some(last) = elem.last_mut() {
+            *last ^= 1;
+        }
+        elem
+    }
+
+    // The expected bytes are pushed with the opcode lexing back to them
+    fn expected_num(&self) -> Option<i64> {
+        match self.expected.len() {
+            0 => Some(0),
+            1 if (1..=16).contains(&self.expected[0]) => Some(self.expected[0] as i64),
+            1 if self.expected[0] == 0x81 => Some(-1),
+            _ => None,
+        }
+    }
+
+    fn ctx_checks(&self) -> Result<(), ScriptContextError> {
+        // An empty slice cannot be told apart from a short element, and a
+        // slice can never be longer than its maximum length
+        if self.expected.is_empty() || self.op.max_len().map_or(false, |n| self.expected.len() > n)
+        {
+            return Err(ScriptContextError::ExtensionError(format!(
+                "{} can never be dissatisfied or satisfied",
+                self
+            )));
+        }
+        if self.sat_elem().len() > MAX_SCRIPT_ELEMENT_SIZE {
+            Err(ScriptContextError::CovElementSizeExceeded)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for SubstrEq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            SliceOp::Substr { begin, len } => {
+                write!(f, "substr_eq({},{},{})", begin, len, self.expected.to_hex())
+            }
+            SliceOp::Left(n) => write!(f, "left_eq({},{})", n, self.expected.to_hex()),
+            SliceOp::Right(n) => write!(f, "right_eq({},{})", n, self.expected.to_hex()),
+        }
+    }
+}
+
+impl Extension for SubstrEq {
+    fn segwit_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        self.ctx_checks()
+    }
+
+    fn tap_ctx_checks(&self) -> Result<(), ScriptContextError> {
+        self.ctx_checks()
+    }
+
+    fn corr_prop(&self) -> Correctness {
+        Correctness {
+            base: Base::B,
+            input: Input::One,
+            dissatisfiable: true,
+            unit: true,
+        }
+    }
+
+    fn mall_prop(&self) -> Malleability {
+        Malleability {
+            dissat: Dissat::Unknown, // Any element with a different slice
+            safe: false,
+            non_malleable: true,
+        }
+    }
+
+    fn extra_prop(&self) -> ExtData {
+        // Satisfaction and dissatisfaction elements have the same size
+        let len = self.sat_elem().len();
+        let size = (len + varint_len(len), len + push_opcode_size(len));
+        // The element and the indices of the slice
+        let exec_elems = match self.op {
+            SliceOp::Substr { .. } => 3,
+            SliceOp::Left(_) | SliceOp::Right(_) => 2,
+        };
+        ExtData {
+            pk_cost: self.script_size(),
+            has_free_verify: true,
+            stack_elem_count_sat: Some(1),
+            stack_elem_count_dissat: Some(1),
+            max_sat_size: Some(size),
+            max_dissat_size: Some(size),
+            timelock_info: TimelockInfo::default(),
+            exec_stack_elem_count_sat: Some(exec_elems),
+            exec_stack_elem_count_dissat: Some(exec_elems),
+            ops: OpLimits {
+                count: 2,
+                sat: Some(0),
+                nsat: Some(0),
+            },
+        }
+    }
+
+    fn script_size(&self) -> usize {
+        let indices = match self.op {
+            SliceOp::Substr { begin, len } => {
+                script_num_size(begin as usize) + script_num_size(len as usize)
+            }
+            SliceOp::Left(n) | SliceOp::Right(n) => script_num_size(n as usize),
+        };
+        let expected = match self.expected_num() {
+            Some(_) => 1,
+            None => push_opcode_size(self.expected.len()) + self.expected.len(),
+        };
+        // indices + slice opcode + expected push + EQUAL
+        indices + 1 + expected + 1
+    }
+
+    fn from_name_tree(name: &str, children: &[expression::Tree<'_>]) -> Result<Self, ()> {
+        let num = |i: usize| expression::terminal(&children[i], expression::parse_num);
+        let (op, expected) = match (name, children.len()) {
+            ("substr_eq", 3) => (
+                SliceOp::Substr {
+                    begin: num(0).map_err(|_| ())?,
+                    len: num(1).map_err(|_| ())?,
+                },
+                &children[2],
+            ),
+            ("left_eq", 2) => (SliceOp::Left(num(0).map_err(|_| ())?), &children[1]),
+            ("right_eq", 2) => (SliceOp::Right(num(0).map_err(|_| ())?), &children[1]),
+            // Correct error handling while parsing fromtree
+            _ => return Err(()),
+        };
+        let expected = expression::terminal(expected, Vec::<u8>::from_hex).map_err(|_| ())?;
+        Ok(Self { op, expected })
+    }
+}
+
+impl ParseableExt for SubstrEq {
+    fn satisfy<Pk, S>(&self, _sat: &S) -> Satisfaction
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        Satisfaction {
+            stack: Witness::with_role(WitnessRole::Other, vec![self.sat_elem()]),
+            has_sig: false,
+        }
+    }
+
+    fn dissatisfy<Pk, S>(&self, _sat: &S) -> Satisfaction
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        Satisfaction {
+            stack: Witness::with_role(WitnessRole::Other, vec![self.dissat_elem()]),
+            has_sig: false,
+        }
+    }
+
+    fn push_to_builder(&self, builder: script::Builder) -> script::Builder {
+        let builder = match self.op {
+            SliceOp::Substr { begin, len } => builder
+                .push_int(begin as i64)
+                .push_int(len as i64)
+                .push_opcode(elements::opcodes::all::OP_SUBSTR),
+            SliceOp::Left(n) => builder
+                .push_int(n as i64)
+                .push_opcode(elements::opcodes::all::OP_LEFT),
+            SliceOp::Right(n) => builder
+                .push_int(n as i64)
+                .push_opcode(elements::opcodes::all::OP_RIGHT),
+        };
+        let builder = match self.expected_num() {
+            Some(n) => builder.push_int(n),
+            None => builder.push_slice(&self.expected),
+        };
+        builder.push_opcode(elements::opcodes::all::OP_EQUAL)
+    }
+
+    fn from_token_iter(tokens: &mut TokenIter<'_>) -> Result<Self, ()> {
+        // Tokens are read from the end: EQUAL, the expected push and the
+        // slicing opcode, preceded by its indices
+        let sl = tokens.peek_slice(4).ok_or(())?;
+        if sl[3] != Tk::Equal {
+            return Err(());
+        }
+        let expected = match sl[2] {
+            Tk::Num(n) => build_scriptint(n as i64),
+            Tk::NumNeg1 => vec![0x81],
+            Tk::Bytes8(b) | Tk::Hash20(b) | Tk::Bytes32(b) | Tk::Bytes33(b) | Tk::Bytes65(b) => {
+                b.to_vec()
+            }
+            Tk::Push(ref b) => b.clone(),
+            _ => return Err(()),
+        };
+        let (op, n_tokens) = match (&sl[0], &sl[1]) {
+            (&Tk::Num(n), &Tk::Left) => (SliceOp::Left(n), 4),
+            (&Tk::Num(n), &Tk::Right) => (SliceOp::Right(n), 4),
+            (&Tk::Num(len), &Tk::Substr) => match tokens.peek_slice(5).ok_or(())?[0] {
+                Tk::Num(begin) => (SliceOp::Substr { begin, len }, 5),
+                _ => return Err(()),
+            },
+            _ => return Err(()),
+        };
+        tokens.advance(n_tokens).expect("Size checked previously");
+        Ok(Self { op, expected })
+    }
+
+    fn evaluate<'intp, 'txin>(
+        &'intp self,
+        stack: &mut interpreter::Stack<'txin>,
+        _txenv: Option<&TxEnv>,
+    ) -> Result<bool, interpreter::Error> {
+        let elem = stack.pop().ok_or(interpreter::Error::UnexpectedStackEnd)?;
+        if self.op.apply(elem.try_push()?) == self.expected.as_slice() {
+            stack.push(interpreter::Element::Satisfied);
+            Ok(true)
+        } else {
+            stack.push(interpreter::Element::Dissatisfied);
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::PublicKey;
+
+    use super::*;
+    use crate::{Miniscript, Segwitv0, Tap, Terminal};
+
+    #[test]
+    fn substr_eq_roundtrip() {
+        type MsSegwit = Miniscript<PublicKey, Segwitv0, SubstrEq>;
+        type MsTap = Miniscript<bitcoin::XOnlyPublicKey, Tap, SubstrEq>;
+
+        let long_pushes = [
+            format!("left_eq(32,{})", "ab".repeat(32)),
+            format!("right_eq(0,{})", "ab".repeat(100)),
+        ];
+        let short_pushes = [
+            "substr_eq(2,3,aabbcc)",
+            "substr_eq(0,20,aabb)",
+            "left_eq(4,deadbeef)",
+            "right_eq(1,01)",
+            "right_eq(200,81)",
+            "left_eq(32,0501)",
+        ];
+        for s in short_pushes
+            .iter()
+            .cloned()
+            .chain(long_pushes.iter().map(String::as_str))
+        {
+            let ms = MsSegwit::from_str_insane(s).unwrap();
+            // string rtt
+            assert_eq!(ms.to_string(), s);
+            // script rtt
+            let script = ms.encode();
+            assert_eq!(script.len(), ms.script_size());
+            assert_eq!(ms, MsSegwit::parse_insane(&script).unwrap());
+
+            let ms = MsTap::from_str_insane(s).unwrap();
+            assert_eq!(ms, MsTap::parse_insane(&ms.encode()).unwrap());
+        }
+
+        let ms = MsSegwit::from_str_insane("substr_eq(2,3,aabbcc)").unwrap();
+        match ms.node {
+            Terminal::Ext(ref e) => {
+                assert_eq!(e.op, SliceOp::Substr { begin: 2, len: 3 });
+                assert_eq!(e.sat_elem(), vec![0, 0, 0xaa, 0xbb, 0xcc]);
+                assert_eq!(e.op.apply(&e.sat_elem()), e.expected.as_slice());
+                assert_ne!(e.op.apply(&e.dissat_elem()), e.expected.as_slice());
+            }
+            ref x => panic!("expected substr_eq, got {:?}", x),
+        }
+
+        // The slice can never be empty or longer than its maximum length
+        assert!(MsSegwit::from_str_insane("left_eq(1,aabb)").is_err());
+        assert!(MsSegwit::from_str_insane("substr_eq(0,0,)").is_err());
+        assert!(MsSegwit::from_str_insane("right_eq(3,)").is_err());
+        // The satisfaction must fit in a stack element
+        assert!(MsSegwit::from_str_insane("right_eq(520,aa)").is_err());
+    }
+
+    #[test]
+    fn slice_op_apply() {
+        let elem = [1, 2, 3, 4];
+        assert_eq!(SliceOp::Substr { begin: 1, len: 2 }.apply(&elem), &[2, 3]);
+        assert_eq!(SliceOp::Substr { begin: 3, len: 2 }.apply(&elem), &[4]);
+        assert_eq!(
+            SliceOp::Substr { begin: 5, len: 2 }.apply(&elem),
+            &[] as &[u8]
+        );
+        assert_eq!(SliceOp::Left(2).apply(&elem), &[1, 2]);
+        assert_eq!(SliceOp::Left(9).apply(&elem), &elem);
+        assert_eq!(SliceOp::Right(3).apply(&elem), &[4]);
+        assert_eq!(SliceOp::Right(9).apply(&elem), &[] as &[u8]);
+    }
+}
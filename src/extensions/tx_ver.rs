@@ -15,21 +15,43 @@ use crate::miniscript::types::extra_props::{OpLimits, TimelockInfo};
 use crate::miniscript::types::{Base, Correctness, Dissat, ExtData, Input, Malleability};
 use crate::policy::{self, Liftable};
 use crate::{
-    expression, interpreter, miniscript, util, Error, Extension, MiniscriptKey, Satisfier,
-    ToPublicKey,
+    expression, interpreter, miniscript, script_num_size, util, Error, Extension, MiniscriptKey,
+    Satisfier, ToPublicKey,
 };
 
 /// Version struct
-/// `DEPTH <12> SUB PICK <num> EQUAL`
+/// `DEPTH <depth> SUB PICK <num> EQUAL`
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
 pub struct LegacyVerEq {
     /// the version of transaction
     pub n: u32, // it's i32 in bitcoin core
+    /// The position of the version in the witness, counted from the bottom
+    /// of the stack starting at 1. This depends on the stack layout of the
+    /// surrounding script and is [`LegacyVerEq::DEFAULT_DEPTH`] for the
+    /// legacy covenant descriptor.
+    pub depth: u32,
+}
+
+impl LegacyVerEq {
+    /// The depth of the version in the legacy covenant descriptor witness
+    pub const DEFAULT_DEPTH: u32 = 12;
+
+    /// Creates a version check at [`LegacyVerEq::DEFAULT_DEPTH`]
+    pub fn new(n: u32) -> Self {
+        Self {
+            n,
+            depth: Self::DEFAULT_DEPTH,
+        }
+    }
 }
 
 impl fmt::Display for LegacyVerEq {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ver_eq({})", self.n)
+        if self.depth == Self::DEFAULT_DEPTH {
+            write!(f, "ver_eq({})", self.n)
+        } else {
+            write!(f, "ver_eq({},{})", self.n, self.depth)
+        }
     }
 }
 
@@ -63,7 +85,7 @@ impl Extension for LegacyVerEq {
 
     fn extra_prop(&self) -> ExtData {
         ExtData {
-            pk_cost: 4 + 1 + 1 + 4, // 4 opcodes, 1 push, (5) 4 byte push
+            pk_cost: self.script_size(),
             has_free_verify: true,
             stack_elem_count_sat: Some(0),
             stack_elem_count_dissat: Some(0),
@@ -81,13 +103,24 @@ impl Extension for LegacyVerEq {
     }
 
     fn script_size(&self) -> usize {
-        4 + 1 + 1 + 4 // opcodes + push opcodes + target size
+        // opcodes + depth + push opcode + target size
+        4 + script_num_size(self.depth as usize) + 1 + 4
     }
 
     fn from_name_tree(name: &str, children: &[expression::Tree<'_>]) -> Result<Self, ()> {
-        if children.len() == 1 && name == "ver_eq" {
+        if (children.len() == 1 || children.len() == 2) && name == "ver_eq" {
             let n = expression::terminal(&children[0], expression::parse_num).map_err(|_| ())?;
-            Ok(Self { n })
+            let depth = match children.get(1) {
+                Some(child) => {
+                    expression::terminal(child, expression::parse_num).map_err(|_| ())?
+                }
+                None => Self::DEFAULT_DEPTH,
+            };
+            // The version must be below the items pushed by the script itself
+            if depth == 0 {
+                return Err(());
+            }
+            Ok(Self { n, depth })
         } else {
             // Correct error handling while parsing fromtree
             Err(())
@@ -140,19 +173,18 @@ impl ParseableExt for LegacyVerEq {
     }
 
     fn push_to_builder(&self, builder: elements::script::Builder) -> elements::script::Builder {
-        builder.check_item_eq(12, &serialize(&self.n))
+        builder.check_item_eq(self.depth, &serialize(&self.n))
     }
 
     fn from_token_iter(tokens: &mut TokenIter<'_>) -> Result<Self, ()> {
         let ver = {
             let sl = tokens.peek_slice(5).ok_or(())?;
-            if let Tk::PickPush4(ver) = sl[3] {
-                if sl[0] == Tk::Depth
-                    && sl[1] == Tk::Num(12)
-                    && sl[2] == Tk::Sub
-                    && sl[4] == Tk::Equal
-                {
-                    Self { n: ver }
+            if let (Tk::Num(depth), Tk::PickPush4(ver)) = (&sl[1], &sl[3]) {
+                if sl[0] == Tk::Depth && *depth != 0 && sl[2] == Tk::Sub && sl[4] == Tk::Equal {
+                    Self {
+                        n: *ver,
+                        depth: *depth,
+                    }
                 } else {
                     return Err(());
                 }
@@ -169,8 +201,13 @@ impl ParseableExt for LegacyVerEq {
         stack: &mut interpreter::Stack<'txin>,
         _txenv: Option<&TxEnv>,
     ) -> Result<bool, interpreter::Error> {
-        // Version is at index 11
-        let ver = stack[11];
+        // Version is at index depth - 1 from the bottom. `depth` is public,
+        // so a zero depth is only rejected when parsing
+        let idx = match (self.depth as usize).checked_sub(1) {
+            Some(idx) if idx < stack.len() => idx,
+            _ => return Err(interpreter::Error::UnexpectedStackEnd),
+        };
+        let ver = stack[idx];
         let elem = ver.try_push()?;
         if elem.len() == 4 {
             let wit_ver = util::slice_to_u32_le(elem);
@@ -195,6 +232,7 @@ mod tests {
     use bitcoin::PublicKey;
 
     use super::*;
+    use crate::miniscript::decode::Terminal;
     use crate::{Miniscript, Segwitv0};
 
     #[test]
@@ -207,4 +245,53 @@ mod tests {
         // script rtt
         assert_eq!(ms, MsExtVer::parse_insane(&ms.encode()).unwrap())
     }
+
+    #[test]
+    fn test_ver_eq_depth() {
+        type MsExtVer = Miniscript<PublicKey, Segwitv0, LegacyVerEq>;
+
+        // The default depth is elided
+        let ms = MsExtVer::from_str_insane("ver_eq(8,12)").unwrap();
+        assert_eq!(ms.to_string(), "ver_eq(8)");
+        assert_eq!(
+            ms.node,
+            MsExtVer::from_str_insane("ver_eq(8)").unwrap().node
+        );
+
+        for (desc, depth, len) in &[("ver_eq(8,3)", 3, 10), ("ver_eq(8,20)", 20, 11)] {
+            let ms = MsExtVer::from_str_insane(desc).unwrap();
+            assert_eq!(ms.to_string(), *desc);
+            match ms.node {
+                Terminal::Ext(ref v) => assert_eq!(
+                    *v,
+                    LegacyVerEq {
+                        n: 8,
+                        depth: *depth
+                    }
+                ),
+                _ => unreachable!(),
+            }
+            let script = ms.encode();
+            assert_eq!(script.len(), *len);
+            assert_eq!(ms.script_size(), *len);
+            assert_eq!(ms, MsExtVer::parse_insane(&script).unwrap());
+        }
+
+        assert!(MsExtVer::from_str_insane("ver_eq(8,0)").is_err());
+    }
+
+    #[test]
+    fn test_ver_eq_evaluate_depth() {
+        let ver = serialize(&8u32);
+        let mut stack = interpreter::Stack::from(vec![interpreter::Element::Push(&ver[..])]);
+        let ver_eq = LegacyVerEq { n: 8, depth: 1 };
+        assert!(matches!(ver_eq.evaluate(&mut stack, None), Ok(true)));
+
+        // A zero depth points past the top of the stack
+        let ver_eq = LegacyVerEq { n: 8, depth: 0 };
+        assert!(matches!(
+            ver_eq.evaluate(&mut stack, None),
+            Err(interpreter::Error::UnexpectedStackEnd)
+        ));
+    }
 }
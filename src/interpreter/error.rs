@@ -121,6 +121,13 @@ pub enum Error {
         /// Actual size
         actual: usize,
     },
+    /// The input index is not an input of the transaction
+    InputIndexOutOfRange {
+        /// The input index
+        index: usize,
+        /// The number of inputs of the transaction
+        n_inputs: usize,
+    },
     /// Errors related to extensions.
     ArithError(EvalError),
 }
@@ -209,6 +216,11 @@ impl fmt::Display for Error {
                 "At script code item position{}: Expected size{}, got size {}",
                 pos, expected, actual
             ),
+            Error::InputIndexOutOfRange { index, n_inputs } => write!(
+                f,
+                "input index {} out of range for a transaction with {} inputs",
+                index, n_inputs
+            ),
             Error::ArithError(ref e) => write!(f, "{}", e),
         }
     }
@@ -259,6 +271,7 @@ impl error::Error for Error {
             SighashError(e) => Some(e),
             IncorrectCovenantWitness => None,
             CovWitnessSizeErr { .. } => None,
+            InputIndexOutOfRange { .. } => None,
             ArithError(..) => None,
         }
     }
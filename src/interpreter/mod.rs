@@ -18,7 +18,7 @@ use elements::{self, secp256k1_zkp, sighash, EcdsaSigHashType, LockTime, Sequenc
 use crate::extensions::{CovExtArgs, ParseableExt, TxEnv};
 use crate::miniscript::context::{NoChecks, SigType};
 use crate::miniscript::ScriptContext;
-use crate::{hash256, util, Descriptor, ElementsSig, Miniscript, Terminal, ToPublicKey};
+use crate::{hash256, util, Descriptor, ElementsSig, HashKind, Miniscript, Terminal, ToPublicKey};
 
 mod error;
 mod inner;
@@ -491,6 +491,77 @@ where
     }
 }
 
+impl<Ext: ParseableExt> Miniscript<BitcoinKey, NoChecks, Ext> {
+    /// Replays the witness stack `witness` (without the witness script)
+    /// against the script and iterates over the constraints it satisfies,
+    /// like [`Interpreter::iter_assume_sigs`] does for a full transaction.
+    ///
+    /// Signatures are not verified since there is no transaction to compute
+    /// a sighash from; they are assumed valid for the key they are checked
+    /// against. Timelocks are compared with the `age` (relative) and
+    /// `lock_time` (absolute) of the spending transaction. Schnorr
+    /// signatures are expected if the script contains x-only keys, ECDSA
+    /// signatures otherwise.
+    pub fn satisfied_constraints<'a>(
+        &'a self,
+        witness: &'a [Vec<u8>],
+        age: Sequence,
+        lock_time: LockTime,
+    ) -> Iter<'a, 'a, Ext> {
+        let sig_type = if self
+            .iter_pk()
+            .any(|pk| matches!(pk, BitcoinKey::XOnlyPublicKey(_)))
+        {
+            SigType::Schnorr
+        } else {
+            SigType::Ecdsa
+        };
+        Iter {
+            verify_sig: Box::new(|_| true),
+            public_key: None,
+            state: vec![NodeEvaluationState {
+                node: self,
+                n_evaluated: 0,
+                n_satisfied: 0,
+            }],
+            stack: witness.iter().map(Element::from).collect::<Vec<_>>().into(),
+            txenv: None,
+            age,
+            lock_time,
+            cov: None,
+            has_errored: false,
+            sig_type,
+        }
+    }
+
+    /// Checks that the witness stack `witness` (without the witness script)
+    /// is a valid satisfaction of the script when spent by input
+    /// `input_index` of `tx`.
+    ///
+    /// Timelocks are checked against the sequence of the input and the lock
+    /// time of `tx`. As for [`Miniscript::satisfied_constraints`],
+    /// signatures are assumed valid for the key they are checked against.
+    pub fn verify_witness(
+        &self,
+        witness: &[Vec<u8>],
+        tx: &elements::Transaction,
+        input_index: usize,
+    ) -> Result<(), Error> {
+        let input = tx
+            .input
+            .get(input_index)
+            .ok_or(Error::InputIndexOutOfRange {
+                index: input_index,
+                n_inputs: tx.input.len(),
+            })?;
+        let lock_time = LockTime::from(tx.lock_time);
+        for constraint in self.satisfied_constraints(witness, input.sequence, lock_time) {
+            constraint?;
+        }
+        Ok(())
+    }
+}
+
 /// Type of HashLock used for SatisfiedConstraint structure
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum HashLockType {
@@ -739,6 +810,20 @@ where
                         return res;
                     }
                 }
+                Terminal::RawHashLock(ref hash, size_guard) => {
+                    debug_assert_eq!(node_state.n_evaluated, 0);
+                    debug_assert_eq!(node_state.n_satisfied, 0);
+                    let hash = match *hash {
+                        HashKind::Sha256(h) => HashLockType::Sha256(h),
+                        HashKind::Hash256(h) => HashLockType::Hash256(h),
+                        HashKind::Ripemd160(h) => HashLockType::Ripemd160(h),
+                        HashKind::Hash160(h) => HashLockType::Hash160(h),
+                    };
+                    let res = self.stack.evaluate_raw_hashlock(hash, size_guard);
+                    if res.is_some() {
+                        return res;
+                    }
+                }
                 Terminal::Ext(ref ext) => {
                     let res = ext.evaluate(&mut self.stack, self.txenv);
                     match res {
@@ -1740,6 +1825,96 @@ mod tests {
         assert!(multi_a_error.is_err());
     }
 
+    #[test]
+    fn satisfied_constraints() {
+        let (pks, der_sigs, ecdsa_sigs, _, _, _, _, _) = setup_keys_sigs(2);
+        let preimage = [0xab; 32];
+        let sha256_hash = sha256::Hash::hash(&preimage);
+        let ms = no_checks_ms(&format!(
+            "and_v(v:pk({}),and_v(v:sha256({}),or_d(pk({}),older(1000))))",
+            pks[0], sha256_hash, pks[1]
+        ));
+        let age = Sequence::from_height(1000);
+        let lock_time = LockTime::from_height(0).unwrap();
+
+        // Dissatisfy the second key and take the timelock branch
+        let witness = vec![vec![], preimage.to_vec(), der_sigs[0].clone()];
+        let constraints: Result<Vec<SatisfiedConstraint<NoExt>>, Error> =
+            ms.satisfied_constraints(&witness, age, lock_time).collect();
+        assert_eq!(
+            constraints.unwrap(),
+            vec![
+                SatisfiedConstraint::PublicKey {
+                    key_sig: KeySigPair::Ecdsa(pks[0], ecdsa_sigs[0])
+                },
+                SatisfiedConstraint::HashLock {
+                    hash: HashLockType::Sha256(sha256_hash),
+                    preimage,
+                },
+                SatisfiedConstraint::RelativeTimelock { n: age },
+            ]
+        );
+
+        // The timelock is not met by a younger input
+        let age = Sequence::from_height(999);
+        let constraints: Result<Vec<SatisfiedConstraint<NoExt>>, Error> =
+            ms.satisfied_constraints(&witness, age, lock_time).collect();
+        assert!(constraints.is_err());
+    }
+
+    #[test]
+    fn verify_witness() {
+        let (pks, der_sigs, _, _, _, _, _, _) = setup_keys_sigs(2);
+        let ms = no_checks_ms(&format!(
+            "and_v(v:pk({}),or_d(pk({}),and_v(v:older(1000),after(500000))))",
+            pks[0], pks[1]
+        ));
+        let txin = |sequence| elements::TxIn {
+            previous_output: elements::OutPoint::default(),
+            is_pegin: false,
+            script_sig: elements::Script::new(),
+            sequence,
+            asset_issuance: elements::AssetIssuance::default(),
+            witness: elements::TxInWitness::default(),
+        };
+        let mut tx = elements::Transaction {
+            version: 2,
+            lock_time: elements::PackedLockTime(500000),
+            input: vec![txin(Sequence::from_height(1000)), txin(Sequence(0))],
+            output: vec![],
+        };
+
+        // Dissatisfy the second key and take the timelock branch
+        let witness = vec![vec![], der_sigs[0].clone()];
+        assert!(ms.verify_witness(&witness, &tx, 0).is_ok());
+        assert!(matches!(
+            ms.verify_witness(&witness, &tx, 1),
+            Err(Error::RelativeLocktimeNotMet(1000))
+        ));
+        assert!(matches!(
+            ms.verify_witness(&witness, &tx, 2),
+            Err(Error::InputIndexOutOfRange {
+                index: 2,
+                n_inputs: 2
+            })
+        ));
+        tx.lock_time = elements::PackedLockTime(499999);
+        assert!(matches!(
+            ms.verify_witness(&witness, &tx, 0),
+            Err(Error::AbsoluteLocktimeNotMet(500000))
+        ));
+
+        // Satisfying both keys does not need the timelocks
+        let witness = vec![der_sigs[1].clone(), der_sigs[0].clone()];
+        assert!(ms.verify_witness(&witness, &tx, 1).is_ok());
+        // Extra elements are left on the stack
+        let witness = vec![vec![], der_sigs[1].clone(), der_sigs[0].clone()];
+        assert!(matches!(
+            ms.verify_witness(&witness, &tx, 1),
+            Err(Error::ScriptSatisfactionError)
+        ));
+    }
+
     // By design there is no support for parse a miniscript with BitcoinKey
     // because it does not implement FromStr
     fn no_checks_ms(ms: &str) -> Miniscript<BitcoinKey, NoChecks> {
@@ -388,6 +388,42 @@ impl<'txin> Stack<'txin> {
         }
     }
 
+    /// Helper function to evaluate a raw hashlock node, a hashlock whose
+    /// `SIZE n EQUALVERIFY` preimage size guard is missing or checks a size
+    /// other than 32. A matching preimage is only accepted if it is 32
+    /// bytes, like the preimages of the guarded hashlocks.
+    pub(super) fn evaluate_raw_hashlock<Ext: Extension>(
+        &mut self,
+        hash: HashLockType,
+        size_guard: Option<u32>,
+    ) -> Option<Result<SatisfiedConstraint<Ext>, Error>> {
+        if let Some(Element::Push(preimage)) = self.pop() {
+            if size_guard.map_or(false, |n| preimage.len() != n as usize) {
+                return Some(Err(Error::HashPreimageLengthMismatch));
+            }
+            let is_preimage = match hash {
+                HashLockType::Sha256(h) => sha256::Hash::hash(preimage) == h,
+                HashLockType::Hash256(h) => hash256::Hash::hash(preimage) == h,
+                HashLockType::Hash160(h) => hash160::Hash::hash(preimage) == h,
+                HashLockType::Ripemd160(h) => ripemd160::Hash::hash(preimage) == h,
+            };
+            if !is_preimage {
+                self.push(Element::Dissatisfied);
+                None
+            } else if preimage.len() != 32 {
+                Some(Err(Error::HashPreimageLengthMismatch))
+            } else {
+                self.push(Element::Satisfied);
+                Some(Ok(SatisfiedConstraint::HashLock {
+                    hash,
+                    preimage: preimage_from_sl(preimage),
+                }))
+            }
+        } else {
+            Some(Err(Error::UnexpectedStackEnd))
+        }
+    }
+
     /// Helper function to evaluate a checkmultisig which takes the top of the
     /// stack as input signatures and validates it in order of pubkeys.
     /// For example, if the first signature is satisfied by second public key,
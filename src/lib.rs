@@ -154,13 +154,17 @@ use elements::{opcodes, script, secp256k1_zkp};
 pub use crate::descriptor::{DefiniteDescriptorKey, Descriptor, DescriptorPublicKey};
 pub use crate::extensions::{CovenantExt, Extension, NoExt, TxEnv};
 pub use crate::interpreter::Interpreter;
-pub use crate::miniscript::analyzable::{AnalysisError, ExtParams};
-pub use crate::miniscript::context::{BareCtx, Legacy, ScriptContext, Segwitv0, Tap};
+pub use crate::miniscript::analyzable::{AnalysisError, ExtParams, HashKind};
+pub use crate::miniscript::context::{
+    BareCtx, ContextKind, Legacy, ScriptContext, ScriptContextError, Segwitv0, Tap,
+};
 pub use crate::miniscript::decode::Terminal;
 pub use crate::miniscript::satisfy::{
-    elementssig_from_rawsig, elementssig_to_rawsig, ElementsSig, Preimage32, Satisfier,
+    elementssig_from_rawsig, elementssig_to_rawsig, ElementsSig, FnSatisfier, LayeredSatisfier,
+    Preimage32, Satisfier, SatisfyOptions,
 };
 pub use crate::miniscript::Miniscript;
+pub use crate::util::witness_to_scriptsig;
 // minimal implementation of contract hash module
 mod contracthash {
     use bitcoin::secp256k1::Scalar;
@@ -297,7 +301,10 @@ pub enum Error {
     AddrError(bitcoin::util::address::Error),
     /// A `CHECKMULTISIG` opcode was preceded by a number > 20
     CmsTooManyKeys(u32),
-    /// A tapscript multi_a cannot support more than MAX_BLOCK_WEIGHT/32 keys
+    /// A tapscript multi_a cannot support more than MAX_BLOCK_WEIGHT/32 keys.
+    /// Every key is a 32 byte push, so no larger multi_a fits in a block.
+    /// Returned by the decoder when either the threshold or the number of
+    /// `CHECKSIGADD` keys exceeds the limit; the limit is carried in the error
     MultiATooManyKeys(u32),
     /// Encountered unprintable character in descriptor
     Unprintable(u8),
@@ -305,6 +312,8 @@ pub enum Error {
     ExpectedChar(char),
     /// While parsing backward, hit beginning of script
     UnexpectedStart,
+    /// `TOALTSTACK` and `FROMALTSTACK` opcodes that do not pair up
+    UnbalancedAltStack,
     /// Got something we were not expecting
     Unexpected(String),
     /// Name of a fragment contained `:` multiple times
@@ -346,8 +355,15 @@ pub enum Error {
     PolicyError(policy::concrete::PolicyError),
     /// Errors related to lifting
     LiftError(policy::LiftError),
+    /// A timelock does not fit in the 32-bit `nLockTime` or `nSequence`
+    /// field it is compared to
+    LocktimeOutOfRange(i64),
     /// Forward script context related errors
     ContextError(miniscript::context::ScriptContextError),
+    /// A fragment is invalid in the script context it was decoded in. The
+    /// position is the index, counted from the end of the script, of the
+    /// last token of the offending fragment
+    FragmentContextError(miniscript::context::ScriptContextError, usize),
     /// Recursion depth exceeded when parsing policy/miniscript from string
     MaxRecursiveDepthExceeded,
     /// Script size too large
@@ -355,10 +371,18 @@ pub enum Error {
     /// Anything but c:pk(key) (P2PK), c:pk_h(key) (P2PKH), and thresh_m(k,...)
     /// up to n=3 is invalid by standardness (bare)
     NonStandardBareScript,
+    /// A tapscript leaf that is unconditionally spendable (`1`) makes the
+    /// rest of the taproot tree pointless
+    TrivialTapLeaf,
     /// Analysis Error
     AnalysisError(miniscript::analyzable::AnalysisError),
     /// Miniscript is equivalent to false. No possible satisfaction
     ImpossibleSatisfaction,
+    /// Miniscript cannot be dissatisfied, e.g. a `v:` fragment
+    ImpossibleDissatisfaction,
+    /// Only malleable satisfactions are available, but a non-malleable
+    /// one was required
+    NoNonMalleableSatisfaction,
     /// Bare descriptors don't have any addresses
     BareDescriptorAddr,
     /// Upstream Miniscript Errors
@@ -378,6 +402,18 @@ pub enum Error {
     MultipathDescLenMismatch,
 }
 
+impl Error {
+    /// If this error was caused by a script context check, returns the
+    /// underlying [`ScriptContextError`]
+    pub fn as_context_error(&self) -> Option<&ScriptContextError> {
+        match *self {
+            Error::ContextError(ref e) => Some(e),
+            Error::FragmentContextError(ref e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[doc(hidden)]
 impl<Pk, Ctx, Ext> From<miniscript::types::Error<Pk, Ctx, Ext>> for Error
 where
@@ -469,6 +505,9 @@ impl fmt::Display for Error {
             Error::Unprintable(x) => write!(f, "unprintable character 0x{:02x}", x),
             Error::ExpectedChar(c) => write!(f, "expected {}", c),
             Error::UnexpectedStart => f.write_str("unexpected start of script"),
+            Error::UnbalancedAltStack => {
+                f.write_str("unbalanced TOALTSTACK and FROMALTSTACK in script")
+            }
             Error::Unexpected(ref s) => write!(f, "unexpected «{}»", s),
             Error::MultiColon(ref s) => write!(f, "«{}» has multiple instances of «:»", s),
             Error::MultiAt(ref s) => write!(f, "«{}» has multiple instances of «@»", s),
@@ -492,7 +531,13 @@ impl fmt::Display for Error {
             Error::TypeCheck(ref e) => write!(f, "typecheck: {}", e),
             Error::BadDescriptor(ref e) => write!(f, "Invalid descriptor: {}", e),
             Error::Secp(ref e) => fmt::Display::fmt(e, f),
+            Error::LocktimeOutOfRange(n) => {
+                write!(f, "timelock {} is out of range, it must fit in 32 bits", n)
+            }
             Error::ContextError(ref e) => fmt::Display::fmt(e, f),
+            Error::FragmentContextError(ref e, pos) => {
+                write!(f, "{} at token {} from the end of the script", e, pos)
+            }
             #[cfg(feature = "compiler")]
             Error::CompilerError(ref e) => fmt::Display::fmt(e, f),
             Error::PolicyError(ref e) => fmt::Display::fmt(e, f),
@@ -513,8 +558,15 @@ impl fmt::Display for Error {
                 up to n=3 is invalid by standardness (bare).
                 "
             ),
+            Error::TrivialTapLeaf => {
+                write!(f, "Tapscript leaf is unconditionally spendable")
+            }
             Error::AnalysisError(ref e) => e.fmt(f),
             Error::ImpossibleSatisfaction => write!(f, "Impossible to satisfy Miniscript"),
+            Error::ImpossibleDissatisfaction => write!(f, "Impossible to dissatisfy Miniscript"),
+            Error::NoNonMalleableSatisfaction => {
+                write!(f, "No non-malleable satisfaction available")
+            }
             Error::BareDescriptorAddr => write!(f, "Bare descriptors don't have address"),
             Error::BtcError(ref e) => write!(f, " Bitcoin Miniscript Error {}", e),
             Error::CovError(ref e) => write!(f, "Covenant Error: {}", e),
@@ -543,6 +595,7 @@ impl error::Error for Error {
             | Unprintable(_)
             | ExpectedChar(_)
             | UnexpectedStart
+            | UnbalancedAltStack
             | Unexpected(_)
             | MultiColon(_)
             | MultiAt(_)
@@ -561,11 +614,15 @@ impl error::Error for Error {
             | MaxRecursiveDepthExceeded
             | ScriptSizeTooLarge
             | NonStandardBareScript
+            | TrivialTapLeaf
             | ImpossibleSatisfaction
+            | ImpossibleDissatisfaction
+            | NoNonMalleableSatisfaction
             | BareDescriptorAddr
             | TaprootSpendInfoUnavialable
             | TrNoScriptCode
-            | TrNoExplicitScript => None,
+            | TrNoExplicitScript
+            | LocktimeOutOfRange(_) => None,
             MultipathDescLenMismatch => None,
             BtcError(e) => Some(e),
             CovError(e) => Some(e),
@@ -578,6 +635,7 @@ impl error::Error for Error {
             PolicyError(e) => Some(e),
             LiftError(e) => Some(e),
             ContextError(e) => Some(e),
+            FragmentContextError(e, _) => Some(e),
             AnalysisError(e) => Some(e),
             PubKeyCtxError(e, _) => Some(e),
         }
@@ -657,6 +715,31 @@ mod tests {
         assert_eq!(got, want)
     }
 
+    #[test]
+    fn context_error_accessor() {
+        let err = Miniscript::<String, Tap>::from_str_insane("multi(1,A)").unwrap_err();
+        assert_eq!(
+            err.as_context_error(),
+            Some(&ScriptContextError::TaprootMultiDisabled)
+        );
+        assert_eq!(Error::CouldNotSatisfy.as_context_error(), None);
+    }
+
+    #[test]
+    fn witness_to_scriptsig_pushes() {
+        let sig = vec![0x30; 72];
+        let witness = vec![sig.clone(), vec![], vec![1], vec![0x81]];
+        let script_sig = witness_to_scriptsig(&witness);
+        let expected = script::Builder::new()
+            .push_slice(&sig)
+            .push_opcode(opcodes::all::OP_PUSHBYTES_0)
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_opcode(opcodes::all::OP_PUSHNUM_NEG1)
+            .into_script();
+        assert_eq!(script_sig, expected);
+        assert_eq!(witness_to_scriptsig(&[]), elements::Script::new());
+    }
+
     #[test]
     fn regression_secp256k1_key_hash() {
         use bitcoin::secp256k1::PublicKey;
@@ -221,6 +221,10 @@ macro_rules! serde_string_impl_pk {
     };
 }
 
+/// Match the next tokens of a `TokenIter` against each arm in turn. A token
+/// matching no arm, or running out of tokens, returns an error from the
+/// enclosing function instead of panicking, so every invocation is safe on
+/// untrusted scripts without a fallback arm of its own.
 macro_rules! match_token {
     // Base case
     ($tokens:expr => $sub:expr,) => { $sub };
@@ -230,7 +234,13 @@ macro_rules! match_token {
             $(
                 Some($first) => match_token!($tokens $(,$rest)* => $sub,),
             )*
-            Some(other) => return Err(Error::Unexpected(other.to_string())),
+            Some(other) => {
+                return Err(Error::Unexpected(format!(
+                    "{} at token {} from the end of the script",
+                    other,
+                    $tokens.position() - 1,
+                )))
+            }
             None => return Err(Error::UnexpectedStart),
         }
     };
@@ -9,7 +9,16 @@
 use std::collections::HashSet;
 use std::fmt;
 
-use crate::{error, Extension, Miniscript, MiniscriptKey, ScriptContext, Terminal};
+use elements::LockTime;
+
+use crate::miniscript::context::ScriptContextError;
+use crate::{
+    error, Error, Extension, Miniscript, MiniscriptKey, ScriptContext, Tap, Terminal, Translator,
+};
+
+// If nSequence encodes a relative lock-time, this mask is applied to extract
+// that lock-time from the sequence field
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
 
 /// Params for parsing miniscripts that either non-sane or non-specified(experimental) in the spec.
 /// Used as a parameter [`Miniscript::from_str_ext`] and [`Miniscript::parse_with_ext`].
@@ -23,6 +32,8 @@ use crate::{error, Extension, Miniscript, MiniscriptKey, ScriptContext, Terminal
 ///    guarantees are not satisfied.
 /// 4. It has repeated public keys
 /// 5. raw pkh fragments without the pk. This could be obtained when parsing miniscript from script
+/// 6. raw hashlock fragments without the canonical preimage size guard. This could be obtained
+///    when parsing miniscript from script
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
 pub struct ExtParams {
     /// Allow parsing of non-safe miniscripts
@@ -38,6 +49,10 @@ pub struct ExtParams {
     /// Allow parsing of miniscripts with raw pkh fragments without the pk.
     /// This could be obtained when parsing miniscript from script
     pub raw_pkh: bool,
+    /// Allow parsing of miniscripts with raw hashlock fragments, whose
+    /// preimage size guard is missing or not `SIZE 32 EQUALVERIFY`.
+    /// This could be obtained when parsing miniscript from script
+    pub raw_hashlock: bool,
 }
 
 impl ExtParams {
@@ -50,6 +65,7 @@ impl ExtParams {
             malleability: false,
             repeated_pk: false,
             raw_pkh: false,
+            raw_hashlock: false,
         }
     }
 
@@ -69,6 +85,7 @@ impl ExtParams {
             malleability: true,
             repeated_pk: true,
             raw_pkh: false,
+            raw_hashlock: false,
         }
     }
 
@@ -81,6 +98,7 @@ impl ExtParams {
             malleability: true,
             repeated_pk: true,
             raw_pkh: true,
+            raw_hashlock: true,
         }
     }
 
@@ -119,6 +137,12 @@ impl ExtParams {
         self.raw_pkh = true;
         self
     }
+
+    /// Builder that allows miniscripts with raw hashlock fragments.
+    pub fn raw_hashlock(mut self) -> ExtParams {
+        self.raw_hashlock = true;
+        self
+    }
 }
 
 /// We currently mark Miniscript as Non-Analyzable if
@@ -143,6 +167,8 @@ pub enum AnalysisError {
     Malleable,
     /// Contains partial descriptor raw pkh
     ContainsRawPkh,
+    /// Contains a hashlock without the canonical preimage size guard
+    ContainsRawHashLock,
 }
 
 impl fmt::Display for AnalysisError {
@@ -162,6 +188,7 @@ impl fmt::Display for AnalysisError {
             }
             AnalysisError::Malleable => f.write_str("Miniscript is malleable"),
             AnalysisError::ContainsRawPkh => f.write_str("Miniscript contains raw pkh"),
+            AnalysisError::ContainsRawHashLock => f.write_str("Miniscript contains raw hashlock"),
         }
     }
 }
@@ -176,9 +203,48 @@ impl error::Error for AnalysisError {
             | BranchExceedResouceLimits
             | HeightTimelockCombination
             | Malleable
-            | ContainsRawPkh => None,
+            | ContainsRawPkh
+            | ContainsRawHashLock => None,
+        }
+    }
+}
+
+/// A hashlock fragment of a Miniscript, along with the hash it locks to.
+/// Returned by [`Miniscript::hashlock_kinds`]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKind<Pk: MiniscriptKey> {
+    /// `sha256(h)`
+    Sha256(Pk::Sha256),
+    /// `hash256(h)`
+    Hash256(Pk::Hash256),
+    /// `ripemd160(h)`
+    Ripemd160(Pk::Ripemd160),
+    /// `hash160(h)`
+    Hash160(Pk::Hash160),
+}
+
+impl<Pk: MiniscriptKey> HashKind<Pk> {
+    /// The length in bytes of the hash
+    pub(crate) fn hash_len(&self) -> usize {
+        match *self {
+            HashKind::Sha256(..) | HashKind::Hash256(..) => 32,
+            HashKind::Ripemd160(..) | HashKind::Hash160(..) => 20,
         }
     }
+
+    /// Translate the hash with the given translator
+    pub(crate) fn translate<Q, T, E>(&self, t: &mut T) -> Result<HashKind<Q>, E>
+    where
+        Q: MiniscriptKey,
+        T: Translator<Pk, Q, E>,
+    {
+        Ok(match *self {
+            HashKind::Sha256(ref h) => HashKind::Sha256(t.sha256(h)?),
+            HashKind::Hash256(ref h) => HashKind::Hash256(t.hash256(h)?),
+            HashKind::Ripemd160(ref h) => HashKind::Ripemd160(t.ripemd160(h)?),
+            HashKind::Hash160(ref h) => HashKind::Hash160(t.hash160(h)?),
+        })
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx, Ext> {
@@ -187,11 +253,116 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
         self.ty.mall.safe
     }
 
+    /// Whether the miniscript has a satisfaction path that does not require
+    /// any signature, e.g. `or_d(pk(A),sha256(H))` can be spent with only
+    /// the preimage of `H`.
+    ///
+    /// This is the negation of [`Miniscript::requires_sig`]. Extensions are
+    /// analyzed through the malleability properties they declare.
+    pub fn has_keyless_spend_path(&self) -> bool {
+        !self.requires_sig()
+    }
+
+    /// Whether at least one satisfaction path has all of its timelocks
+    /// satisfied in the block after a chain tip at `height` with median time
+    /// past `mtp`. Signatures, preimages and extensions are assumed to be
+    /// available, so this only filters out paths that are not yet live.
+    ///
+    /// `height` and `mtp` alone cannot decide an `older`: BIP68 measures
+    /// relative timelocks from the block which confirmed the spent output,
+    /// so the same `older(144)` is live or not at a given tip depending on
+    /// the output. That block is given by `confirmed` as its height and the
+    /// median time past of its parent block. Pass `None` for unconfirmed
+    /// outputs, which cannot satisfy any `older`, so that only branches
+    /// without relative timelocks are considered live.
+    pub fn is_satisfiable_at(&self, height: u32, mtp: u32, confirmed: Option<(u32, u32)>) -> bool {
+        match self.node {
+            Terminal::True
+            | Terminal::PkK(..)
+            | Terminal::PkH(..)
+            | Terminal::RawPkH(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..)
+            | Terminal::RawHashLock(..)
+            | Terminal::Multi(..)
+            | Terminal::MultiA(..)
+            | Terminal::Ext(..) => true,
+            Terminal::False => false,
+            Terminal::After(t) => {
+                // BIP65/BIP113: the locktime must be below the height of the
+                // new block, or below the median time past of the tip
+                let t = LockTime::from(t);
+                if t.is_block_height() {
+                    t.to_consensus_u32() <= height
+                } else {
+                    t.to_consensus_u32() < mtp
+                }
+            }
+            Terminal::Older(seq) => match confirmed {
+                // BIP68
+                Some((conf_height, conf_mtp)) => {
+                    let n = seq.to_consensus_u32() & SEQUENCE_LOCKTIME_MASK;
+                    if seq.is_time_locked() {
+                        mtp.saturating_sub(conf_mtp) >= n * 512
+                    } else {
+                        (height + 1).saturating_sub(conf_height) >= n
+                    }
+                }
+                None => false,
+            },
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::DupIf(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.is_satisfiable_at(height, mtp, confirmed),
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                l.is_satisfiable_at(height, mtp, confirmed)
+                    && r.is_satisfiable_at(height, mtp, confirmed)
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                (a.is_satisfiable_at(height, mtp, confirmed)
+                    && b.is_satisfiable_at(height, mtp, confirmed))
+                    || c.is_satisfiable_at(height, mtp, confirmed)
+            }
+            Terminal::OrB(ref l, ref r)
+            | Terminal::OrD(ref l, ref r)
+            | Terminal::OrC(ref l, ref r)
+            | Terminal::OrI(ref l, ref r) => {
+                l.is_satisfiable_at(height, mtp, confirmed)
+                    || r.is_satisfiable_at(height, mtp, confirmed)
+            }
+            Terminal::Thresh(k, ref subs) => {
+                subs.iter()
+                    .filter(|sub| sub.is_satisfiable_at(height, mtp, confirmed))
+                    .count()
+                    >= k
+            }
+        }
+    }
+
     /// Whether the miniscript is malleable
     pub fn is_non_malleable(&self) -> bool {
         self.ty.mall.non_malleable
     }
 
+    /// Check every fragment of the miniscript against the context specific
+    /// malleability rules, e.g. `pkh` and `or_i` are malleable under Legacy
+    /// rules. Returns the error of the first offending fragment, in
+    /// pre-order.
+    ///
+    /// Unlike [`Miniscript::is_non_malleable`], this only covers the context
+    /// rules and not the malleability derived from the type system.
+    pub fn check_non_malleable(&self) -> Result<(), ScriptContextError> {
+        for ms in self.iter() {
+            Ctx::check_terminal_non_malleable(&ms.node)?;
+        }
+        Ok(())
+    }
+
     /// Whether the miniscript can exceed the resource limits(Opcodes, Stack limit etc)
     // It maybe possible to return a detail error type containing why the miniscript
     // failed. But doing so may require returning a collection of errors
@@ -215,6 +386,17 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
         unique_pkhs_len != all_pkhs_len
     }
 
+    /// Whether the miniscript contains any uncompressed public key
+    pub fn has_uncompressed_keys(&self) -> bool {
+        self.iter_pk().any(|pk| pk.is_uncompressed())
+    }
+
+    /// Number of uncompressed public keys in the miniscript, counting
+    /// repeated keys once per occurrence
+    pub fn uncompressed_key_count(&self) -> usize {
+        self.iter_pk().filter(|pk| pk.is_uncompressed()).count()
+    }
+
     /// Whether the given miniscript contains a raw pkh fragment
     pub fn contains_raw_pkh(&self) -> bool {
         self.iter().any(|ms| match ms.node {
@@ -223,6 +405,227 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
         })
     }
 
+    /// Whether the given miniscript contains a raw hashlock fragment
+    pub fn contains_raw_hashlock(&self) -> bool {
+        self.iter().any(|ms| match ms.node {
+            Terminal::RawHashLock(..) => true,
+            _ => false,
+        })
+    }
+
+    /// The raw hashlock fragments of the miniscript, as the hash and the
+    /// size checked by the preimage size guard, if any. These are the
+    /// hashlocks which were parsed from a script without the canonical
+    /// `SIZE 32 EQUALVERIFY` guard.
+    pub fn raw_hashlocks(&self) -> Vec<(HashKind<Pk>, Option<u32>)> {
+        self.iter()
+            .filter_map(|ms| match ms.node {
+                Terminal::RawHashLock(ref h, size_guard) => Some((h.clone(), size_guard)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The distinct hashlocks of the miniscript, i.e. every preimage that a
+    /// satisfaction may have to reveal
+    pub fn hashlock_kinds(&self) -> HashSet<HashKind<Pk>> {
+        self.iter()
+            .filter_map(|ms| match ms.node {
+                Terminal::Sha256(ref h) => Some(HashKind::Sha256(h.clone())),
+                Terminal::Hash256(ref h) => Some(HashKind::Hash256(h.clone())),
+                Terminal::Ripemd160(ref h) => Some(HashKind::Ripemd160(h.clone())),
+                Terminal::Hash160(ref h) => Some(HashKind::Hash160(h.clone())),
+                Terminal::RawHashLock(ref h, _) => Some(h.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Find the sub-miniscripts that always evaluate to true or always to
+    /// false, whatever the witness. These are dead branches which only waste
+    /// script space, e.g. the `0` in `or_d(pk(A),0)`.
+    ///
+    /// Each entry is the path to the subtree, as the child indices to pass
+    /// to [`Miniscript::get_nth_child`] starting from the root, along with
+    /// its constant value. Only the outermost constant subtree is reported.
+    /// The `0` and `1` introduced by the `l:`, `u:` and `t:` wrappers are
+    /// not reported.
+    pub fn find_constant_subtrees(&self) -> Vec<(Vec<usize>, bool)> {
+        fn constant_value<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            ms: &Miniscript<Pk, Ctx, Ext>,
+        ) -> Option<bool> {
+            match ms.node {
+                Terminal::True => Some(true),
+                Terminal::False => Some(false),
+                Terminal::Alt(ref sub)
+                | Terminal::Swap(ref sub)
+                | Terminal::Verify(ref sub)
+                | Terminal::ZeroNotEqual(ref sub) => constant_value(sub),
+                Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                    match (constant_value(l), constant_value(r)) {
+                        (Some(false), _) | (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    }
+                }
+                Terminal::OrB(ref l, ref r)
+                | Terminal::OrD(ref l, ref r)
+                | Terminal::OrC(ref l, ref r) => match (constant_value(l), constant_value(r)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+                // The witness picks the branch, so either may be taken
+                Terminal::OrI(ref l, ref r) => match (constant_value(l), constant_value(r)) {
+                    (Some(x), Some(y)) if x == y => Some(x),
+                    _ => None,
+                },
+                Terminal::AndOr(ref a, ref b, ref c) => match constant_value(a) {
+                    Some(true) => constant_value(b),
+                    Some(false) => constant_value(c),
+                    None => match (constant_value(b), constant_value(c)) {
+                        (Some(x), Some(y)) if x == y => Some(x),
+                        _ => None,
+                    },
+                },
+                Terminal::Thresh(k, ref subs) => {
+                    let values: Vec<_> = subs.iter().map(|sub| constant_value(sub)).collect();
+                    let n_true = values.iter().filter(|v| **v == Some(true)).count();
+                    let n_false = values.iter().filter(|v| **v == Some(false)).count();
+                    // Exactly `k` subs must be true
+                    if n_true > k || subs.len() - n_false < k {
+                        Some(false)
+                    } else if n_true == k && n_true + n_false == subs.len() {
+                        Some(true)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        }
+
+        // Whether `ms` is the `n`th child of `parent` because of a `l:`, `u:`
+        // or `t:` wrapper
+        fn is_wrapper_constant<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            parent: &Miniscript<Pk, Ctx, Ext>,
+            n: usize,
+            ms: &Miniscript<Pk, Ctx, Ext>,
+        ) -> bool {
+            match (&parent.node, n, &ms.node) {
+                (&Terminal::OrI(..), _, &Terminal::False) => true,
+                (&Terminal::AndV(..), 1, &Terminal::True) => true,
+                _ => false,
+            }
+        }
+
+        fn walk<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            ms: &Miniscript<Pk, Ctx, Ext>,
+            path: &mut Vec<usize>,
+            ret: &mut Vec<(Vec<usize>, bool)>,
+        ) {
+            for (n, child) in ms.branches().into_iter().enumerate() {
+                if is_wrapper_constant(ms, n, child) {
+                    continue;
+                }
+                path.push(n);
+                match constant_value(child) {
+                    Some(value) => ret.push((path.clone(), value)),
+                    None => walk(child, path, ret),
+                }
+                path.pop();
+            }
+        }
+
+        let mut ret = vec![];
+        match constant_value(self) {
+            Some(value) => ret.push((vec![], value)),
+            None => walk(self, &mut vec![], &mut ret),
+        }
+        ret
+    }
+
+    /// The number of distinct ways to satisfy the miniscript, as a complexity
+    /// metric. Each choice of branches, and of keys in `multi`/`multi_a`,
+    /// counts as one spending path: disjunctions add up the paths of their
+    /// branches, conjunctions multiply them and `thresh(k,...)` sums over all
+    /// sets of `k` satisfied subs. Saturates at `u64::MAX`.
+    pub fn num_satisfaction_paths(&self) -> u64 {
+        fn binomial(n: usize, k: usize) -> u64 {
+            // After each step `ret` is itself a binomial coefficient, so the
+            // division is exact and only the final value needs to fit a u64
+            let mut ret = 1u128;
+            for i in 0..k.min(n - k) {
+                ret = ret * (n - i) as u128 / (i as u128 + 1);
+                if ret > u64::MAX as u128 {
+                    return u64::MAX;
+                }
+            }
+            ret as u64
+        }
+
+        match self.node {
+            Terminal::False => 0,
+            Terminal::Multi(k, ref pks) | Terminal::MultiA(k, ref pks) => binomial(pks.len(), k),
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::DupIf(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.num_satisfaction_paths(),
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => l
+                .num_satisfaction_paths()
+                .saturating_mul(r.num_satisfaction_paths()),
+            Terminal::OrB(ref l, ref r)
+            | Terminal::OrD(ref l, ref r)
+            | Terminal::OrC(ref l, ref r)
+            | Terminal::OrI(ref l, ref r) => l
+                .num_satisfaction_paths()
+                .saturating_add(r.num_satisfaction_paths()),
+            Terminal::AndOr(ref a, ref b, ref c) => a
+                .num_satisfaction_paths()
+                .saturating_mul(b.num_satisfaction_paths())
+                .saturating_add(c.num_satisfaction_paths()),
+            Terminal::Thresh(k, ref subs) => {
+                // paths[j] is the number of paths satisfying exactly j of
+                // the subs considered so far
+                let mut paths = vec![0u64; k + 1];
+                paths[0] = 1;
+                for sub in subs {
+                    let n = sub.num_satisfaction_paths();
+                    for j in (1..=k).rev() {
+                        paths[j] = paths[j].saturating_add(paths[j - 1].saturating_mul(n));
+                    }
+                }
+                paths[k]
+            }
+            _ => 1,
+        }
+    }
+
+    /// The number of fragments on the longest path from the root to a leaf,
+    /// counting wrappers as fragments. A single fragment such as `pk_k(A)`
+    /// has depth 1 and `c:pk_k(A)` has depth 2.
+    ///
+    /// The tree is walked without recursion, so this can be used to reject
+    /// pathologically deep scripts.
+    pub fn depth(&self) -> usize {
+        let mut ret = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((ms, depth)) = stack.pop() {
+            ret = ret.max(depth);
+            stack.extend(ms.branches().into_iter().map(|sub| (sub, depth + 1)));
+        }
+        ret
+    }
+
+    /// The total number of fragments in the miniscript, counting wrappers
+    /// as fragments
+    pub fn node_count(&self) -> usize {
+        self.iter().count()
+    }
+
     /// Check whether the underlying Miniscript is safe under the current context
     /// Lifting these polices would create a semantic representation that does
     /// not represent the underlying semantics when miniscript is spent.
@@ -248,6 +651,47 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
         }
     }
 
+    /// Run every check of [`Miniscript::sanity_check`], along with the
+    /// context validity rules and the context malleability rules of
+    /// [`Miniscript::check_non_malleable`], and report all the failures
+    /// instead of only the first one.
+    ///
+    /// Because of the context malleability rules, this rejects some scripts
+    /// which [`Miniscript::sanity_check`] accepts, such as `or_i` under
+    /// `Legacy` rules.
+    pub fn sanity_check_all(&self) -> Result<(), Vec<Error>> {
+        let mut errors = vec![];
+        if let Err(e) = Ctx::check_local_validity(self) {
+            errors.push(Error::ContextError(e));
+        }
+        if let Err(e) = self.check_non_malleable() {
+            errors.push(Error::ContextError(e));
+        }
+        let analysis = vec![
+            (!self.requires_sig(), AnalysisError::SiglessBranch),
+            (!self.is_non_malleable(), AnalysisError::Malleable),
+            (
+                !self.within_resource_limits(),
+                AnalysisError::BranchExceedResouceLimits,
+            ),
+            (self.has_repeated_keys(), AnalysisError::RepeatedPubkeys),
+            (
+                self.has_mixed_timelocks(),
+                AnalysisError::HeightTimelockCombination,
+            ),
+        ];
+        for (failed, e) in analysis {
+            if failed {
+                errors.push(Error::AnalysisError(e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Check whether the miniscript follows the given Extra policy [`ExtParams`]
     pub fn ext_check(&self, ext: &ExtParams) -> Result<(), AnalysisError> {
         if !ext.top_unsafe && !self.requires_sig() {
@@ -262,6 +706,24 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
             Err(AnalysisError::HeightTimelockCombination)
         } else if !ext.raw_pkh && self.contains_raw_pkh() {
             Err(AnalysisError::ContainsRawPkh)
+        } else if !ext.raw_hashlock && self.contains_raw_hashlock() {
+            Err(AnalysisError::ContainsRawHashLock)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey, Ext: Extension> Miniscript<Pk, Tap, Ext> {
+    /// Check that the leaf script is at most `max_size` bytes long.
+    ///
+    /// Neither consensus nor standardness bound the size of a tapscript
+    /// leaf, only the block weight does, so the context checks accept
+    /// leaves far larger than any real use needs. This check is never run
+    /// by the parsers; callers wanting a tighter bound choose their own.
+    pub fn check_leaf_size(&self, max_size: usize) -> Result<(), ScriptContextError> {
+        if self.ext.pk_cost > max_size {
+            Err(ScriptContextError::MaxWitnessScriptSizeExceeded)
         } else {
             Ok(())
         }
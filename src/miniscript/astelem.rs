@@ -8,6 +8,7 @@
 //! encoding in Bitcoin script, as well as a datatype. Full details
 //! are given on the Miniscript website.
 
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -15,15 +16,15 @@ use std::sync::Arc;
 use bitcoin::hashes::hash160;
 use elements::{opcodes, script, LockTime, Sequence};
 
-use super::limits::{MAX_SCRIPT_ELEMENT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEM_SIZE};
 use crate::extensions::ParseableExt;
 use crate::miniscript::context::SigType;
+use crate::miniscript::limits::MAX_PUBKEYS_IN_CHECKSIGADD;
 use crate::miniscript::types::{self, Property};
 use crate::miniscript::ScriptContext;
 use crate::util::MsKeyBuilder;
 use crate::{
-    errstr, expression, script_num_size, Error, ExtTranslator, Extension, ForEachKey, Miniscript,
-    MiniscriptKey, Terminal, ToPublicKey, TranslateExt, TranslatePk, Translator,
+    errstr, expression, script_num_size, Error, ExtTranslator, Extension, ForEachKey, HashKind,
+    Miniscript, MiniscriptKey, Terminal, ToPublicKey, TranslateExt, TranslatePk, Translator,
 };
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ext> {
@@ -45,6 +46,65 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             _ => None,
         }
     }
+
+    /// Whether this is a timelock fragment, `after` or `older`
+    pub fn is_timelock(&self) -> bool {
+        match *self {
+            Terminal::After(_) | Terminal::Older(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is a hashlock fragment, `sha256`, `hash256`,
+    /// `ripemd160`, `hash160` or a raw hashlock
+    pub fn is_hashlock(&self) -> bool {
+        match *self {
+            Terminal::Sha256(_)
+            | Terminal::Hash256(_)
+            | Terminal::Ripemd160(_)
+            | Terminal::Hash160(_)
+            | Terminal::RawHashLock(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this fragment checks a signature against one or more keys,
+    /// `pk_k`, `pk_h`, `expr_raw_pkh`, `multi` or `multi_a`
+    pub fn is_key_check(&self) -> bool {
+        match *self {
+            Terminal::PkK(_)
+            | Terminal::PkH(_)
+            | Terminal::RawPkH(_)
+            | Terminal::Multi(..)
+            | Terminal::MultiA(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is an extension fragment. All covenant fragments, such
+    /// as `ver_eq` and `outputs_pref`, are extensions
+    pub fn is_covenant(&self) -> bool {
+        match *self {
+            Terminal::Ext(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The threshold and keys of a `multi` fragment
+    pub fn as_multi(&self) -> Option<(usize, &[Pk])> {
+        match *self {
+            Terminal::Multi(k, ref keys) => Some((k, keys)),
+            _ => None,
+        }
+    }
+
+    /// The threshold and keys of a `multi_a` fragment
+    pub fn as_multi_a(&self) -> Option<(usize, &[Pk])> {
+        match *self {
+            Terminal::MultiA(k, ref keys) => Some((k, keys)),
+            _ => None,
+        }
+    }
 }
 
 impl<Pk, Q, Ctx, Ext> TranslatePk<Pk, Q> for Terminal<Pk, Ctx, Ext>
@@ -98,6 +158,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             | Terminal::Hash256(..)
             | Terminal::Ripemd160(..)
             | Terminal::Hash160(..)
+            | Terminal::RawHashLock(..)
             | Terminal::True
             | Terminal::False => true,
             Terminal::Alt(ref sub)
@@ -122,6 +183,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             }
             Terminal::Thresh(_, ref subs) => subs.iter().all(|sub| sub.real_for_each_key(pred)),
             Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => keys.iter().all(pred),
+            // Extensions are not generic over `Pk`, so they hold no such keys
             Terminal::Ext(ref _e) => true,
         }
     }
@@ -145,6 +207,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             Terminal::Hash256(ref x) => Terminal::Hash256(t.hash256(x)?),
             Terminal::Ripemd160(ref x) => Terminal::Ripemd160(t.ripemd160(x)?),
             Terminal::Hash160(ref x) => Terminal::Hash160(t.hash160(x)?),
+            Terminal::RawHashLock(ref h, size_guard) => {
+                Terminal::RawHashLock(h.translate(t)?, size_guard)
+            }
             Terminal::True => Terminal::True,
             Terminal::False => Terminal::False,
             Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.real_translate_pk(t)?)),
@@ -226,6 +291,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             Terminal::Hash256(ref x) => Terminal::Hash256(x.clone()),
             Terminal::Ripemd160(ref x) => Terminal::Ripemd160(x.clone()),
             Terminal::Hash160(ref x) => Terminal::Hash160(x.clone()),
+            Terminal::RawHashLock(ref h, size_guard) => {
+                Terminal::RawHashLock(h.clone(), size_guard)
+            }
             Terminal::True => Terminal::True,
             Terminal::False => Terminal::False,
             Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.real_translate_ext(t)?)),
@@ -279,6 +347,126 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
         };
         Ok(frag)
     }
+
+    pub(super) fn real_map_ext<CtxQ, ExtQ, F>(
+        &self,
+        f: &mut F,
+    ) -> Result<Terminal<Pk, CtxQ, ExtQ>, Error>
+    where
+        CtxQ: ScriptContext,
+        ExtQ: Extension,
+        F: FnMut(Ext) -> ExtQ,
+    {
+        let frag: Terminal<Pk, CtxQ, ExtQ> = match *self {
+            Terminal::PkK(ref p) => Terminal::PkK(p.clone()),
+            Terminal::PkH(ref p) => Terminal::PkH(p.clone()),
+            Terminal::RawPkH(ref h) => Terminal::RawPkH(h.clone()),
+            Terminal::After(n) => Terminal::After(n),
+            Terminal::Older(n) => Terminal::Older(n),
+            Terminal::Sha256(ref x) => Terminal::Sha256(x.clone()),
+            Terminal::Hash256(ref x) => Terminal::Hash256(x.clone()),
+            Terminal::Ripemd160(ref x) => Terminal::Ripemd160(x.clone()),
+            Terminal::Hash160(ref x) => Terminal::Hash160(x.clone()),
+            Terminal::RawHashLock(ref h, size_guard) => {
+                Terminal::RawHashLock(h.clone(), size_guard)
+            }
+            Terminal::True => Terminal::True,
+            Terminal::False => Terminal::False,
+            Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::Swap(ref sub) => Terminal::Swap(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::Check(ref sub) => Terminal::Check(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::DupIf(ref sub) => Terminal::DupIf(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::Verify(ref sub) => Terminal::Verify(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::NonZero(ref sub) => Terminal::NonZero(Arc::new(sub.real_map_ext(f)?)),
+            Terminal::ZeroNotEqual(ref sub) => {
+                Terminal::ZeroNotEqual(Arc::new(sub.real_map_ext(f)?))
+            }
+            Terminal::AndV(ref left, ref right) => Terminal::AndV(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::AndB(ref left, ref right) => Terminal::AndB(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::AndOr(ref a, ref b, ref c) => Terminal::AndOr(
+                Arc::new(a.real_map_ext(f)?),
+                Arc::new(b.real_map_ext(f)?),
+                Arc::new(c.real_map_ext(f)?),
+            ),
+            Terminal::OrB(ref left, ref right) => Terminal::OrB(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::OrD(ref left, ref right) => Terminal::OrD(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::OrC(ref left, ref right) => Terminal::OrC(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::OrI(ref left, ref right) => Terminal::OrI(
+                Arc::new(left.real_map_ext(f)?),
+                Arc::new(right.real_map_ext(f)?),
+            ),
+            Terminal::Thresh(k, ref subs) => {
+                let subs: Result<Vec<Arc<Miniscript<Pk, _, _>>>, _> = subs
+                    .iter()
+                    .map(|s| s.real_map_ext(f).map(Arc::new))
+                    .collect();
+                Terminal::Thresh(k, subs?)
+            }
+            Terminal::Multi(k, ref keys) => Terminal::Multi(k, keys.clone()),
+            Terminal::MultiA(k, ref keys) => Terminal::MultiA(k, keys.clone()),
+            Terminal::Ext(ref e) => Terminal::Ext(f(e.clone())),
+        };
+        Ok(frag)
+    }
+
+    /// Rebuild the fragment with every direct child replaced by `f(child)`.
+    /// Fragments without children are cloned as is.
+    pub(crate) fn map_children<E, F>(&self, f: &mut F) -> Result<Self, E>
+    where
+        F: FnMut(&Arc<Miniscript<Pk, Ctx, Ext>>) -> Result<Arc<Miniscript<Pk, Ctx, Ext>>, E>,
+    {
+        let frag = match *self {
+            Terminal::Alt(ref sub) => Terminal::Alt(f(sub)?),
+            Terminal::Swap(ref sub) => Terminal::Swap(f(sub)?),
+            Terminal::Check(ref sub) => Terminal::Check(f(sub)?),
+            Terminal::DupIf(ref sub) => Terminal::DupIf(f(sub)?),
+            Terminal::Verify(ref sub) => Terminal::Verify(f(sub)?),
+            Terminal::NonZero(ref sub) => Terminal::NonZero(f(sub)?),
+            Terminal::ZeroNotEqual(ref sub) => Terminal::ZeroNotEqual(f(sub)?),
+            Terminal::AndV(ref left, ref right) => Terminal::AndV(f(left)?, f(right)?),
+            Terminal::AndB(ref left, ref right) => Terminal::AndB(f(left)?, f(right)?),
+            Terminal::AndOr(ref a, ref b, ref c) => Terminal::AndOr(f(a)?, f(b)?, f(c)?),
+            Terminal::OrB(ref left, ref right) => Terminal::OrB(f(left)?, f(right)?),
+            Terminal::OrD(ref left, ref right) => Terminal::OrD(f(left)?, f(right)?),
+            Terminal::OrC(ref left, ref right) => Terminal::OrC(f(left)?, f(right)?),
+            Terminal::OrI(ref left, ref right) => Terminal::OrI(f(left)?, f(right)?),
+            Terminal::Thresh(k, ref subs) => {
+                let subs: Result<Vec<_>, _> = subs.iter().map(&mut *f).collect();
+                Terminal::Thresh(k, subs?)
+            }
+            Terminal::PkK(..)
+            | Terminal::PkH(..)
+            | Terminal::RawPkH(..)
+            | Terminal::After(..)
+            | Terminal::Older(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..)
+            | Terminal::RawHashLock(..)
+            | Terminal::True
+            | Terminal::False
+            | Terminal::Multi(..)
+            | Terminal::MultiA(..)
+            | Terminal::Ext(..) => self.clone(),
+        };
+        Ok(frag)
+    }
 }
 
 impl<Pk, Ctx, Ext> ForEachKey<Pk> for Terminal<Pk, Ctx, Ext>
@@ -356,6 +544,7 @@ where
                 Terminal::Hash256(ref h) => write!(f, "hash256({})", h),
                 Terminal::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
                 Terminal::Hash160(ref h) => write!(f, "hash160({})", h),
+                Terminal::RawHashLock(ref h, size_guard) => fmt_raw_hashlock(f, h, size_guard),
                 Terminal::True => f.write_str("1"),
                 Terminal::False => f.write_str("0"),
                 Terminal::Ext(ref e) => write!(f, "{:?}", e),
@@ -416,6 +605,7 @@ where
             Terminal::Hash256(ref h) => write!(f, "hash256({})", h),
             Terminal::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
             Terminal::Hash160(ref h) => write!(f, "hash160({})", h),
+            Terminal::RawHashLock(ref h, size_guard) => fmt_raw_hashlock(f, h, size_guard),
             Terminal::True => f.write_str("1"),
             Terminal::False => f.write_str("0"),
             Terminal::Ext(ref e) => write!(f, "{}", e),
@@ -504,6 +694,52 @@ where
     }
 }
 
+/// Writes a raw hashlock as `expr_raw_<hash>(<h>)`, or as
+/// `expr_raw_<hash>(<h>,<n>)` if it has a `SIZE <n> EQUALVERIFY` guard
+fn fmt_raw_hashlock<Pk: MiniscriptKey>(
+    f: &mut fmt::Formatter<'_>,
+    hash: &HashKind<Pk>,
+    size_guard: Option<u32>,
+) -> fmt::Result {
+    match *hash {
+        HashKind::Sha256(ref h) => write!(f, "expr_raw_sha256({}", h)?,
+        HashKind::Hash256(ref h) => write!(f, "expr_raw_hash256({}", h)?,
+        HashKind::Ripemd160(ref h) => write!(f, "expr_raw_ripemd160({}", h)?,
+        HashKind::Hash160(ref h) => write!(f, "expr_raw_hash160({}", h)?,
+    }
+    if let Some(n) = size_guard {
+        write!(f, ",{}", n)?;
+    }
+    f.write_str(")")
+}
+
+/// Builds a raw hashlock from its hash and the optional second argument of
+/// `expr_raw_<hash>`, the size checked by its preimage size guard. A guard
+/// of 32 is the canonical hashlock and is rejected.
+fn raw_hashlock<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+    top: &expression::Tree<'_>,
+    hash: HashKind<Pk>,
+) -> Result<Terminal<Pk, Ctx, Ext>, Error> {
+    let size_guard = match top.args.get(1) {
+        Some(arg) => Some(expression::terminal(arg, expression::parse_num::<u32>)?),
+        None => None,
+    };
+    if size_guard == Some(32) {
+        return Err(errstr("raw hashlock with the canonical size guard"));
+    }
+    Ok(Terminal::RawHashLock(hash, size_guard))
+}
+
+/// Parses the value of an `after` or `older` fragment, which must fit in the
+/// 32-bit `nLockTime` or `nSequence` field it is compared to
+fn parse_locktime(term: &expression::Tree<'_>) -> Result<u32, Error> {
+    if !term.args.is_empty() {
+        return Err(errstr(term.name));
+    }
+    let n = expression::parse_num::<i64>(term.name)?;
+    u32::try_from(n).map_err(|_| Error::LocktimeOutOfRange(n))
+}
+
 impl_from_tree!(
     ;Ctx; ScriptContext,
     Arc<Terminal<Pk, Ctx, Ext>>,
@@ -570,12 +806,10 @@ impl_from_tree!(
                 expression::terminal(&top.args[0], |x| Pk::from_str(x).map(Terminal::PkK))
             }
             ("pk_h", 1) => expression::terminal(&top.args[0], |x| Pk::from_str(x).map(Terminal::PkH)),
-            ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num::<u32>(x).map(|x| Terminal::After(LockTime::from_consensus(x).into()))
-            }),
-            ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num::<u32>(x).map(|x| Terminal::Older(Sequence::from_consensus(x)))
-            }),
+            ("after", 1) => parse_locktime(&top.args[0])
+                .map(|x| Terminal::After(LockTime::from_consensus(x).into())),
+            ("older", 1) => parse_locktime(&top.args[0])
+                .map(|x| Terminal::Older(Sequence::from_consensus(x))),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
                 Pk::Sha256::from_str(x).map(Terminal::Sha256)
             }),
@@ -588,6 +822,22 @@ impl_from_tree!(
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
                 Pk::Hash160::from_str(x).map(Terminal::Hash160)
             }),
+            ("expr_raw_sha256", 1..=2) => expression::terminal(&top.args[0], |x| {
+                Pk::Sha256::from_str(x).map(HashKind::Sha256)
+            })
+            .and_then(|h| raw_hashlock(top, h)),
+            ("expr_raw_hash256", 1..=2) => expression::terminal(&top.args[0], |x| {
+                Pk::Hash256::from_str(x).map(HashKind::Hash256)
+            })
+            .and_then(|h| raw_hashlock(top, h)),
+            ("expr_raw_ripemd160", 1..=2) => expression::terminal(&top.args[0], |x| {
+                Pk::Ripemd160::from_str(x).map(HashKind::Ripemd160)
+            })
+            .and_then(|h| raw_hashlock(top, h)),
+            ("expr_raw_hash160", 1..=2) => expression::terminal(&top.args[0], |x| {
+                Pk::Hash160::from_str(x).map(HashKind::Hash160)
+            })
+            .and_then(|h| raw_hashlock(top, h)),
             ("1", 0) => Ok(Terminal::True),
             ("0", 0) => Ok(Terminal::False),
             ("and_v", 2) => expression::binary(top, Terminal::AndV),
@@ -633,6 +883,10 @@ impl_from_tree!(
                 if k > n - 1 {
                     return Err(errstr("higher threshold than there were keys in multi"));
                 }
+                // Same bound as the script decoder
+                if frag_name == "multi_a" && n - 1 > MAX_PUBKEYS_IN_CHECKSIGADD {
+                    return Err(Error::MultiATooManyKeys(MAX_PUBKEYS_IN_CHECKSIGADD as u32));
+                }
 
                 let pks: Result<Vec<Pk>, _> = top.args[1..]
                     .iter()
@@ -727,14 +981,14 @@ pub trait StackCtxOperations: Sized {
 
     /// Since, there is a policy restriction that initial pushes must be
     /// only 80 bytes, we need user to provide suffix in separate items
-    /// There can be atmost 7 cats, because the script element must be less
-    /// than 520 bytes total in order to compute an hash256 on it.
-    /// Even if the witness does not require 7 pushes, the user should push
-    /// 7 elements with possibly empty values.
+    /// `num_cats` CATs join the `num_cats + 1` suffix items, which must be
+    /// less than 520 bytes total in order to compute an hash256 on it.
+    /// Even if the witness does not require all pushes, the user should push
+    /// `num_cats + 1` elements with possibly empty values.
     ///
     /// Copies the script item at position and compare the hash256
     /// with it
-    fn check_item_pref(self, idx: u32, pref: &[u8]) -> Self;
+    fn check_item_pref(self, idx: u32, pref: &[u8], num_cats: usize) -> Self;
 }
 
 impl StackCtxOperations for script::Builder {
@@ -747,12 +1001,11 @@ impl StackCtxOperations for script::Builder {
             .push_opcode(opcodes::all::OP_EQUAL)
     }
 
-    fn check_item_pref(self, idx: u32, pref: &[u8]) -> Self {
+    fn check_item_pref(self, idx: u32, pref: &[u8], num_cats: usize) -> Self {
         let mut builder = self;
         // Initial Witness
-        // The nuumber of maximum witness elements in the suffix
-        let max_elems = MAX_SCRIPT_ELEMENT_SIZE / MAX_STANDARD_P2WSH_STACK_ITEM_SIZE + 1;
-        for _ in 0..(max_elems - 1) {
+        // Join the suffix witness elements
+        for _ in 0..num_cats {
             builder = builder.push_opcode(opcodes::all::OP_CAT);
         }
         builder = builder
@@ -771,7 +1024,139 @@ impl StackCtxOperations for script::Builder {
     }
 }
 
+/// An element of the script a fragment is encoded to, as returned by
+/// [`Terminal::opcode_template`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScriptElement {
+    /// An opcode
+    Op(opcodes::All),
+    /// A number push, such as a threshold or a timelock
+    Num(i64),
+    /// A push of the `n`th key of the fragment
+    Key(usize),
+    /// A push of the hash of the fragment's key, in `pk_h` and `expr_raw_pkh`
+    KeyHash,
+    /// A push of the hash of a hashlock
+    Hash,
+    /// The script of the `n`th child of the fragment
+    Child(usize),
+    /// The script of an extension, which the template does not describe
+    Ext,
+}
+
 impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ext> {
+    /// The opcodes and pushes the fragment is encoded to, in script order,
+    /// with the scripts of its children left as [`ScriptElement::Child`].
+    ///
+    /// This describes the same encoding as [`Terminal::encode`], except that
+    /// the `OP_VERIFY` of a `v:` wrapper is merged by the encoder into a
+    /// preceding `OP_EQUAL`, `OP_NUMEQUAL`, `OP_CHECKSIG` or
+    /// `OP_CHECKMULTISIG` of the child.
+    pub fn opcode_template(&self) -> Vec<ScriptElement> {
+        use elements::opcodes::all::*;
+
+        use self::ScriptElement::{Child, Hash, Key, KeyHash, Num, Op};
+
+        match *self {
+            Terminal::PkK(..) => vec![Key(0)],
+            Terminal::PkH(..) | Terminal::RawPkH(..) => {
+                vec![Op(OP_DUP), Op(OP_HASH160), KeyHash, Op(OP_EQUALVERIFY)]
+            }
+            Terminal::After(t) => vec![Num(t.to_u32().into()), Op(OP_CLTV)],
+            Terminal::Older(t) => vec![Num(t.to_consensus_u32().into()), Op(OP_CSV)],
+            Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..) => {
+                let op = match *self {
+                    Terminal::Sha256(..) => OP_SHA256,
+                    Terminal::Hash256(..) => OP_HASH256,
+                    Terminal::Ripemd160(..) => OP_RIPEMD160,
+                    _ => OP_HASH160,
+                };
+                vec![
+                    Op(OP_SIZE),
+                    Num(32),
+                    Op(OP_EQUALVERIFY),
+                    Op(op),
+                    Hash,
+                    Op(OP_EQUAL),
+                ]
+            }
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                let mut ret = match size_guard {
+                    Some(n) => vec![Op(OP_SIZE), Num(n.into()), Op(OP_EQUALVERIFY)],
+                    None => vec![],
+                };
+                let op = match *hash {
+                    HashKind::Sha256(..) => OP_SHA256,
+                    HashKind::Hash256(..) => OP_HASH256,
+                    HashKind::Ripemd160(..) => OP_RIPEMD160,
+                    HashKind::Hash160(..) => OP_HASH160,
+                };
+                ret.extend_from_slice(&[Op(op), Hash, Op(OP_EQUAL)]);
+                ret
+            }
+            Terminal::True => vec![Op(opcodes::OP_TRUE)],
+            Terminal::False => vec![Op(opcodes::OP_FALSE)],
+            Terminal::Alt(..) => vec![Op(OP_TOALTSTACK), Child(0), Op(OP_FROMALTSTACK)],
+            Terminal::Swap(..) => vec![Op(OP_SWAP), Child(0)],
+            Terminal::Check(..) => vec![Child(0), Op(OP_CHECKSIG)],
+            Terminal::DupIf(..) => vec![Op(OP_DUP), Op(OP_IF), Child(0), Op(OP_ENDIF)],
+            Terminal::Verify(..) => vec![Child(0), Op(OP_VERIFY)],
+            Terminal::NonZero(..) => vec![
+                Op(OP_SIZE),
+                Op(OP_0NOTEQUAL),
+                Op(OP_IF),
+                Child(0),
+                Op(OP_ENDIF),
+            ],
+            Terminal::ZeroNotEqual(..) => vec![Child(0), Op(OP_0NOTEQUAL)],
+            Terminal::AndV(..) => vec![Child(0), Child(1)],
+            Terminal::AndB(..) => vec![Child(0), Child(1), Op(OP_BOOLAND)],
+            Terminal::AndOr(..) => vec![
+                Child(0),
+                Op(OP_NOTIF),
+                Child(2),
+                Op(OP_ELSE),
+                Child(1),
+                Op(OP_ENDIF),
+            ],
+            Terminal::OrB(..) => vec![Child(0), Child(1), Op(OP_BOOLOR)],
+            Terminal::OrD(..) => vec![Child(0), Op(OP_IFDUP), Op(OP_NOTIF), Child(1), Op(OP_ENDIF)],
+            Terminal::OrC(..) => vec![Child(0), Op(OP_NOTIF), Child(1), Op(OP_ENDIF)],
+            Terminal::OrI(..) => vec![Op(OP_IF), Child(0), Op(OP_ELSE), Child(1), Op(OP_ENDIF)],
+            Terminal::Thresh(k, ref subs) => {
+                let mut ret = vec![Child(0)];
+                for i in 1..subs.len() {
+                    ret.push(Child(i));
+                    ret.push(Op(OP_ADD));
+                }
+                ret.push(Num(k as i64));
+                ret.push(Op(OP_EQUAL));
+                ret
+            }
+            Terminal::Multi(k, ref keys) => {
+                let mut ret = vec![Num(k as i64)];
+                ret.extend((0..keys.len()).map(Key));
+                ret.push(Num(keys.len() as i64));
+                ret.push(Op(OP_CHECKMULTISIG));
+                ret
+            }
+            Terminal::MultiA(k, ref keys) => {
+                let mut ret = vec![Key(0), Op(OP_CHECKSIG)];
+                for i in 1..keys.len() {
+                    ret.push(Key(i));
+                    ret.push(Op(OP_CHECKSIGADD));
+                }
+                ret.push(Num(k as i64));
+                ret.push(Op(OP_NUMEQUAL));
+                ret
+            }
+            Terminal::Ext(..) => vec![ScriptElement::Ext],
+        }
+    }
+
     /// Encode the element as a fragment of Bitcoin Script. The inverse
     /// function, from Script to an AST element, is implemented in the
     /// `parse` module.
@@ -826,6 +1211,29 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
                 .push_opcode(opcodes::all::OP_HASH160)
                 .push_slice(&Pk::to_hash160(h))
                 .push_opcode(opcodes::all::OP_EQUAL),
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                if let Some(n) = size_guard {
+                    builder = builder
+                        .push_opcode(opcodes::all::OP_SIZE)
+                        .push_int(n.into())
+                        .push_opcode(opcodes::all::OP_EQUALVERIFY);
+                }
+                let builder = match *hash {
+                    HashKind::Sha256(ref h) => builder
+                        .push_opcode(opcodes::all::OP_SHA256)
+                        .push_slice(&Pk::to_sha256(h)),
+                    HashKind::Hash256(ref h) => builder
+                        .push_opcode(opcodes::all::OP_HASH256)
+                        .push_slice(&Pk::to_hash256(h)),
+                    HashKind::Ripemd160(ref h) => builder
+                        .push_opcode(opcodes::all::OP_RIPEMD160)
+                        .push_slice(&Pk::to_ripemd160(h)),
+                    HashKind::Hash160(ref h) => builder
+                        .push_opcode(opcodes::all::OP_HASH160)
+                        .push_slice(&Pk::to_hash160(h)),
+                };
+                builder.push_opcode(opcodes::all::OP_EQUAL)
+            }
             Terminal::True => builder.push_opcode(opcodes::OP_TRUE),
             Terminal::False => builder.push_opcode(opcodes::OP_FALSE),
             Terminal::Alt(ref sub) => builder
@@ -937,6 +1345,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ex
             Terminal::Hash256(..) => 33 + 6,
             Terminal::Ripemd160(..) => 21 + 6,
             Terminal::Hash160(..) => 21 + 6,
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                let guard_size = size_guard.map_or(0, |n| script_num_size(n as usize) + 2);
+                guard_size + hash.hash_len() + 3
+            }
             Terminal::True => 1,
             Terminal::False => 1,
             Terminal::Alt(ref sub) => sub.node.script_size() + 2,
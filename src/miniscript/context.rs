@@ -1,7 +1,7 @@
 // Written in 2019 by Sanket Kanjalkar and Andrew Poelstra
 // SPDX-License-Identifier: CC0-1.0
 
-use std::{error, fmt, hash};
+use std::{error, fmt, hash, mem, str};
 
 use bitcoin;
 use bitcoin::blockdata::constants::MAX_BLOCK_WEIGHT;
@@ -16,7 +16,7 @@ use crate::miniscript::limits::{
 use crate::miniscript::types;
 use crate::util::witness_to_scriptsig;
 pub use crate::SigType;
-use crate::{hash256, Error, Extension, Miniscript, MiniscriptKey, Terminal};
+use crate::{hash256, Error, Extension, Miniscript, MiniscriptKey, Terminal, ToPublicKey};
 
 /// Error for Script Context
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -70,6 +70,42 @@ pub enum ScriptContextError {
     ExtensionError(String),
 }
 
+impl ScriptContextError {
+    /// A short, stable code identifying the kind of error, e.g. for use in
+    /// APIs where the [`fmt::Display`] message is not suitable. Codes do not
+    /// change between releases, even if the messages do.
+    pub fn code(&self) -> &'static str {
+        use self::ScriptContextError::*;
+
+        match *self {
+            MalleablePkH => "MALLEABLE_PKH",
+            MalleableOrI => "MALLEABLE_OR_I",
+            MalleableDupIf => "MALLEABLE_DUP_IF",
+            CompressedOnly(_) => "COMPRESSED_ONLY",
+            XOnlyKeysNotAllowed(_, _) => "XONLY_NOT_ALLOWED",
+            UncompressedKeysNotAllowed => "UNCOMPRESSED_NOT_ALLOWED",
+            MaxWitnessItemssExceeded { .. } => "MAX_WITNESS_ITEMS",
+            MaxOpCountExceeded => "MAX_OPS",
+            MaxWitnessScriptSizeExceeded => "MAX_WITNESS_SCRIPT_SIZE",
+            MaxRedeemScriptSizeExceeded => "MAX_REDEEM_SCRIPT_SIZE",
+            MaxScriptSigSizeExceeded => "MAX_SCRIPT_SIG_SIZE",
+            ImpossibleSatisfaction => "IMPOSSIBLE_SATISFACTION",
+            CovElementSizeExceeded => "COV_ELEMENT_SIZE",
+            TaprootMultiDisabled => "TAPROOT_MULTI_DISABLED",
+            StackSizeLimitExceeded { .. } => "MAX_STACK_SIZE",
+            CheckMultiSigLimitExceeded => "MAX_MULTISIG_KEYS",
+            MultiANotAllowed => "MULTI_A_NOT_ALLOWED",
+            ExtensionError(_) => "EXTENSION_ERROR",
+        }
+    }
+
+    /// Whether both errors are the same variant, ignoring any keys, limits
+    /// or messages they carry
+    pub fn same_kind(&self, other: &Self) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
+}
+
 impl error::Error for ScriptContextError {
     fn cause(&self) -> Option<&dyn error::Error> {
         use self::ScriptContextError::*;
@@ -183,6 +219,60 @@ impl fmt::Display for ScriptContextError {
     }
 }
 
+/// The kind of a [`ScriptContext`], for code that needs to branch on the
+/// context of a generic miniscript without comparing strings
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[non_exhaustive]
+pub enum ContextKind {
+    /// [`Legacy`] context
+    Legacy,
+    /// [`Segwitv0`] context
+    Segwitv0,
+    /// [`Tap`] context
+    Tap,
+    /// [`BareCtx`] context
+    Bare,
+    /// [`NoChecks`] context
+    NoChecks,
+}
+
+impl ContextKind {
+    /// The name of the context, as used in error messages
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ContextKind::Legacy => "Legacy/p2sh",
+            ContextKind::Segwitv0 => "Segwitv0",
+            ContextKind::Tap => "TapscriptCtx",
+            ContextKind::Bare => "BareCtx",
+            ContextKind::NoChecks => "NochecksEcdsa",
+        }
+    }
+}
+
+impl fmt::Display for ContextKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl str::FromStr for ContextKind {
+    type Err = Error;
+
+    /// Parses either the name used by [`ContextKind::as_str`] or the
+    /// lowercase name of the context: `legacy`, `segwitv0`, `tap`, `bare` or
+    /// `nochecks`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Legacy/p2sh" | "legacy" => Ok(ContextKind::Legacy),
+            "Segwitv0" | "segwitv0" => Ok(ContextKind::Segwitv0),
+            "TapscriptCtx" | "tap" => Ok(ContextKind::Tap),
+            "BareCtx" | "bare" => Ok(ContextKind::Bare),
+            "NochecksEcdsa" | "nochecks" => Ok(ContextKind::NoChecks),
+            _ => Err(Error::Unexpected(format!("unknown script context {}", s))),
+        }
+    }
+}
+
 /// The ScriptContext for Miniscript. Additional type information associated with
 /// miniscript that is used for carrying out checks that dependent on the
 /// context under which the script is used.
@@ -212,6 +302,20 @@ where
         Pk: MiniscriptKey,
         Ext: Extension;
 
+    /// Checks whether the fragment may appear at all under the ScriptContext,
+    /// regardless of its children and of resource limits. For example,
+    /// `Multi` is disabled in Tap. The decoder calls this on every fragment
+    /// so that errors point at the offending fragment.
+    fn check_terminal_valid<Pk, Ext>(
+        _frag: &Terminal<Pk, Self, Ext>,
+    ) -> Result<(), ScriptContextError>
+    where
+        Pk: MiniscriptKey,
+        Ext: Extension,
+    {
+        Ok(())
+    }
+
     /// Check whether the given satisfaction is valid under the ScriptContext
     /// For example, segwit satisfactions may fail if the witness len is more
     /// 3600 or number of stack elements are more than 100.
@@ -231,6 +335,21 @@ where
     where
         Pk: MiniscriptKey,
         Ext: Extension;
+
+    /// Depending on script context, the size of a dissatisfaction witness may
+    /// slightly differ. `None` if the fragment cannot be dissatisfied.
+    fn max_dissatisfaction_size<Pk, Ext>(ms: &Miniscript<Pk, Self, Ext>) -> Option<usize>
+    where
+        Pk: MiniscriptKey,
+        Ext: Extension;
+
+    /// Depending on script context, the size of the cheapest satisfaction
+    /// witness may slightly differ. `None` if the fragment cannot be satisfied.
+    fn min_satisfaction_size<Pk, Ext>(ms: &Miniscript<Pk, Self, Ext>) -> Option<usize>
+    where
+        Pk: MiniscriptKey,
+        Ext: Extension;
+
     /// Depending on script Context, some of the Terminals might not
     /// be valid under the current consensus rules.
     /// Or some of the script resource limits may have been exceeded.
@@ -284,6 +403,10 @@ where
 
     /// Check the consensus + policy(if not disabled) rules that are not based
     /// satisfaction
+    ///
+    /// Like the checks it is made of, this only looks at `ms` itself and not
+    /// at its children: the parsers call it once on every node as it is
+    /// built, so validating a tree costs one call per node.
     fn check_global_validity<Pk, Ext>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError>
@@ -338,6 +461,25 @@ where
     // even though it does not depend on context, but helps in cleaner code
     fn top_level_checks<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Result<(), Error> {
+        Self::top_level_consensus_checks(ms)?;
+        Self::top_level_policy_checks(ms)
+    }
+
+    /// Top level policy rules that are context specific. For example, in
+    /// Bare context, multisig with more than 3 keys is non-standard.
+    fn top_level_policy_checks<Pk, Ext>(_ms: &Miniscript<Pk, Self, Ext>) -> Result<(), Error>
+    where
+        Pk: MiniscriptKey,
+        Ext: Extension,
+    {
+        Ok(())
+    }
+
+    /// Check the top level rules, skipping the policy rules of
+    /// [`ScriptContext::top_level_policy_checks`]
+    fn top_level_consensus_checks<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), Error> {
         Self::top_level_type_check(ms)?;
         Self::other_top_level_checks(ms)
@@ -354,8 +496,56 @@ where
     /// 34 for Segwitv0, 33 for Tap
     fn pk_len<Pk: MiniscriptKey>(pk: &Pk) -> usize;
 
+    /// Upper bound on the size of a signature pushed onto the stack,
+    /// including the push opcode and sighash suffix. 73 for ECDSA contexts
+    /// and 66 for Tap
+    fn max_sig_size() -> usize {
+        match Self::sig_type() {
+            // OP_72 + <sig(71)+sigHash(1)>
+            SigType::Ecdsa => 73,
+            // OP_65 + <sig(64)+sigHash(1)>
+            SigType::Schnorr => 66,
+        }
+    }
+
+    /// Serialize the public key as it is pushed into the script by key
+    /// fragments, without the push opcode, so that its length is one less
+    /// than [`ScriptContext::pk_len`]. This is the 33 or 65 byte
+    /// serialization for ECDSA contexts and the 32 byte x-only serialization
+    /// for Tap.
+    fn serialize_pk<Pk: ToPublicKey>(pk: &Pk) -> Vec<u8> {
+        match Self::sig_type() {
+            SigType::Ecdsa => pk.to_public_key().to_bytes(),
+            SigType::Schnorr => pk.to_x_only_pubkey().serialize().to_vec(),
+        }
+    }
+
+    /// Get the worst-case len of the public key revealed in the witness
+    /// when satisfying a key-hash fragment. The script only commits to a
+    /// 20-byte hash, so the compressedness of the key is unknown and the
+    /// bound must cover every key the context allows. Includes the
+    /// serialization prefix. Returns 66 for Bare/Legacy, 34 for Segwitv0
+    /// and 33 for Tap
+    fn pkh_witness_key_len() -> usize;
+
+    /// The maximum script size permitted by consensus rules in this context.
+    /// Returns `MAX_SCRIPT_ELEMENT_SIZE` (520) for Legacy, `MAX_SCRIPT_SIZE`
+    /// (10000) for Segwitv0 and Bare, and `MAX_BLOCK_WEIGHT` for Tap
+    fn max_script_size() -> usize;
+
+    /// The maximum number of non-push opcodes executed by a script, checked
+    /// by [`ScriptContext::check_local_consensus_validity`]. Returns
+    /// `Some(MAX_OPS_PER_SCRIPT)` (201) for Bare, Legacy and Segwitv0 and
+    /// `None` for Tap, as tapscript has no opcode limit
+    fn op_count_limit() -> Option<usize>;
+
+    /// The kind of this context
+    fn context_kind() -> ContextKind;
+
     /// Local helper function to display error messages with context
-    fn name_str() -> &'static str;
+    fn name_str() -> &'static str {
+        Self::context_kind().as_str()
+    }
 }
 
 /// Legacy ScriptContext
@@ -394,7 +584,7 @@ impl ScriptContext for Legacy {
     fn check_global_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        if ms.ext.pk_cost > MAX_SCRIPT_ELEMENT_SIZE {
+        if ms.ext.pk_cost > Self::max_script_size() {
             return Err(ScriptContextError::MaxRedeemScriptSizeExceeded);
         }
 
@@ -423,10 +613,8 @@ impl ScriptContext for Legacy {
             }
             _ => {}
         }
-        if let Terminal::Ext(ref _e) = ms.node {
-            return Err(ScriptContextError::ExtensionError(String::from(
-                "No Extensions in Legacy context",
-            )));
+        if let Terminal::Ext(ref e) = ms.node {
+            e.legacy_ctx_checks()?;
         }
         Ok(())
     }
@@ -434,13 +622,7 @@ impl ScriptContext for Legacy {
     fn check_local_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        match ms.ext.ops.op_count() {
-            None => Err(ScriptContextError::MaxOpCountExceeded),
-            Some(op_count) if op_count > MAX_OPS_PER_SCRIPT => {
-                Err(ScriptContextError::MaxOpCountExceeded)
-            }
-            _ => Ok(()),
-        }
+        check_op_count(ms)
     }
 
     fn check_local_policy_validity<Pk: MiniscriptKey, Ext: Extension>(
@@ -465,6 +647,20 @@ impl ScriptContext for Legacy {
         ms.ext.max_sat_size.map(|x| x.1)
     }
 
+    fn max_dissatisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The scriptSig cost is the second element of the tuple
+        ms.ext.max_dissat_size.map(|x| x.1)
+    }
+
+    fn min_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The scriptSig cost is the second element of the tuple
+        ms.min_sat_dissat_size().0.map(|x| x.1)
+    }
+
     fn pk_len<Pk: MiniscriptKey>(pk: &Pk) -> usize {
         if pk.is_uncompressed() {
             66
@@ -473,8 +669,20 @@ impl ScriptContext for Legacy {
         }
     }
 
-    fn name_str() -> &'static str {
-        "Legacy/p2sh"
+    fn pkh_witness_key_len() -> usize {
+        66
+    }
+
+    fn max_script_size() -> usize {
+        MAX_SCRIPT_ELEMENT_SIZE
+    }
+
+    fn op_count_limit() -> Option<usize> {
+        Some(MAX_OPS_PER_SCRIPT)
+    }
+
+    fn context_kind() -> ContextKind {
+        ContextKind::Legacy
     }
 
     fn sig_type() -> SigType {
@@ -510,7 +718,7 @@ impl ScriptContext for Segwitv0 {
     fn check_global_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        if ms.ext.pk_cost > MAX_SCRIPT_SIZE {
+        if ms.ext.pk_cost > Self::max_script_size() {
             return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
         }
 
@@ -554,13 +762,7 @@ impl ScriptContext for Segwitv0 {
     fn check_local_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        match ms.ext.ops.op_count() {
-            None => Err(ScriptContextError::MaxOpCountExceeded),
-            Some(op_count) if op_count > MAX_OPS_PER_SCRIPT => {
-                Err(ScriptContextError::MaxOpCountExceeded)
-            }
-            _ => Ok(()),
-        }
+        check_op_count(ms)
     }
 
     fn check_global_policy_validity<Pk: MiniscriptKey, Ext: Extension>(
@@ -598,12 +800,38 @@ impl ScriptContext for Segwitv0 {
         ms.ext.max_sat_size.map(|x| x.0)
     }
 
+    fn max_dissatisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The witness stack cost is the first element of the tuple
+        ms.ext.max_dissat_size.map(|x| x.0)
+    }
+
+    fn min_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The witness stack cost is the first element of the tuple
+        ms.min_sat_dissat_size().0.map(|x| x.0)
+    }
+
     fn pk_len<Pk: MiniscriptKey>(_pk: &Pk) -> usize {
         34
     }
 
-    fn name_str() -> &'static str {
-        "Segwitv0"
+    fn pkh_witness_key_len() -> usize {
+        34
+    }
+
+    fn max_script_size() -> usize {
+        MAX_SCRIPT_SIZE
+    }
+
+    fn op_count_limit() -> Option<usize> {
+        Some(MAX_OPS_PER_SCRIPT)
+    }
+
+    fn context_kind() -> ContextKind {
+        ContextKind::Segwitv0
     }
 
     fn sig_type() -> SigType {
@@ -625,6 +853,16 @@ impl ScriptContext for Tap {
         Ok(())
     }
 
+    fn check_terminal_valid<Pk: MiniscriptKey, Ext: Extension>(
+        frag: &Terminal<Pk, Self, Ext>,
+    ) -> Result<(), ScriptContextError> {
+        match *frag {
+            Terminal::Multi(..) => Err(ScriptContextError::TaprootMultiDisabled),
+            Terminal::Ext(ref e) => e.tap_ctx_checks(),
+            _ => Ok(()),
+        }
+    }
+
     fn check_witness<Pk: MiniscriptKey, Ext: Extension>(
         witness: &[Vec<u8>],
     ) -> Result<(), ScriptContextError> {
@@ -646,7 +884,7 @@ impl ScriptContext for Tap {
         // When the transaction sizes get close to block limits,
         // some guarantees are not easy to satisfy because of knapsack
         // constraints
-        if ms.ext.pk_cost > MAX_BLOCK_WEIGHT as usize {
+        if ms.ext.pk_cost > Self::max_script_size() {
             return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
         }
 
@@ -657,9 +895,11 @@ impl ScriptContext for Tap {
                 }
                 Ok(())
             }
-            Terminal::Multi(..) => Err(ScriptContextError::TaprootMultiDisabled),
-            Terminal::Ext(ref e) => {
-                e.tap_ctx_checks()?;
+            Terminal::Multi(..) | Terminal::Ext(..) => Self::check_terminal_valid(&ms.node),
+            Terminal::MultiA(_, ref pks) => {
+                if pks.iter().any(|pk| pk.is_uncompressed()) {
+                    return Err(ScriptContextError::UncompressedKeysNotAllowed);
+                }
                 Ok(())
             }
             _ => Ok(()),
@@ -700,9 +940,17 @@ impl ScriptContext for Tap {
     }
 
     fn check_local_policy_validity<Pk: MiniscriptKey, Ext: Extension>(
-        _ms: &Miniscript<Pk, Self, Ext>,
+        ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        Ok(())
+        // The stack counts are only `None` when there is no satisfaction, so
+        // the consensus stack check had nothing to bound
+        match (
+            ms.ext.exec_stack_elem_count_sat,
+            ms.ext.stack_elem_count_sat,
+        ) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(ScriptContextError::ImpossibleSatisfaction),
+        }
     }
 
     fn max_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
@@ -712,6 +960,31 @@ impl ScriptContext for Tap {
         ms.ext.max_sat_size.map(|x| x.0)
     }
 
+    fn max_dissatisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The witness stack cost is the first element of the tuple
+        ms.ext.max_dissat_size.map(|x| x.0)
+    }
+
+    fn min_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The witness stack cost is the first element of the tuple
+        ms.min_sat_dissat_size().0.map(|x| x.0)
+    }
+
+    fn other_top_level_checks<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Result<(), Error> {
+        // A leaf that anyone can spend makes the internal key and every
+        // other leaf pointless
+        match ms.node {
+            Terminal::True => Err(Error::TrivialTapLeaf),
+            _ => Ok(()),
+        }
+    }
+
     fn sig_type() -> SigType {
         SigType::Schnorr
     }
@@ -720,8 +993,21 @@ impl ScriptContext for Tap {
         33
     }
 
-    fn name_str() -> &'static str {
-        "TapscriptCtx"
+    fn pkh_witness_key_len() -> usize {
+        33
+    }
+
+    fn max_script_size() -> usize {
+        MAX_BLOCK_WEIGHT as usize
+    }
+
+    fn op_count_limit() -> Option<usize> {
+        // No opcode limit in tapscript
+        None
+    }
+
+    fn context_kind() -> ContextKind {
+        ContextKind::Tap
     }
 }
 
@@ -747,14 +1033,12 @@ impl ScriptContext for BareCtx {
     fn check_global_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        if ms.ext.pk_cost > MAX_SCRIPT_SIZE {
+        if ms.ext.pk_cost > Self::max_script_size() {
             return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
         }
 
-        if let Terminal::Ext(ref _e) = ms.node {
-            return Err(ScriptContextError::ExtensionError(String::from(
-                "No Extensions in Bare context",
-            )));
+        if let Terminal::Ext(ref e) = ms.node {
+            e.bare_ctx_checks()?;
         }
         match ms.node {
             Terminal::PkK(ref key) if key.is_x_only_key() => {
@@ -785,13 +1069,7 @@ impl ScriptContext for BareCtx {
     fn check_local_consensus_validity<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Result<(), ScriptContextError> {
-        match ms.ext.ops.op_count() {
-            None => Err(ScriptContextError::MaxOpCountExceeded),
-            Some(op_count) if op_count > MAX_OPS_PER_SCRIPT => {
-                Err(ScriptContextError::MaxOpCountExceeded)
-            }
-            _ => Ok(()),
-        }
+        check_op_count(ms)
     }
 
     fn other_top_level_checks<Pk: MiniscriptKey, Ext: Extension>(
@@ -803,11 +1081,20 @@ impl ScriptContext for BareCtx {
                 Terminal::PkK(_pk) | Terminal::PkH(_pk) => Ok(()),
                 _ => Err(Error::NonStandardBareScript),
             },
-            Terminal::Multi(_k, subs) if subs.len() <= 3 => Ok(()),
+            Terminal::Multi(..) => Ok(()),
             _ => Err(Error::NonStandardBareScript),
         }
     }
 
+    fn top_level_policy_checks<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Result<(), Error> {
+        match ms.node {
+            Terminal::Multi(_k, ref subs) if subs.len() > 3 => Err(Error::NonStandardBareScript),
+            _ => Ok(()),
+        }
+    }
+
     fn max_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
         ms: &Miniscript<Pk, Self, Ext>,
     ) -> Option<usize> {
@@ -815,6 +1102,20 @@ impl ScriptContext for BareCtx {
         ms.ext.max_sat_size.map(|x| x.1)
     }
 
+    fn max_dissatisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The scriptSig cost is the second element of the tuple
+        ms.ext.max_dissat_size.map(|x| x.1)
+    }
+
+    fn min_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        // The scriptSig cost is the second element of the tuple
+        ms.min_sat_dissat_size().0.map(|x| x.1)
+    }
+
     fn pk_len<Pk: MiniscriptKey>(pk: &Pk) -> usize {
         if pk.is_uncompressed() {
             66
@@ -823,8 +1124,20 @@ impl ScriptContext for BareCtx {
         }
     }
 
-    fn name_str() -> &'static str {
-        "BareCtx"
+    fn pkh_witness_key_len() -> usize {
+        66
+    }
+
+    fn max_script_size() -> usize {
+        MAX_SCRIPT_SIZE
+    }
+
+    fn op_count_limit() -> Option<usize> {
+        Some(MAX_OPS_PER_SCRIPT)
+    }
+
+    fn context_kind() -> ContextKind {
+        ContextKind::Bare
     }
 
     fn sig_type() -> SigType {
@@ -877,13 +1190,39 @@ impl ScriptContext for NoChecks {
         panic!("Tried to compute a satisfaction size bound on a no-checks ecdsa miniscript")
     }
 
+    fn max_dissatisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        _ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        panic!("Tried to compute a dissatisfaction size bound on a no-checks ecdsa miniscript")
+    }
+
+    fn min_satisfaction_size<Pk: MiniscriptKey, Ext: Extension>(
+        _ms: &Miniscript<Pk, Self, Ext>,
+    ) -> Option<usize> {
+        panic!("Tried to compute a satisfaction size bound on a no-checks ecdsa miniscript")
+    }
+
     fn pk_len<Pk: MiniscriptKey>(_pk: &Pk) -> usize {
         panic!("Tried to compute a pk len bound on a no-checks ecdsa miniscript")
     }
 
-    fn name_str() -> &'static str {
-        // Internally used code
-        "NochecksEcdsa"
+    fn pkh_witness_key_len() -> usize {
+        // Type checking still runs for no-checks scripts; use the
+        // compressed ecdsa key length like the pre-existing estimate
+        34
+    }
+
+    fn max_script_size() -> usize {
+        // No size checks are done on scripts read off the chain
+        usize::MAX
+    }
+
+    fn op_count_limit() -> Option<usize> {
+        None
+    }
+
+    fn context_kind() -> ContextKind {
+        ContextKind::NoChecks
     }
 
     fn check_witness<Pk: MiniscriptKey, Ext: Extension>(
@@ -940,6 +1279,23 @@ impl ScriptContext for NoChecks {
     }
 }
 
+// Check the number of opcodes executed by a satisfaction of `ms` against
+// the limit of its context
+fn check_op_count<Pk, Ctx, Ext>(ms: &Miniscript<Pk, Ctx, Ext>) -> Result<(), ScriptContextError>
+where
+    Pk: MiniscriptKey,
+    Ctx: ScriptContext,
+    Ext: Extension,
+{
+    match (ms.ext.ops.op_count(), Ctx::op_count_limit()) {
+        (None, _) => Err(ScriptContextError::MaxOpCountExceeded),
+        (Some(op_count), Some(limit)) if op_count > limit => {
+            Err(ScriptContextError::MaxOpCountExceeded)
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Private Mod to prevent downstream from implementing this public trait
 mod private {
     use super::{BareCtx, Legacy, NoChecks, Segwitv0, Tap};
@@ -6,6 +6,7 @@
 //! Functionality to parse a Bitcoin Script into a `Miniscript`
 //!
 
+use std::convert::TryFrom;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::{error, fmt};
@@ -15,14 +16,20 @@ use elements::hashes::{hash160, ripemd160, sha256, Hash};
 use crate::elements::{LockTime, PackedLockTime, Sequence};
 use crate::extensions::ParseableExt;
 use crate::miniscript::lex::{Token as Tk, TokenIter};
-use crate::miniscript::limits::{MAX_BLOCK_WEIGHT, MAX_PUBKEYS_PER_MULTISIG};
+use crate::miniscript::limits::{MAX_PUBKEYS_IN_CHECKSIGADD, MAX_PUBKEYS_PER_MULTISIG};
 use crate::miniscript::types::extra_props::ExtData;
 use crate::miniscript::types::{Property, Type};
 use crate::miniscript::ScriptContext;
 #[cfg(doc)]
 use crate::Descriptor;
-use crate::{bitcoin, hash256, Error, Extension, Miniscript, MiniscriptKey, NoExt, ToPublicKey};
+use crate::{
+    bitcoin, hash256, Error, Extension, HashKind, Miniscript, MiniscriptKey, NoExt, ToPublicKey,
+};
 
+// `Type` and `ExtData` compute the type of a fragment directly from the `ty`
+// and `ext` fields already cached in its children, so the child lookup passed
+// to `type_check` while reducing is never used and each reduction only does
+// work proportional to the number of direct children.
 fn return_none<T>(_: usize) -> Option<T> {
     None
 }
@@ -135,9 +142,9 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension = NoExt>
     /// over raw descriptors.
     RawPkH(hash160::Hash),
     // timelocks
-    /// `n CHECKLOCKTIMEVERIFY`
+    /// `n CHECKLOCKTIMEVERIFY`, with `n` bounded by the 32-bit `nLockTime`
     After(PackedLockTime),
-    /// `n CHECKSEQUENCEVERIFY`
+    /// `n CHECKSEQUENCEVERIFY`, with `n` bounded by the 32-bit `nSequence`
     Older(Sequence),
     // hashlocks
     /// `SIZE 32 EQUALVERIFY SHA256 <hash> EQUAL`
@@ -148,6 +155,12 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension = NoExt>
     Ripemd160(Pk::Ripemd160),
     /// `SIZE 32 EQUALVERIFY HASH160 <hash> EQUAL`
     Hash160(Pk::Hash160),
+    /// Only for parsing hashlocks whose preimage size guard is missing (`None`)
+    /// or checks a size other than 32 (`Some(n)` for `SIZE <n> EQUALVERIFY`).
+    /// These are not valid miniscript. We only use this variant internally for
+    /// inferring miniscripts from raw Scripts, so that the inferred miniscript
+    /// still encodes the script it was parsed from.
+    RawHashLock(HashKind<Pk>, Option<u32>),
     // Wrappers
     /// `TOALTSTACK [E] FROMALTSTACK`
     Alt(Arc<Miniscript<Pk, Ctx, Ext>>),
@@ -157,7 +170,9 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension = NoExt>
     Check(Arc<Miniscript<Pk, Ctx, Ext>>),
     /// `DUP IF [V] ENDIF`
     DupIf(Arc<Miniscript<Pk, Ctx, Ext>>),
-    /// `[T] VERIFY`
+    /// `[T] VERIFY`, where the `VERIFY` is merged into a final `EQUAL`,
+    /// `NUMEQUAL`, `CHECKSIG` or `CHECKMULTISIG` of `T`. `T` must be of
+    /// type B, so `Verify` fragments never nest.
     Verify(Arc<Miniscript<Pk, Ctx, Ext>>),
     /// `SIZE 0NOTEQUAL IF [Fn] ENDIF`
     NonZero(Arc<Miniscript<Pk, Ctx, Ext>>),
@@ -196,50 +211,169 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension = NoExt>
 
 ///Vec representing terminals stack while decoding.
 #[derive(Debug)]
-struct TerminalStack<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
-    Vec<Miniscript<Pk, Ctx, Ext>>,
-);
+struct TerminalStack<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> {
+    terms: Vec<Miniscript<Pk, Ctx, Ext>>,
+    // Whether each terminal, or any of its descendants, failed to type check
+    poisoned: Vec<bool>,
+    // Whether any terminal popped since the last push was poisoned
+    popped_poisoned: bool,
+    // Type check errors collected so far, if errors are collected rather
+    // than returned
+    errors: Option<Vec<Error>>,
+    // Position, from the end of the script, of the token the parser was at
+    // when the current step started
+    position: usize,
+    // While a snapshot is taken, the terminals that were on the stack when
+    // it was taken and have been popped since, the last popped at the end
+    undo: Option<Vec<(Miniscript<Pk, Ctx, Ext>, bool)>>,
+    // While a snapshot is taken, the number of terminals that were on the
+    // stack when it was taken and still are
+    n_kept: usize,
+}
+
+///Saved state of a `TerminalStack`. The popped terminals are kept by the
+///stack itself
+struct TerminalStackSnapshot {
+    popped_poisoned: bool,
+    n_errors: usize,
+}
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> TerminalStack<Pk, Ctx, Ext> {
-    ///Wrapper around self.0.pop()
+    ///Create an empty stack, which collects type check errors if `collect`
+    ///is set
+    fn new(capacity: usize, collect: bool) -> Self {
+        TerminalStack {
+            terms: Vec::with_capacity(capacity),
+            poisoned: Vec::with_capacity(capacity),
+            popped_poisoned: false,
+            errors: if collect { Some(vec![]) } else { None },
+            position: 0,
+            undo: None,
+            n_kept: 0,
+        }
+    }
+
+    ///Save the stack, so that a step can be undone with `restore`. Only
+    ///the terminals popped afterwards are copied, as the ones pushed can be
+    ///dropped and the others are left untouched
+    fn snapshot(&mut self) -> TerminalStackSnapshot {
+        self.undo = Some(vec![]);
+        self.n_kept = self.terms.len();
+        TerminalStackSnapshot {
+            popped_poisoned: self.popped_poisoned,
+            n_errors: self.errors.as_ref().map_or(0, Vec::len),
+        }
+    }
+
+    ///Return the stack to a state saved by the last `snapshot`
+    fn restore(&mut self, snapshot: TerminalStackSnapshot) {
+        self.terms.truncate(self.n_kept);
+        self.poisoned.truncate(self.n_kept);
+        for (ms, poisoned) in self.undo.take().unwrap_or_default().into_iter().rev() {
+            self.terms.push(ms);
+            self.poisoned.push(poisoned);
+        }
+        self.popped_poisoned = snapshot.popped_poisoned;
+        if let Some(ref mut errors) = self.errors {
+            // Errors are only ever pushed, so dropping the new ones is enough
+            errors.truncate(snapshot.n_errors);
+        }
+    }
+
+    ///Stop keeping what is needed to restore the last `snapshot`
+    fn forget_snapshot(&mut self) {
+        self.undo = None;
+    }
+
+    ///Wrapper around self.terms.pop()
     fn pop(&mut self) -> Option<Miniscript<Pk, Ctx, Ext>> {
-        self.0.pop()
+        let ms = self.terms.pop()?;
+        let poisoned = self.poisoned.pop().expect("same length as terms");
+        self.popped_poisoned |= poisoned;
+        if let Some(ref mut undo) = self.undo {
+            // Terminals pushed since the snapshot are dropped on restore
+            if self.terms.len() < self.n_kept {
+                undo.push((ms.clone(), poisoned));
+                self.n_kept = self.terms.len();
+            }
+        }
+        Some(ms)
     }
 
-    ///reduce, type check and push a 0-arg node
-    fn reduce0(&mut self, ms: Terminal<Pk, Ctx, Ext>) -> Result<(), Error> {
-        let ty = Type::type_check(&ms, return_none)?;
-        let ext = ExtData::type_check(&ms, return_none)?;
-        let ms = Miniscript {
-            node: ms,
-            ty,
-            ext,
-            phantom: PhantomData,
+    ///Pop a terminal, returning an error instead of panicking if the stack
+    ///is empty
+    fn pop_or_err(&mut self) -> Result<Miniscript<Pk, Ctx, Ext>, Error> {
+        self.pop()
+            .ok_or_else(|| Error::Unexpected("empty terminal stack while parsing".to_owned()))
+    }
+
+    ///Type check and push a node built from the terminals popped since the
+    ///last push
+    fn push_checked(&mut self, node: Terminal<Pk, Ctx, Ext>) -> Result<(), Error> {
+        let children_poisoned = std::mem::replace(&mut self.popped_poisoned, false);
+        let position = self.position;
+        let types = Ctx::check_terminal_valid(&node)
+            .map_err(|e| Error::FragmentContextError(e, position))
+            .and_then(|()| Type::type_check(&node, return_none).map_err(Error::from))
+            .and_then(|ty| Ok((ty, ExtData::type_check(&node, return_none)?)));
+        let (ms, err) = match types {
+            Ok((ty, ext)) => {
+                let ms = Miniscript {
+                    node,
+                    ty,
+                    ext,
+                    phantom: PhantomData,
+                };
+                let err = Ctx::check_global_validity(&ms).err().map(Error::from);
+                (ms, err)
+            }
+            Err(e) => {
+                // Keep the node with a placeholder type so parsing can go on.
+                // The type is never looked at, as the parse fails in the end
+                let ms = Miniscript {
+                    ty: Type::type_check(&Terminal::<Pk, Ctx, Ext>::True, return_none)
+                        .expect("1 is valid"),
+                    ext: ExtData::type_check(&Terminal::<Pk, Ctx, Ext>::True, return_none)
+                        .expect("1 is valid"),
+                    node,
+                    phantom: PhantomData,
+                };
+                (ms, Some(e))
+            }
         };
-        Ctx::check_global_validity(&ms)?;
-        self.0.push(ms);
+        match err {
+            None => {
+                self.terms.push(ms);
+                self.poisoned.push(children_poisoned);
+            }
+            Some(e) => match self.errors {
+                None => return Err(e),
+                Some(ref mut errors) => {
+                    // Errors of a node with an invalid child are usually
+                    // caused by the child, so only report the innermost one
+                    if !children_poisoned {
+                        errors.push(e);
+                    }
+                    self.terms.push(ms);
+                    self.poisoned.push(true);
+                }
+            },
+        }
         Ok(())
     }
 
+    ///reduce, type check and push a 0-arg node
+    fn reduce0(&mut self, ms: Terminal<Pk, Ctx, Ext>) -> Result<(), Error> {
+        self.push_checked(ms)
+    }
+
     ///reduce, type check and push a 1-arg node
     fn reduce1<F>(&mut self, wrap: F) -> Result<(), Error>
     where
         F: FnOnce(Arc<Miniscript<Pk, Ctx, Ext>>) -> Terminal<Pk, Ctx, Ext>,
     {
-        let top = self.pop().unwrap();
-        let wrapped_ms = wrap(Arc::new(top));
-
-        let ty = Type::type_check(&wrapped_ms, return_none)?;
-        let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-        let ms = Miniscript {
-            node: wrapped_ms,
-            ty,
-            ext,
-            phantom: PhantomData,
-        };
-        Ctx::check_global_validity(&ms)?;
-        self.0.push(ms);
-        Ok(())
+        let top = self.pop_or_err()?;
+        self.push_checked(wrap(Arc::new(top)))
     }
 
     ///reduce, type check and push a 2-arg node
@@ -250,44 +384,203 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> TerminalStack<Pk, Ct
             Arc<Miniscript<Pk, Ctx, Ext>>,
         ) -> Terminal<Pk, Ctx, Ext>,
     {
-        let left = self.pop().unwrap();
-        let right = self.pop().unwrap();
-
-        let wrapped_ms = wrap(Arc::new(left), Arc::new(right));
-
-        let ty = Type::type_check(&wrapped_ms, return_none)?;
-        let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-        let ms = Miniscript {
-            node: wrapped_ms,
-            ty,
-            ext,
-            phantom: PhantomData,
-        };
-        Ctx::check_global_validity(&ms)?;
-        self.0.push(ms);
-        Ok(())
+        let left = self.pop_or_err()?;
+        let right = self.pop_or_err()?;
+
+        self.push_checked(wrap(Arc::new(left), Arc::new(right)))
     }
 }
 
 /// Parse a script fragment into an `Miniscript`
-#[allow(unreachable_patterns)]
+///
+/// The decoder allocates in proportion to the number of tokens. Scripts
+/// from untrusted sources should be lexed with
+/// [`lex_with_max_size`](super::lex::lex_with_max_size).
+///
+/// This is unchecked: no [`crate::ExtParams`] are applied, so hashlocks
+/// without a canonical `SIZE 32 EQUALVERIFY` guard are returned as
+/// [`Terminal::RawHashLock`]. [`Miniscript::parse_with_ext`] rejects them
+/// unless [`crate::ExtParams::raw_hashlock`] is set.
 pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
     tokens: &mut TokenIter<'_>,
 ) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
-    let mut non_term = Vec::with_capacity(tokens.len());
-    let mut term = TerminalStack(Vec::with_capacity(tokens.len()));
+    Parser::with_stack(TerminalStack::new(tokens.len(), false)).finish(tokens)
+}
+
+/// Check that every `TOALTSTACK` of a script is paired with a later
+/// `FROMALTSTACK`, as in the `a:` wrapper, tracking the altstack depth from
+/// the start of the script. `tokens` are in script order.
+pub(crate) fn check_alt_stack(tokens: &[Tk<'_>]) -> Result<(), Error> {
+    let mut depth = 0usize;
+    for tk in tokens {
+        match *tk {
+            Tk::ToAltStack => depth += 1,
+            Tk::FromAltStack => {
+                depth = depth.checked_sub(1).ok_or(Error::UnbalancedAltStack)?;
+            }
+            _ => {}
+        }
+    }
+    if depth == 0 {
+        Ok(())
+    } else {
+        Err(Error::UnbalancedAltStack)
+    }
+}
+
+/// Parse a script fragment into an `Miniscript`, reporting all the type
+/// check errors instead of only the first one.
+///
+/// Fragments which fail to type check are kept in the parse tree as
+/// placeholders so parsing can continue past them. Errors of fragments that
+/// contain an invalid fragment are not reported, as they are usually caused
+/// by it. Errors that stop the parse, such as unexpected tokens, are reported
+/// last.
+///
+/// As with [`parse`], non-canonical hashlocks are returned as
+/// [`Terminal::RawHashLock`] rather than reported as errors.
+pub fn parse_collect_errors<Ctx: ScriptContext, Ext: ParseableExt>(
+    tokens: &mut TokenIter<'_>,
+) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Vec<Error>> {
+    let mut parser = Parser::with_stack(TerminalStack::new(tokens.len(), true));
+    let res = parser.run(tokens);
+    let mut errors = parser.term.errors.take().unwrap_or_default();
+    match res {
+        Ok(ms) if errors.is_empty() => Ok(ms),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
+}
+
+/// Parser state kept between chunks of tokens: the non-terminals still to
+/// be matched and the terminals reduced so far.
+///
+/// Scripts are parsed backward, so chunks of tokens are fed from the end of
+/// the script towards its start, each chunk being the tokens that precede
+/// the previous one. [`Parser::feed`] parses as far as it can without
+/// reaching the first token, leaving the tokens it could not use yet in the
+/// iterator, and is then given the next chunk along with the same iterator.
+/// Once the whole script has been fed, [`Parser::finish`] parses what is
+/// left. Feeding every token and then finishing gives the same result as
+/// [`parse`], including its [`Terminal::RawHashLock`] fragments for
+/// non-canonical hashlocks.
+pub struct Parser<Ctx: ScriptContext, Ext: ParseableExt> {
+    non_term: Vec<NonTerm>,
+    term: TerminalStack<Ctx::Key, Ctx, Ext>,
+}
 
-    // top level cannot be swap, must be B
-    non_term.push(NonTerm::MaybeAndV);
-    non_term.push(NonTerm::Expression);
-    loop {
+impl<Ctx: ScriptContext, Ext: ParseableExt> Default for Parser<Ctx, Ext> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Ctx: ScriptContext, Ext: ParseableExt> Parser<Ctx, Ext> {
+    /// Create a parser for a single top-level fragment
+    pub fn new() -> Self {
+        Self::with_stack(TerminalStack::new(0, false))
+    }
+
+    fn with_stack(term: TerminalStack<Ctx::Key, Ctx, Ext>) -> Self {
+        // top level cannot be swap, must be B
+        Parser {
+            non_term: vec![NonTerm::MaybeAndV, NonTerm::Expression],
+            term,
+        }
+    }
+
+    /// Add `chunk`, the tokens preceding those in `tokens`, to the start of
+    /// `tokens`, then parse tokens from the end of `tokens` for as long as
+    /// some are left.
+    ///
+    /// A step which would consume or look ahead to the first token, or
+    /// which needs more tokens than are left, is rolled back, as the tokens
+    /// preceding it may change how it parses, and is retried by the next
+    /// `feed` or by `finish`. Errors which do not depend on the tokens not
+    /// fed yet are returned right away. Extensions which parse from
+    /// [`TokenIter::remaining`] are assumed to look at every token, so their
+    /// steps are always left to `finish`.
+    pub fn feed<'s>(
+        &mut self,
+        tokens: &mut TokenIter<'s>,
+        chunk: Vec<Tk<'s>>,
+    ) -> Result<(), Error> {
+        tokens.prepend(chunk);
+        let res = self.feed_steps(tokens);
+        self.term.forget_snapshot();
+        res
+    }
+
+    fn feed_steps(&mut self, tokens: &mut TokenIter<'_>) -> Result<(), Error> {
+        while !tokens.is_empty() {
+            let checkpoint = tokens.checkpoint();
+            // A step pops at most the top non-terminal
+            let n_non_term = self.non_term.len();
+            let top = self.non_term.last().copied();
+            let term = self.term.snapshot();
+            tokens.reset_lookahead();
+            let res = self.step(tokens);
+            // A step which read the first token, or wanted more tokens than
+            // there were, may parse differently once preceding tokens are fed
+            let complete = !tokens.lookahead_reached_start();
+            match res {
+                Ok(true) if complete => {}
+                Ok(false) => return Ok(()),
+                Err(e) if complete => return Err(e),
+                _ => {
+                    tokens.restore(checkpoint);
+                    if let Some(top) = top {
+                        self.non_term.truncate(n_non_term - 1);
+                        self.non_term.push(top);
+                    }
+                    self.term.restore(term);
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the remaining tokens as the start of the script, and return
+    /// the fragment. Tokens preceding the fragment are left in `tokens`, as
+    /// with [`parse`].
+    pub fn finish(
+        mut self,
+        tokens: &mut TokenIter<'_>,
+    ) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
+        self.run(tokens)
+    }
+
+    fn run(&mut self, tokens: &mut TokenIter<'_>) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
+        while self.step(tokens)? {}
+
+        if !self.non_term.is_empty() || self.term.terms.len() != 1 {
+            return Err(Error::Unexpected(format!(
+                "unbalanced parse: {} non-terminals and {} terminals left",
+                self.non_term.len(),
+                self.term.terms.len(),
+            )));
+        }
+        self.term.pop_or_err()
+    }
+
+    /// Match or reduce the top non-terminal, returning whether there is
+    /// anything left to parse
+    #[allow(unreachable_patterns)]
+    fn step(&mut self, tokens: &mut TokenIter<'_>) -> Result<bool, Error> {
+        let non_term = &mut self.non_term;
+        let term = &mut self.term;
+        term.position = tokens.position();
         // Parse extensions as expressions
         if let Some(NonTerm::Expression) = non_term.last() {
             if let Ok(ext) = Ext::from_token_iter(tokens) {
                 // Since we successfully parsed the expression, pop it
                 non_term.pop();
                 term.reduce0(Terminal::Ext(ext))?;
-                continue;
+                return Ok(true);
             }
         }
         match non_term.pop() {
@@ -333,40 +626,50 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                             tokens,
                             Tk::Hash20(hash) => match_token!(
                                 tokens,
-                                Tk::Hash160 => match_token!(
-                                    tokens,
-                                    Tk::Dup => {
-                                        term.reduce0(Terminal::RawPkH(
-                                            hash160::Hash::from_slice(hash).expect("valid size")
-                                        ))?
-                                    },
-                                    Tk::Verify, Tk::Equal, Tk::Num(32), Tk::Size => {
+                                Tk::Hash160 => {
+                                    let hash = hash160::Hash::from_slice(hash).expect("valid size");
+                                    if tokens.peek() == Some(&Tk::Dup) {
+                                        tokens.next();
+                                        term.reduce0(Terminal::RawPkH(hash))?
+                                    } else {
                                         non_term.push(NonTerm::Verify);
-                                        term.reduce0(Terminal::Hash160(
-                                            hash160::Hash::from_slice(hash).expect("valid size")
+                                        term.reduce0(hashlock(
+                                            tokens,
+                                            Terminal::Hash160(hash),
+                                            HashKind::Hash160(hash),
                                         ))?
-                                    },
-                                ),
-                                Tk::Ripemd160, Tk::Verify, Tk::Equal, Tk::Num(32), Tk::Size => {
+                                    }
+                                },
+                                Tk::Ripemd160 => {
+                                    let hash = ripemd160::Hash::from_slice(hash)
+                                        .expect("valid size");
                                     non_term.push(NonTerm::Verify);
-                                    term.reduce0(Terminal::Ripemd160(
-                                        ripemd160::Hash::from_slice(hash).expect("valid size")
+                                    term.reduce0(hashlock(
+                                        tokens,
+                                        Terminal::Ripemd160(hash),
+                                        HashKind::Ripemd160(hash),
                                     ))?
                                 },
                             ),
                             // Tk::Hash20(hash),
                             Tk::Bytes32(hash) => match_token!(
                                 tokens,
-                                Tk::Sha256, Tk::Verify, Tk::Equal, Tk::Num(32), Tk::Size => {
+                                Tk::Sha256 => {
+                                    let hash = sha256::Hash::from_slice(hash).expect("valid size");
                                     non_term.push(NonTerm::Verify);
-                                    term.reduce0(Terminal::Sha256(
-                                        sha256::Hash::from_slice(hash).expect("valid size")
+                                    term.reduce0(hashlock(
+                                        tokens,
+                                        Terminal::Sha256(hash),
+                                        HashKind::Sha256(hash),
                                     ))?
                                 },
-                                Tk::Hash256, Tk::Verify, Tk::Equal, Tk::Num(32), Tk::Size => {
+                                Tk::Hash256 => {
+                                    let hash = hash256::Hash::from_slice(hash).expect("valid size");
                                     non_term.push(NonTerm::Verify);
-                                    term.reduce0(Terminal::Hash256(
-                                        hash256::Hash::from_slice(hash).expect("valid size")
+                                    term.reduce0(hashlock(
+                                        tokens,
+                                        Terminal::Hash256(hash),
+                                        HashKind::Hash256(hash),
                                     ))?
                                 },
                             ),
@@ -395,46 +698,64 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                         non_term.push(NonTerm::Expression);
                     },
                     // timelocks
-                    Tk::CheckSequenceVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::Older(Sequence::from_consensus(n)))?,
-                    Tk::CheckLockTimeVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::After(LockTime::from_consensus(n).into()))?,
+                    Tk::CheckSequenceVerify => match_token!(
+                        tokens,
+                        Tk::Num(n)
+                            => term.reduce0(Terminal::Older(Sequence::from_consensus(n)))?,
+                        Tk::Push(bytes) => {
+                            let n = locktime_from_push(&bytes)?;
+                            term.reduce0(Terminal::Older(Sequence::from_consensus(n)))?
+                        },
+                    ),
+                    Tk::CheckLockTimeVerify => match_token!(
+                        tokens,
+                        Tk::Num(n)
+                            => term.reduce0(Terminal::After(LockTime::from_consensus(n).into()))?,
+                        Tk::Push(bytes) => {
+                            let n = locktime_from_push(&bytes)?;
+                            term.reduce0(Terminal::After(LockTime::from_consensus(n).into()))?
+                        },
+                    ),
                     // hashlocks
                     Tk::Equal => match_token!(
                         tokens,
                         Tk::Bytes32(hash) => match_token!(
                             tokens,
-                            Tk::Sha256,
-                            Tk::Verify,
-                            Tk::Equal,
-                            Tk::Num(32),
-                            Tk::Size => term.reduce0(Terminal::Sha256(
-                                sha256::Hash::from_slice(hash).expect("valid size")
-                            ))?,
-                            Tk::Hash256,
-                            Tk::Verify,
-                            Tk::Equal,
-                            Tk::Num(32),
-                            Tk::Size => term.reduce0(Terminal::Hash256(
-                                hash256::Hash::from_slice(hash).expect("valid size")
-                            ))?,
+                            Tk::Sha256 => {
+                                let hash = sha256::Hash::from_slice(hash).expect("valid size");
+                                term.reduce0(hashlock(
+                                    tokens,
+                                    Terminal::Sha256(hash),
+                                    HashKind::Sha256(hash),
+                                ))?
+                            },
+                            Tk::Hash256 => {
+                                let hash = hash256::Hash::from_slice(hash).expect("valid size");
+                                term.reduce0(hashlock(
+                                    tokens,
+                                    Terminal::Hash256(hash),
+                                    HashKind::Hash256(hash),
+                                ))?
+                            },
                         ),
                         Tk::Hash20(hash) => match_token!(
                             tokens,
-                            Tk::Ripemd160,
-                            Tk::Verify,
-                            Tk::Equal,
-                            Tk::Num(32),
-                            Tk::Size => term.reduce0(Terminal::Ripemd160(
-                                ripemd160::Hash::from_slice(hash).expect("valid size")
-                            ))?,
-                            Tk::Hash160,
-                            Tk::Verify,
-                            Tk::Equal,
-                            Tk::Num(32),
-                            Tk::Size => term.reduce0(Terminal::Hash160(
-                                hash160::Hash::from_slice(hash).expect("valid size")
-                            ))?,
+                            Tk::Ripemd160 => {
+                                let hash = ripemd160::Hash::from_slice(hash).expect("valid size");
+                                term.reduce0(hashlock(
+                                    tokens,
+                                    Terminal::Ripemd160(hash),
+                                    HashKind::Ripemd160(hash),
+                                ))?
+                            },
+                            Tk::Hash160 => {
+                                let hash = hash160::Hash::from_slice(hash).expect("valid size");
+                                term.reduce0(hashlock(
+                                    tokens,
+                                    Terminal::Hash160(hash),
+                                    HashKind::Hash160(hash),
+                                ))?
+                            },
                         ),
                         // thresholds
                         Tk::Num(k) => {
@@ -491,11 +812,16 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                     // MultiA
                     Tk::NumEqual, Tk::Num(k) => {
                         // Check size before allocating keys
-                        if k > (MAX_BLOCK_WEIGHT/32) as u32 {
-                            return Err(Error::MultiATooManyKeys((MAX_BLOCK_WEIGHT/32) as u32))
+                        if k > MAX_PUBKEYS_IN_CHECKSIGADD as u32 {
+                            return Err(Error::MultiATooManyKeys(MAX_PUBKEYS_IN_CHECKSIGADD as u32))
                         }
                         let mut keys = Vec::with_capacity(k as usize); // atleast k capacity
                         while tokens.peek() == Some(&Tk::CheckSigAdd) {
+                            if keys.len() >= MAX_PUBKEYS_IN_CHECKSIGADD {
+                                return Err(Error::MultiATooManyKeys(
+                                    MAX_PUBKEYS_IN_CHECKSIGADD as u32,
+                                ))
+                            }
                             match_token!(
                                 tokens,
                                 Tk::CheckSigAdd, Tk::Bytes32(pk) => keys.push(<Ctx::Key>::from_slice(pk)
@@ -508,6 +834,9 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                             Tk::CheckSig, Tk::Bytes32(pk) => keys.push(<Ctx::Key>::from_slice(pk)
                                 .map_err(|e| Error::PubKeyCtxError(e, Ctx::name_str()))?),
                         );
+                        if keys.len() > MAX_PUBKEYS_IN_CHECKSIGADD {
+                            return Err(Error::MultiATooManyKeys(MAX_PUBKEYS_IN_CHECKSIGADD as u32))
+                        }
                         keys.reverse();
                         term.reduce0(Terminal::MultiA(k as usize, keys))?;
                     },
@@ -554,20 +883,10 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
             Some(NonTerm::OrC) => term.reduce2(Terminal::OrC)?,
             Some(NonTerm::OrD) => term.reduce2(Terminal::OrD)?,
             Some(NonTerm::Tern) => {
-                let a = term.pop().unwrap();
-                let b = term.pop().unwrap();
-                let c = term.pop().unwrap();
-                let wrapped_ms = Terminal::AndOr(Arc::new(a), Arc::new(c), Arc::new(b));
-
-                let ty = Type::type_check(&wrapped_ms, return_none)?;
-                let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-
-                term.0.push(Miniscript {
-                    node: wrapped_ms,
-                    ty,
-                    ext,
-                    phantom: PhantomData,
-                });
+                let a = term.pop_or_err()?;
+                let b = term.pop_or_err()?;
+                let c = term.pop_or_err()?;
+                term.push_checked(Terminal::AndOr(Arc::new(a), Arc::new(c), Arc::new(b)))?;
             }
             Some(NonTerm::ThreshW { n, k }) => {
                 match_token!(
@@ -586,7 +905,7 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
             Some(NonTerm::ThreshE { n, k }) => {
                 let mut subs = Vec::with_capacity(n);
                 for _ in 0..n {
-                    subs.push(Arc::new(term.pop().unwrap()));
+                    subs.push(Arc::new(term.pop_or_err()?));
                 }
                 term.reduce0(Terminal::Thresh(k, subs))?;
             }
@@ -601,6 +920,9 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                     Tk::If => match_token!(
                         tokens,
                         Tk::Dup => non_term.push(NonTerm::DupIf),
+                        // `j:X` is `SIZE 0NOTEQUAL IF [X] ENDIF`. Scripts are read
+                        // backward, so the closing ENDIF is always seen first and
+                        // this is the only place the opening needs to be matched.
                         Tk::ZeroNotEqual, Tk::Size
                             => non_term.push(NonTerm::NonZero),
                     ),
@@ -621,16 +943,26 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
                 non_term.push(NonTerm::Expression);
             }
             Some(NonTerm::EndIfElse) => {
-                match_token!(
-                    tokens,
-                    Tk::If => {
+                // Only `or_i` (IF) and `andor` (NOTIF) have an ELSE branch, any
+                // other opening is malformed.
+                match tokens.next() {
+                    Some(Tk::If) => {
                         term.reduce2(Terminal::OrI)?;
-                    },
-                    Tk::NotIf => {
+                    }
+                    Some(Tk::NotIf) => {
                         non_term.push(NonTerm::Tern);
                         non_term.push(NonTerm::Expression);
-                    },
-                );
+                    }
+                    Some(other) => {
+                        return Err(Error::Unexpected(format!(
+                            "{} at token {} from the end of the script, expected IF or NOTIF \
+                             opening an IF ELSE ENDIF",
+                            other,
+                            tokens.position() - 1,
+                        )))
+                    }
+                    None => return Err(Error::UnexpectedStart),
+                }
             }
             Some(NonTerm::WExpression) => {
                 // W expression must be either from swap or Fromaltstack
@@ -642,14 +974,76 @@ pub fn parse<Ctx: ScriptContext, Ext: ParseableExt>(
             }
             None => {
                 // Done :)
-                break;
+                return Ok(false);
             }
         }
+        Ok(true)
     }
+}
+
+/// Parse a single top-level fragment from the end of `tokens`.
+///
+/// Parsing stops as soon as a complete fragment has been read, and the
+/// remaining tokens are left in the returned iterator for the caller to
+/// inspect. Unlike [`Miniscript::parse`], this does not fail on trailing
+/// tokens and does not run any top level or sanity checks. In particular
+/// non-canonical hashlocks are returned as [`Terminal::RawHashLock`], which
+/// the `raw_hashlock` [`crate::ExtParams`] check would reject.
+pub fn parse_one<'a, 's, Ctx: ScriptContext, Ext: ParseableExt>(
+    tokens: &'a mut TokenIter<'s>,
+) -> Result<(Miniscript<Ctx::Key, Ctx, Ext>, &'a mut TokenIter<'s>), Error> {
+    let ms = parse(tokens)?;
+    Ok((ms, tokens))
+}
 
-    assert_eq!(non_term.len(), 0);
-    assert_eq!(term.0.len(), 1);
-    Ok(term.pop().unwrap())
+/// Decodes a timelock pushed as a number which does not fit in a script
+/// number of at most 4 bytes, such as a 5-byte push of a value in
+/// `2^31..2^32`. Timelocks are compared to the 32-bit `nLockTime` and
+/// `nSequence` fields, so any value outside of `0..2^32` is rejected.
+fn locktime_from_push(bytes: &[u8]) -> Result<u32, Error> {
+    // CHECKLOCKTIMEVERIFY and CHECKSEQUENCEVERIFY read at most 5 bytes
+    if bytes.is_empty() || bytes.len() > 5 {
+        return Err(Error::InvalidPush(bytes.to_owned()));
+    }
+    let mut n: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        n |= i64::from(*byte) << (8 * i);
+    }
+    let sign_bit = 0x80 << (8 * (bytes.len() - 1));
+    if n & sign_bit != 0 {
+        n = -(n & !sign_bit);
+    }
+    if elements::script::Builder::new().push_int(n).into_script()[1..] != *bytes {
+        return Err(Error::InvalidPush(bytes.to_owned()));
+    }
+    u32::try_from(n).map_err(|_| Error::LocktimeOutOfRange(n))
+}
+
+/// Consumes the `SIZE <n> EQUALVERIFY` preimage size guard in front of a
+/// hashlock, if any. A 32 byte guard makes the `canonical` fragment, while a
+/// missing or different guard makes a [`Terminal::RawHashLock`], so that the
+/// fragment still encodes the parsed tokens.
+fn hashlock<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+    tokens: &mut TokenIter<'_>,
+    canonical: Terminal<Pk, Ctx, Ext>,
+    hash: HashKind<Pk>,
+) -> Terminal<Pk, Ctx, Ext> {
+    let size_guard = match tokens.peek_slice(4) {
+        Some(guard) if guard[0] == Tk::Size && guard[2] == Tk::Equal && guard[3] == Tk::Verify => {
+            match guard[1] {
+                Tk::Num(n) => Some(n),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    if size_guard.is_some() {
+        tokens.advance(4);
+    }
+    match size_guard {
+        Some(32) => canonical,
+        size_guard => Terminal::RawHashLock(hash, size_guard),
+    }
 }
 
 fn is_and_v(tokens: &mut TokenIter<'_>) -> bool {
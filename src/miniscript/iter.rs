@@ -31,32 +31,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
     /// Enumerates all child nodes of the current AST node (`self`) and returns a `Vec` referencing
     /// them.
     pub fn branches(&self) -> Vec<&Miniscript<Pk, Ctx, Ext>> {
-        match self.node {
-            Terminal::PkK(_) | Terminal::PkH(_) | Terminal::RawPkH(_) | Terminal::Multi(_, _) => {
-                vec![]
-            }
-
-            Terminal::Alt(ref node)
-            | Terminal::Swap(ref node)
-            | Terminal::Check(ref node)
-            | Terminal::DupIf(ref node)
-            | Terminal::Verify(ref node)
-            | Terminal::NonZero(ref node)
-            | Terminal::ZeroNotEqual(ref node) => vec![node],
-
-            Terminal::AndV(ref node1, ref node2)
-            | Terminal::AndB(ref node1, ref node2)
-            | Terminal::OrB(ref node1, ref node2)
-            | Terminal::OrD(ref node1, ref node2)
-            | Terminal::OrC(ref node1, ref node2)
-            | Terminal::OrI(ref node1, ref node2) => vec![node1, node2],
-
-            Terminal::AndOr(ref node1, ref node2, ref node3) => vec![node1, node2, node3],
-
-            Terminal::Thresh(_, ref node_vec) => node_vec.iter().map(Arc::deref).collect(),
-
-            _ => vec![],
-        }
+        self.node.children().into_iter().map(Arc::deref).collect()
     }
 
     /// Returns child node with given index, if any
@@ -106,6 +81,50 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
     }
 }
 
+/// Iterator-related extensions for [Terminal]
+impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Terminal<Pk, Ctx, Ext> {
+    /// Returns the child nodes of this fragment in canonical (left to right)
+    /// order. Leaf fragments, including extensions, have no children.
+    pub fn children(&self) -> Vec<&Arc<Miniscript<Pk, Ctx, Ext>>> {
+        match *self {
+            Terminal::True
+            | Terminal::False
+            | Terminal::PkK(_)
+            | Terminal::PkH(_)
+            | Terminal::RawPkH(_)
+            | Terminal::After(_)
+            | Terminal::Older(_)
+            | Terminal::Sha256(_)
+            | Terminal::Hash256(_)
+            | Terminal::Ripemd160(_)
+            | Terminal::Hash160(_)
+            | Terminal::RawHashLock(..)
+            | Terminal::Multi(_, _)
+            | Terminal::MultiA(_, _)
+            | Terminal::Ext(_) => vec![],
+
+            Terminal::Alt(ref node)
+            | Terminal::Swap(ref node)
+            | Terminal::Check(ref node)
+            | Terminal::DupIf(ref node)
+            | Terminal::Verify(ref node)
+            | Terminal::NonZero(ref node)
+            | Terminal::ZeroNotEqual(ref node) => vec![node],
+
+            Terminal::AndV(ref node1, ref node2)
+            | Terminal::AndB(ref node1, ref node2)
+            | Terminal::OrB(ref node1, ref node2)
+            | Terminal::OrD(ref node1, ref node2)
+            | Terminal::OrC(ref node1, ref node2)
+            | Terminal::OrI(ref node1, ref node2) => vec![node1, node2],
+
+            Terminal::AndOr(ref node1, ref node2, ref node3) => vec![node1, node2, node3],
+
+            Terminal::Thresh(_, ref node_vec) => node_vec.iter().collect(),
+        }
+    }
+}
+
 /// Iterator for traversing all [Miniscript] miniscript AST references starting from some specific
 /// node which constructs the iterator via [Miniscript::iter] method.
 pub struct Iter<'a, Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> {
@@ -369,4 +388,17 @@ pub mod test {
             assert_eq!(ms.iter_pk().collect::<Vec<bitcoin::PublicKey>>(), k);
         })
     }
+
+    #[test]
+    fn children() {
+        gen_testcases().into_iter().for_each(|(ms, _, _, _)| {
+            for node in ms.iter() {
+                let children = node.node.children();
+                for (i, child) in children.iter().enumerate() {
+                    assert_eq!(node.get_nth_child(i), Some(&***child));
+                }
+                assert_eq!(node.get_nth_child(children.len()), None);
+            }
+        })
+    }
 }
@@ -6,7 +6,8 @@
 //! Translates a script into a reversed sequence of tokens
 //!
 
-use std::fmt;
+use std::cell::Cell;
+use std::{cmp, fmt};
 
 use elements::{opcodes, script};
 
@@ -31,7 +32,9 @@ pub enum Token<'s> {
     CheckLockTimeVerify,
     FromAltStack,
     ToAltStack,
+    Substr,
     Left,
+    Right,
     Cat,
     CodeSep,
     Over,
@@ -107,27 +110,93 @@ impl<'s> fmt::Display for Token<'s> {
 
 #[derive(Debug, Clone)]
 /// Iterator that goes through a vector of tokens backward (our parser wants to read
-/// backward and this is more efficient anyway since we can use `Vec::pop()`).
+/// backward and this is more efficient anyway since we only move the end of the vector).
 // This really does not need to be an iterator because the way we are using it, we are
 // actually collecting lexed symbols into a vector. If that is the case, might as well
 // use the inner vector directly
-pub struct TokenIter<'s>(Vec<Token<'s>>);
+pub struct TokenIter<'s> {
+    // Tokens in script order. Consumed tokens are kept after `end`, so that
+    // `restore` only has to move `end` back
+    tokens: Vec<Token<'s>>,
+    // Number of tokens not consumed yet
+    end: usize,
+    // Number of tokens consumed so far
+    pos: usize,
+    // Index of the earliest token looked at or consumed since the last
+    // `reset_lookahead`. Zero when the first token was reached, or when more
+    // tokens were asked for than there are
+    lowest: Cell<usize>,
+}
+
+/// A position in a [`TokenIter`] that can be returned to with
+/// [`TokenIter::restore`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    end: usize,
+    pos: usize,
+}
 
 impl<'s> TokenIter<'s> {
     /// Create a new TokenIter
     pub fn new(v: Vec<Token<'s>>) -> TokenIter<'s> {
-        TokenIter(v)
+        TokenIter {
+            end: v.len(),
+            lowest: Cell::new(v.len()),
+            tokens: v,
+            pos: 0,
+        }
+    }
+
+    // Record that the tokens from `index` onward were looked at
+    fn look_back_to(&self, index: usize) {
+        self.lowest.set(cmp::min(self.lowest.get(), index));
+    }
+
+    /// Start tracking lookahead afresh from the current position, for
+    /// [`TokenIter::lookahead_reached_start`].
+    pub fn reset_lookahead(&self) {
+        self.lowest.set(self.end);
+    }
+
+    /// Whether the first token was looked at or consumed since the last
+    /// [`TokenIter::reset_lookahead`], or more tokens were asked for than
+    /// there were. When it was not, adding tokens before the remaining ones
+    /// cannot change what was read since. [`TokenIter::remaining`] counts as
+    /// looking at every token.
+    pub fn lookahead_reached_start(&self) -> bool {
+        self.lowest.get() == 0
+    }
+
+    /// Mark the current position, so that tokens consumed afterwards can be
+    /// given back with [`TokenIter::restore`]. Lets extension parsers consume
+    /// several tokens speculatively instead of peeking at a fixed pattern.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            end: self.end,
+            pos: self.pos,
+        }
+    }
+
+    /// Return to a position marked by [`TokenIter::checkpoint`], giving back
+    /// every token consumed since. The checkpoint is only meaningful if the
+    /// inner vector was not modified through [`TokenIter::as_inner_mut`] in
+    /// between.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.end = cmp::min(checkpoint.end, self.tokens.len());
+        self.pos = checkpoint.pos;
     }
 
     /// Look at the top at Iterator
     pub fn peek(&self) -> Option<&'s Token<'_>> {
-        self.0.last()
+        self.look_back_to(self.end.saturating_sub(1));
+        self.tokens[..self.end].last()
     }
 
     /// Look at the slice with the last n elements
     pub fn peek_slice(&self, n: usize) -> Option<&[Token<'_>]> {
+        self.look_back_to(self.end.saturating_sub(n));
         if n <= self.len() {
-            Some(self.0[self.len() - n..].as_ref())
+            Some(self.tokens[self.end - n..self.end].as_ref())
         } else {
             None
         }
@@ -148,23 +217,59 @@ impl<'s> TokenIter<'s> {
 
     /// Push a value to the iterator
     /// This will be first value consumed by popun_
+    ///
+    /// The token is assumed to be the last one consumed, and takes its place.
     pub fn un_next(&mut self, tok: Token<'s>) {
-        self.0.push(tok)
+        if self.end < self.tokens.len() {
+            self.tokens[self.end] = tok;
+        } else {
+            self.tokens.push(tok);
+        }
+        self.end += 1;
+        self.pos = self.pos.saturating_sub(1);
+    }
+
+    /// The number of tokens consumed so far. Scripts are parsed backward,
+    /// so this is the index, counted from the end of the script, of the
+    /// next token to be consumed
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The tokens that have not been consumed yet, in script order. The
+    /// last element of the slice is the next token returned by `next`
+    pub fn remaining(&self) -> &[Token<'s>] {
+        self.look_back_to(0);
+        &self.tokens[..self.end]
     }
 
-    /// The len of the iterator
+    /// The number of tokens that have not been consumed yet. Equal to
+    /// `self.remaining().len()`
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.end
     }
 
     /// Check if the iterator is empty
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.end == 0
     }
 
-    /// Get the inner mutable vector
+    /// Add `tokens` before the tokens not consumed yet, in script order, so
+    /// that they are consumed after them. This drops the consumed tokens,
+    /// so earlier checkpoints can no longer be restored.
+    pub fn prepend(&mut self, tokens: Vec<Token<'s>>) {
+        self.tokens.truncate(self.end);
+        self.end += tokens.len();
+        self.lowest.set(self.lowest.get() + tokens.len());
+        self.tokens.splice(0..0, tokens);
+    }
+
+    /// Get the inner mutable vector of the tokens not consumed yet. This
+    /// drops the consumed tokens, so earlier checkpoints can no longer be
+    /// restored.
     pub fn as_inner_mut(&mut self) -> &mut Vec<Token<'s>> {
-        &mut self.0
+        self.tokens.truncate(self.end);
+        &mut self.tokens
     }
 }
 
@@ -172,11 +277,39 @@ impl<'s> Iterator for TokenIter<'s> {
     type Item = Token<'s>;
 
     fn next(&mut self) -> Option<Token<'s>> {
-        self.0.pop()
+        self.end = self.end.checked_sub(1)?;
+        self.look_back_to(self.end);
+        self.pos += 1;
+        Some(self.tokens[self.end].clone())
     }
 }
 
+/// Tokenize a script of at most `max_size` bytes, failing with
+/// [`Error::ScriptSizeTooLarge`] before any token is allocated otherwise.
+///
+/// Scripts from untrusted sources should be lexed with the
+/// [`ScriptContext::max_script_size`](crate::ScriptContext::max_script_size)
+/// of their context: any larger script would fail the context checks once
+/// decoded, so this bounds what lexing and decoding allocate without
+/// rejecting any valid script.
+pub fn lex_with_max_size(
+    script: &script::Script,
+    max_size: usize,
+) -> Result<Vec<Token<'_>>, Error> {
+    if script.len() > max_size {
+        return Err(Error::ScriptSizeTooLarge);
+    }
+    lex(script)
+}
+
 /// Tokenize a script
+///
+/// Decoding is always strict about push encodings: non-minimal pushes fail
+/// with `script::Error::NonMinimalPush` and non-minimally encoded script
+/// numbers with [`Error::InvalidPush`].
+///
+/// The whole script is tokenized up front: the parser reads it backward, and
+/// pushes before an `OP_CAT` are only recognized once the `OP_CAT` is seen.
 pub fn lex(script: &script::Script) -> Result<Vec<Token<'_>>, Error> {
     let mut ret = Vec::with_capacity(script.len());
 
@@ -322,9 +455,15 @@ pub fn lex(script: &script::Script) -> Result<Vec<Token<'_>>, Error> {
             script::Instruction::Op(opcodes::all::OP_TOALTSTACK) => {
                 ret.push(Token::ToAltStack);
             }
+            script::Instruction::Op(opcodes::all::OP_SUBSTR) => {
+                ret.push(Token::Substr);
+            }
             script::Instruction::Op(opcodes::all::OP_LEFT) => {
                 ret.push(Token::Left);
             }
+            script::Instruction::Op(opcodes::all::OP_RIGHT) => {
+                ret.push(Token::Right);
+            }
             script::Instruction::Op(opcodes::all::OP_2DUP) => {
                 ret.push(Token::Dup2);
             }
@@ -13,13 +13,16 @@
 //! components of the AST.
 //!
 
+use std::convert::TryFrom;
 use std::marker::PhantomData;
 use std::{fmt, str};
 
-use elements::script;
+use elements::hashes::hex::{FromHex, ToHex};
+use elements::hashes::{sha256, Hash};
 use elements::taproot::{LeafVersion, TapLeafHash};
+use elements::{script, LockTime, PackedLockTime, Sequence};
 
-use self::analyzable::ExtParams;
+use self::analyzable::{AnalysisError, ExtParams, HashKind};
 pub use self::context::{BareCtx, Legacy, Segwitv0, Tap};
 
 pub mod analyzable;
@@ -35,9 +38,10 @@ pub mod types;
 use std::cmp;
 use std::sync::Arc;
 
-use self::lex::{lex, TokenIter};
+use self::lex::{lex_with_max_size, TokenIter};
 use self::types::Property;
 use crate::extensions::ParseableExt;
+use crate::miniscript::context::NoChecks;
 pub use crate::miniscript::context::ScriptContext;
 use crate::miniscript::decode::Terminal;
 use crate::miniscript::types::extra_props::ExtData;
@@ -172,13 +176,70 @@ where
         script: &script::Script,
         ext: &ExtParams,
     ) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
-        let tokens = lex(script)?;
+        Self::parse_tokens(lex_with_max_size(script, Ctx::max_script_size())?, ext)
+    }
+
+    /// Attempt to parse a script which may contain hashlocks that omit the
+    /// `SIZE <32> EQUALVERIFY` guard, such as `SHA256 <h> EQUAL`.
+    ///
+    /// Such scripts are not valid miniscript; this is only meant to allow
+    /// analysis of legacy scripts. Each unguarded hashlock is parsed as a
+    /// [`Terminal::RawHashLock`] without a size guard, so the returned
+    /// miniscript still encodes `script`. Along with the miniscript, returns
+    /// the hashes of the hashlocks which were missing their size guard.
+    /// Hashlocks with a guard of a size other than 32 are rejected.
+    pub fn parse_with_unguarded_hashlocks(
+        script: &script::Script,
+        ext: &ExtParams,
+    ) -> Result<(Miniscript<Ctx::Key, Ctx, Ext>, Vec<HashKind<Ctx::Key>>), Error> {
+        let ms = Self::parse_with_ext(script, &ext.raw_hashlock())?;
+        let mut unguarded = vec![];
+        for (hash, size_guard) in ms.raw_hashlocks() {
+            if size_guard.is_some() {
+                return Err(Error::AnalysisError(AnalysisError::ContainsRawHashLock));
+            }
+            unguarded.push(hash);
+        }
+        Ok((ms, unguarded))
+    }
+
+    /// Attempt to parse a script which may contain `ripemd160`/`hash160`
+    /// hashlocks guarded by `SIZE <20> EQUALVERIFY` instead of the canonical
+    /// `SIZE <32> EQUALVERIFY`.
+    ///
+    /// Miniscript's guard bounds the size of the preimage, not the hash, so
+    /// such scripts are not valid miniscript and only accept 20 byte
+    /// preimages. Each short guard is parsed as a [`Terminal::RawHashLock`]
+    /// which keeps the guard, so the returned miniscript still encodes
+    /// `script`. Along with the miniscript, returns the hashes of the
+    /// hashlocks which had a short guard. Any other hashlock without the
+    /// canonical guard is rejected.
+    pub fn parse_with_short_hashlock_guards(
+        script: &script::Script,
+        ext: &ExtParams,
+    ) -> Result<(Miniscript<Ctx::Key, Ctx, Ext>, Vec<HashKind<Ctx::Key>>), Error> {
+        let ms = Self::parse_with_ext(script, &ext.raw_hashlock())?;
+        let mut short_guards = vec![];
+        for (hash, size_guard) in ms.raw_hashlocks() {
+            if hash.hash_len() != 20 || size_guard != Some(20) {
+                return Err(Error::AnalysisError(AnalysisError::ContainsRawHashLock));
+            }
+            short_guards.push(hash);
+        }
+        Ok((ms, short_guards))
+    }
+
+    fn parse_tokens(
+        tokens: Vec<lex::Token<'_>>,
+        ext: &ExtParams,
+    ) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
+        decode::check_alt_stack(&tokens)?;
         let mut iter = TokenIter::new(tokens);
 
+        // Every node, including the top one, was type checked and checked
+        // with `Ctx::check_global_validity` when it was reduced
         let top = decode::parse(&mut iter)?;
-        Ctx::check_global_validity(&top)?;
-        let type_check = types::Type::type_check(&top.node, |_| None)?;
-        if type_check.corr.base != types::Base::B {
+        if top.ty.corr.base != types::Base::B {
             return Err(Error::NonTopLevel(format!("{:?}", top)));
         };
         if let Some(leading) = iter.next() {
@@ -228,6 +289,31 @@ where
         ms.sanity_check()?;
         Ok(ms)
     }
+
+    /// Attempt to parse a Script given as hex into Miniscript representation,
+    /// with the same checks as [Miniscript::parse].
+    pub fn from_script_hex(hex: &str) -> Result<Miniscript<Ctx::Key, Ctx, Ext>, Error> {
+        let script = script::Script::from_hex(hex).map_err(|e| Error::Unexpected(e.to_string()))?;
+        Self::parse(&script)
+    }
+}
+
+impl<'s, Ctx, Ext> TryFrom<&'s script::Script> for Miniscript<Ctx::Key, Ctx, Ext>
+where
+    Ctx: ScriptContext,
+    Ext: ParseableExt,
+{
+    type Error = Error;
+
+    /// Parses a script with [`Miniscript::parse`] and applies the top level
+    /// checks of the context. Lexing failures are reported as
+    /// [`Error::Script`], [`Error::InvalidOpcode`] or [`Error::InvalidPush`],
+    /// distinct from the [`Error::TypeCheck`] failures of the parsed fragments.
+    fn try_from(script: &'s script::Script) -> Result<Self, Self::Error> {
+        let ms = Self::parse(script)?;
+        Ctx::top_level_checks(&ms)?;
+        Ok(ms)
+    }
 }
 
 impl<Pk, Ctx, Ext> Miniscript<Pk, Ctx, Ext>
@@ -245,6 +331,28 @@ where
         self.node.encode(script::Builder::new()).into_script()
     }
 
+    /// Encode as a Bitcoin script, returned as hex
+    pub fn to_script_hex(&self) -> String
+    where
+        Pk: ToPublicKey,
+        Ext: ParseableExt,
+    {
+        self.encode().to_hex()
+    }
+
+    /// A stable content fingerprint: the sha256 of the encoded script.
+    ///
+    /// Unlike the derived `Hash`, this only depends on the script bytes, so
+    /// it is identical across runs and for any two miniscripts that encode
+    /// to the same script.
+    pub fn fingerprint(&self) -> [u8; 32]
+    where
+        Pk: ToPublicKey,
+        Ext: ParseableExt,
+    {
+        sha256::Hash::hash(self.encode().as_bytes()).into_inner()
+    }
+
     /// Size, in bytes of the script-pubkey. If this Miniscript is used outside
     /// of segwit (e.g. in a bare or P2SH descriptor), this quantity should be
     /// multiplied by 4 to compute the weight.
@@ -252,6 +360,9 @@ where
     /// In general, it is not recommended to use this function directly, but
     /// to instead call the corresponding function on a `Descriptor`, which
     /// will handle the segwit/non-segwit technicalities for you.
+    ///
+    /// This is the same size the context consensus and policy checks compare
+    /// against the script size limits.
     pub fn script_size(&self) -> usize {
         self.node.script_size()
     }
@@ -287,6 +398,249 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
     pub fn max_satisfaction_size(&self) -> Result<usize, Error> {
         Ctx::max_satisfaction_size::<Pk, Ext>(self).ok_or(Error::ImpossibleSatisfaction)
     }
+
+    /// Maximum size, in bytes, of a dissatisfying witness, i.e. the cost of
+    /// proving the fragment false when a sibling branch is taken instead.
+    /// Fails with [`Error::ImpossibleDissatisfaction`] if the fragment
+    /// cannot be dissatisfied.
+    ///
+    /// All signatures are assumed to be 73 bytes in size, like
+    /// [`Miniscript::max_satisfaction_size`].
+    pub fn max_dissatisfaction_size(&self) -> Result<usize, Error> {
+        Ctx::max_dissatisfaction_size::<Pk, Ext>(self).ok_or(Error::ImpossibleDissatisfaction)
+    }
+
+    /// Minimum size, in bytes, of a satisfying witness, i.e. the cost of the
+    /// cheapest spending path. Together with
+    /// [`Miniscript::max_satisfaction_size`] this bounds the size of any
+    /// satisfaction.
+    ///
+    /// Signatures are assumed to be 73 bytes in size as well, so the bound
+    /// only accounts for the choice of branches. Extension fragments are
+    /// counted with their maximum satisfaction size.
+    pub fn min_satisfaction_size(&self) -> Result<usize, Error> {
+        Ctx::min_satisfaction_size::<Pk, Ext>(self).ok_or(Error::ImpossibleSatisfaction)
+    }
+
+    /// Minimum satisfaction and dissatisfaction sizes, as (witness stack,
+    /// scriptSig) costs like [`types::ExtData::max_sat_size`]. Each cost is
+    /// minimized on its own.
+    pub(crate) fn min_sat_dissat_size(&self) -> (Option<(usize, usize)>, Option<(usize, usize)>) {
+        type Size = Option<(usize, usize)>;
+
+        fn add(a: Size, b: Size) -> Size {
+            a.and_then(|(aw, asig)| b.map(|(bw, bsig)| (aw + bw, asig + bsig)))
+        }
+
+        fn min(a: Size, b: Size) -> Size {
+            match (a, b) {
+                (Some((aw, asig)), Some((bw, bsig))) => {
+                    Some((cmp::min(aw, bw), cmp::min(asig, bsig)))
+                }
+                (a, None) => a,
+                (None, b) => b,
+            }
+        }
+
+        // Selecting the left or right branch of an `IF`: the number 1 is
+        // two bytes in a witness, both are a single opcode in a scriptSig
+        let one = Some((2, 1));
+        let zero = Some((1, 1));
+        match self.node {
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.min_sat_dissat_size(),
+            Terminal::DupIf(ref sub) => (add(sub.min_sat_dissat_size().0, one), zero),
+            Terminal::Verify(ref sub) => (sub.min_sat_dissat_size().0, None),
+            Terminal::NonZero(ref sub) => (sub.min_sat_dissat_size().0, zero),
+            Terminal::AndV(ref l, ref r) => {
+                let ((l_sat, _), (r_sat, _)) = (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (add(l_sat, r_sat), None)
+            }
+            Terminal::AndB(ref l, ref r) => {
+                let ((l_sat, l_dis), (r_sat, r_dis)) =
+                    (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (add(l_sat, r_sat), add(l_dis, r_dis))
+            }
+            Terminal::OrB(ref l, ref r) => {
+                let ((l_sat, l_dis), (r_sat, r_dis)) =
+                    (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (min(add(l_sat, r_dis), add(l_dis, r_sat)), add(l_dis, r_dis))
+            }
+            Terminal::OrD(ref l, ref r) => {
+                let ((l_sat, l_dis), (r_sat, r_dis)) =
+                    (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (min(l_sat, add(l_dis, r_sat)), add(l_dis, r_dis))
+            }
+            Terminal::OrC(ref l, ref r) => {
+                let ((l_sat, l_dis), (r_sat, _)) =
+                    (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (min(l_sat, add(l_dis, r_sat)), None)
+            }
+            Terminal::OrI(ref l, ref r) => {
+                let ((l_sat, l_dis), (r_sat, r_dis)) =
+                    (l.min_sat_dissat_size(), r.min_sat_dissat_size());
+                (
+                    min(add(l_sat, one), add(r_sat, zero)),
+                    min(add(l_dis, one), add(r_dis, zero)),
+                )
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                let ((a_sat, a_dis), (b_sat, _), (c_sat, c_dis)) = (
+                    a.min_sat_dissat_size(),
+                    b.min_sat_dissat_size(),
+                    c.min_sat_dissat_size(),
+                );
+                (min(add(a_sat, b_sat), add(a_dis, c_sat)), add(a_dis, c_dis))
+            }
+            Terminal::Thresh(k, ref subs) => {
+                let sizes: Vec<_> = subs.iter().map(|s| s.min_sat_dissat_size()).collect();
+                let dissat = sizes
+                    .iter()
+                    .fold(Some((0, 0)), |acc, &(_, dis)| add(acc, dis));
+                // Dissatisfy every sub and satisfy the `k` cheapest instead
+                let cheapest = |cost: fn((usize, usize)) -> usize| -> Option<usize> {
+                    let mut total = 0;
+                    let mut extra = Vec::with_capacity(sizes.len());
+                    for &(sat, dis) in &sizes {
+                        let dis = cost(dis?) as isize;
+                        total += dis;
+                        if let Some(sat) = sat {
+                            extra.push(cost(sat) as isize - dis);
+                        }
+                    }
+                    if extra.len() < k {
+                        return None;
+                    }
+                    extra.sort_unstable();
+                    Some((total + extra[..k].iter().sum::<isize>()) as usize)
+                };
+                let sat = cheapest(|x| x.0).and_then(|w| cheapest(|x| x.1).map(|s| (w, s)));
+                (sat, dissat)
+            }
+            // The satisfaction of every other fragment has a fixed size
+            _ => (self.ext.max_sat_size, self.ext.max_dissat_size),
+        }
+    }
+
+    /// Maximum number of elements on the stack when satisfying the fragment,
+    /// not counting the witness script. `None` if the fragment cannot be
+    /// satisfied.
+    ///
+    /// Tapscript limits the stack to `MAX_STACK_SIZE` (1000) elements during
+    /// execution: a `Tap` miniscript is rejected with
+    /// `StackSizeLimitExceeded` when this count plus
+    /// [`Miniscript::max_exec_stack_elements`] exceeds that limit.
+    pub fn max_stack_elements(&self) -> Option<usize> {
+        self.ext.stack_elem_count_sat
+    }
+
+    /// Maximum number of additional stack elements pushed while executing
+    /// the script when satisfying the fragment. `None` if the fragment
+    /// cannot be satisfied.
+    pub fn max_exec_stack_elements(&self) -> Option<usize> {
+        self.ext.exec_stack_elem_count_sat
+    }
+
+    /// Replace the value of every `after` fragment with `after` and of every
+    /// `older` fragment with `older`; `None` leaves the fragments as they are.
+    ///
+    /// The rebuilt tree is type checked and context checked again, and it is
+    /// rejected if a spending path mixes height and time based timelocks.
+    pub fn with_locktimes(&self, after: Option<u32>, older: Option<u32>) -> Result<Self, Error> {
+        fn substitute<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            ms: &Miniscript<Pk, Ctx, Ext>,
+            after: Option<PackedLockTime>,
+            older: Option<Sequence>,
+        ) -> Result<Miniscript<Pk, Ctx, Ext>, Error> {
+            let node = match (&ms.node, after, older) {
+                (&Terminal::After(_), Some(after), _) => Terminal::After(after),
+                (&Terminal::Older(_), _, Some(older)) => Terminal::Older(older),
+                (node, _, _) => {
+                    node.map_children(&mut |sub| substitute(sub, after, older).map(Arc::new))?
+                }
+            };
+            let ms = Miniscript::from_ast(node)?;
+            Ctx::check_global_validity(&ms)?;
+            Ok(ms)
+        }
+
+        let after = after.map(|n| LockTime::from_consensus(n).into());
+        let older = older.map(Sequence::from_consensus);
+        let ms = substitute(self, after, older)?;
+        if ms.has_mixed_timelocks() {
+            return Err(Error::AnalysisError(
+                AnalysisError::HeightTimelockCombination,
+            ));
+        }
+        Ok(ms)
+    }
+
+    /// Replace every extension fragment `e` with `f(e)`, possibly changing
+    /// the extension type.
+    ///
+    /// Unlike [`TranslateExt`], which copies the type of every node, the
+    /// rebuilt tree is type checked and context checked again from the
+    /// leaves up, so a replacement with a different correctness or
+    /// malleability property is either accepted with the new types or
+    /// rejected with the corresponding error.
+    pub fn map_ext<ExtQ, F>(&self, mut f: F) -> Result<Miniscript<Pk, Ctx, ExtQ>, Error>
+    where
+        ExtQ: Extension,
+        F: FnMut(Ext) -> ExtQ,
+    {
+        self.real_map_ext(&mut f)
+    }
+
+    /// Rewrite every chain of `and_v` fragments into a canonical left-nested
+    /// form, `and_v(and_v(a,b),c)`, which is the form produced by the
+    /// decoder.
+    ///
+    /// `and_v(X,Y)` is encoded as `[X] [Y]`, so any nesting of the same
+    /// operands encodes to the same script and the rewrite never changes the
+    /// encoding or the type. Other fragments are left as they are:
+    /// reassociating `or_b`, `and_b` or flattening nested `thresh`
+    /// fragments changes the script.
+    pub fn normalize(&self) -> Self {
+        fn collect_and_v<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            ms: &Miniscript<Pk, Ctx, Ext>,
+            operands: &mut Vec<Arc<Miniscript<Pk, Ctx, Ext>>>,
+        ) -> Result<(), Error> {
+            match ms.node {
+                Terminal::AndV(ref left, ref right) => {
+                    collect_and_v(left, operands)?;
+                    collect_and_v(right, operands)
+                }
+                _ => {
+                    operands.push(Arc::new(normalize(ms)?));
+                    Ok(())
+                }
+            }
+        }
+
+        fn normalize<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension>(
+            ms: &Miniscript<Pk, Ctx, Ext>,
+        ) -> Result<Miniscript<Pk, Ctx, Ext>, Error> {
+            let node = match ms.node {
+                Terminal::AndV(..) => {
+                    let mut operands = vec![];
+                    collect_and_v(ms, &mut operands)?;
+                    let mut operands = operands.into_iter();
+                    let first = operands.next().expect("and_v has two operands");
+                    let last = operands.next_back().expect("and_v has two operands");
+                    let left = operands.try_fold(first, |left, right| {
+                        Miniscript::from_ast(Terminal::AndV(left, right)).map(Arc::new)
+                    })?;
+                    Terminal::AndV(left, last)
+                }
+                ref node => node.map_children(&mut |sub| normalize(sub).map(Arc::new))?,
+            };
+            Miniscript::from_ast(node)
+        }
+
+        normalize(self).expect("reassociating and_v fragments does not change types")
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> ForEachKey<Pk>
@@ -387,6 +741,41 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx,
         };
         Ok(ms)
     }
+
+    /// Rebuilds a node with its extensions mapped by `f`, in the context
+    /// `CtxQ`. Unlike key translation, swapping an extension or the context
+    /// may change the type and extra data of every node above it, so both
+    /// are recomputed.
+    fn real_map_ext<CtxQ, ExtQ, F>(&self, f: &mut F) -> Result<Miniscript<Pk, CtxQ, ExtQ>, Error>
+    where
+        CtxQ: ScriptContext,
+        ExtQ: Extension,
+        F: FnMut(Ext) -> ExtQ,
+    {
+        let ms = Miniscript::from_ast(self.node.real_map_ext(f)?)?;
+        CtxQ::check_global_validity(&ms)?;
+        Ok(ms)
+    }
+}
+
+impl<Pk: MiniscriptKey, Ext: Extension> Miniscript<Pk, NoChecks, Ext> {
+    /// Upgrades a miniscript parsed without any context checks into the
+    /// context `Ctx2`.
+    ///
+    /// The tree is rebuilt from the leaves up, so the type and extra data of
+    /// every node are recomputed for `Ctx2`, and
+    /// [`ScriptContext::check_global_validity`] is run on every node. The
+    /// whole miniscript must then pass [`ScriptContext::check_local_validity`]
+    /// and [`ScriptContext::top_level_checks`].
+    ///
+    /// This is useful when a script is read permissively with `NoChecks`
+    /// and only later known to belong to a specific context.
+    pub fn upgrade_context<Ctx2: ScriptContext>(self) -> Result<Miniscript<Pk, Ctx2, Ext>, Error> {
+        let ms: Miniscript<Pk, Ctx2, Ext> = self.real_map_ext(&mut |e| e)?;
+        Ctx2::check_local_validity(&ms)?;
+        Ctx2::top_level_checks(&ms)?;
+        Ok(ms)
+    }
 }
 
 impl_block_str!(
@@ -433,14 +822,57 @@ impl_block_str!(
 impl<Pk: ToPublicKey, Ctx: ScriptContext, Ext: ParseableExt> Miniscript<Pk, Ctx, Ext> {
     /// Attempt to produce non-malleable satisfying witness for the
     /// witness script represented by the parse tree
+    ///
+    /// Among the non-malleable satisfactions the satisfier can produce, the
+    /// one with the smallest witness size is returned. For example, with
+    /// signatures for both keys and a satisfied timelock,
+    /// `or_d(pk(A),and_v(v:older(144),pk(B)))` is satisfied with the
+    /// signature for `A` alone. Ties are broken deterministically in favour
+    /// of the leftmost branch of a disjunction, and of the leftmost subs of
+    /// a `thresh`.
     pub fn satisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Result<Vec<Vec<u8>>, Error> {
+        let stack = self.satisfy_items(satisfier, false /* allow_mall */)?;
+        Ok(stack.into_iter().map(|item| item.data).collect())
+    }
+
+    /// Attempt to produce a malleable satisfying witness for the
+    /// witness script represented by the parse tree
+    ///
+    /// The smallest satisfaction is chosen as in [`Miniscript::satisfy`],
+    /// with the same tie-breaking, but among all satisfactions including
+    /// malleable ones. The result may therefore be smaller.
+    pub fn satisfy_malleable<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let stack = self.satisfy_items(satisfier, true /* allow_mall */)?;
+        Ok(stack.into_iter().map(|item| item.data).collect())
+    }
+
+    /// Attempt to produce a satisfying witness as [`Miniscript::satisfy`],
+    /// or as [`Miniscript::satisfy_malleable`] if `allow_mall` is set, with
+    /// every item tagged with the role it was given when it was pushed
+    pub(crate) fn satisfy_items<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+    ) -> Result<Vec<satisfy::WitnessItem>, Error> {
         // Only satisfactions for default versions (0xc0) are allowed.
         let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::default());
-        match satisfy::Satisfaction::satisfy(&self.node, &satisfier, self.ty.mall.safe, &leaf_hash)
-            .stack
-        {
+        let sat = if allow_mall {
+            satisfy::Satisfaction::satisfy_mall(
+                &self.node,
+                &satisfier,
+                self.ty.mall.safe,
+                &leaf_hash,
+            )
+        } else {
+            satisfy::Satisfaction::satisfy(&self.node, &satisfier, self.ty.mall.safe, &leaf_hash)
+        };
+        match sat.stack {
             satisfy::Witness::Stack(stack) => {
-                Ctx::check_witness::<Pk, Ext>(&stack)?;
+                let raw: Vec<_> = stack.iter().map(|item| item.data.clone()).collect();
+                Ctx::check_witness::<Pk, Ext>(&raw)?;
                 Ok(stack)
             }
             satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
@@ -449,29 +881,95 @@ impl<Pk: ToPublicKey, Ctx: ScriptContext, Ext: ParseableExt> Miniscript<Pk, Ctx,
         }
     }
 
-    /// Attempt to produce a malleable satisfying witness for the
-    /// witness script represented by the parse tree
-    pub fn satisfy_malleable<S: satisfy::Satisfier<Pk>>(
+    /// Attempt to produce a satisfying witness according to `options`.
+    ///
+    /// By default, this behaves as [`Miniscript::satisfy_malleable`]. With
+    /// [`satisfy::SatisfyOptions::require_non_malleable`] set, it behaves as
+    /// [`Miniscript::satisfy`], but also rejects scripts which break the
+    /// context specific malleability rules (see
+    /// [`Miniscript::check_non_malleable`]). It fails with
+    /// [`Error::NoNonMalleableSatisfaction`] rather than
+    /// [`Error::CouldNotSatisfy`] when a malleable satisfaction exists.
+    pub fn satisfy_with_options<S: satisfy::Satisfier<Pk>>(
         &self,
         satisfier: S,
+        options: satisfy::SatisfyOptions,
     ) -> Result<Vec<Vec<u8>>, Error> {
+        if !options.require_non_malleable {
+            return self.satisfy_malleable(satisfier);
+        }
+        match self.satisfy(&satisfier) {
+            Err(Error::CouldNotSatisfy) => {
+                self.satisfy_malleable(&satisfier)?;
+                Err(Error::NoNonMalleableSatisfaction)
+            }
+            Ok(_) if self.check_non_malleable().is_err() => Err(Error::NoNonMalleableSatisfaction),
+            res => res,
+        }
+    }
+
+    /// Enumerate the `max_paths` cheapest distinct satisfying witnesses
+    /// available with the given satisfier, cheapest first. Every fragment
+    /// keeps only its `max_paths` cheapest witnesses, which bounds the work
+    /// done on deep `thresh` trees.
+    ///
+    /// Unlike [`Miniscript::satisfy`], this makes no attempt to filter out
+    /// malleable witnesses; it is meant for listing the spending options of a
+    /// script. Witnesses exceeding the context's resource limits are skipped
+    /// after the cap is applied, so fewer than `max_paths` may be returned.
+    pub fn all_satisfactions<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        max_paths: usize,
+    ) -> Vec<satisfy::Satisfaction> {
         let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::default());
-        match satisfy::Satisfaction::satisfy_mall(
+        satisfy::Satisfaction::all_satisfactions(
             &self.node,
             &satisfier,
             self.ty.mall.safe,
             &leaf_hash,
+            max_paths,
         )
-        .stack
-        {
-            satisfy::Witness::Stack(stack) => {
-                Ctx::check_witness::<Pk, Ext>(&stack)?;
-                Ok(stack)
-            }
-            satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
-                Err(Error::CouldNotSatisfy)
+        .into_iter()
+        .filter(|sat| match sat.stack.raw_stack() {
+            Some(stack) => Ctx::check_witness::<Pk, Ext>(&stack).is_ok(),
+            None => false,
+        })
+        .collect()
+    }
+}
+
+impl<Pk: ToPublicKey, Ctx: ScriptContext, Ext: Extension> Miniscript<Pk, Ctx, Ext> {
+    /// Whether the keys of every `multi` fragment are sorted as in BIP67,
+    /// by their serialization in the script. Keys are decoded in the order
+    /// they are pushed, so this tells whether a parsed script was built
+    /// from a `sortedmulti`. Returns `None` if there is no `multi`.
+    pub fn multi_keys_sorted(&self) -> Option<bool> {
+        let mut ret = None;
+        for ms in self.iter() {
+            if let Some((_, keys)) = ms.node.as_multi() {
+                let keys: Vec<_> = keys.iter().map(Ctx::serialize_pk).collect();
+                if keys.windows(2).any(|w| w[0] > w[1]) {
+                    return Some(false);
+                }
+                ret = Some(true);
             }
         }
+        ret
+    }
+}
+
+impl<Pk: ToPublicKey, Ext: ParseableExt> Miniscript<Pk, Tap, Ext> {
+    /// The [`TapLeafHash`] of this miniscript as a tapscript leaf with the
+    /// default Elements leaf version (0xc4)
+    pub fn leaf_hash(&self) -> TapLeafHash {
+        self.leaf_hash_with_version(LeafVersion::default())
+    }
+
+    /// The [`TapLeafHash`] of this miniscript as a tapscript leaf with the
+    /// given leaf version
+    pub fn leaf_hash_with_version(&self, version: LeafVersion) -> TapLeafHash {
+        TapLeafHash::from_script(&self.encode(), version)
     }
 }
 
@@ -539,8 +1037,9 @@ mod tests {
     use elements::taproot::TapLeafHash;
     use elements::{self, secp256k1_zkp, Sequence};
 
-    use super::{Miniscript, ScriptContext, Segwitv0, Tap};
+    use super::{satisfy, Miniscript, ScriptContext, Segwitv0, Tap};
     use crate::extensions::CovExtArgs;
+    use crate::miniscript::context::ScriptContextError;
     use crate::miniscript::types::{self, ExtData, Property, Type};
     use crate::miniscript::Terminal;
     use crate::policy::Liftable;
@@ -1154,6 +1653,50 @@ mod tests {
         let tap_multi_a_ms = TapMs::from_str_insane("multi_a(1,A,B,C)").unwrap();
         assert_eq!(tap_multi_a_ms.to_string(), "multi_a(1,A,B,C)");
 
+        let keys = (0..125001).map(|i| format!("K{}", i)).collect::<Vec<_>>();
+        let err = TapMs::from_str_insane(&format!("multi_a(1,{})", keys.join(","))).unwrap_err();
+        assert!(matches!(err, crate::Error::MultiATooManyKeys(125000)));
+
+        // `<pk> CHECKSIG <125001> NUMEQUAL`: the threshold alone is beyond
+        // what any block can hold
+        let err = Tapscript::parse_insane(&hex_script(
+            "202788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99ac0349e8019c",
+        ))
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::MultiATooManyKeys(125000)));
+
+        // A small threshold over as many keys as the decoder allows, and one
+        // more. Such scripts are beyond the parse size limits, so feed the
+        // tokens to the decoder directly
+        {
+            use crate::miniscript::decode;
+            use crate::miniscript::lex::{Token as Tk, TokenIter};
+
+            let pk = Vec::<u8>::from_hex(
+                "2788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99",
+            )
+            .unwrap();
+            let multi_a = |n_keys: usize| {
+                let mut tokens = vec![Tk::Bytes32(&pk), Tk::CheckSig];
+                for _ in 1..n_keys {
+                    tokens.push(Tk::Bytes32(&pk));
+                    tokens.push(Tk::CheckSigAdd);
+                }
+                tokens.push(Tk::Num(1));
+                tokens.push(Tk::NumEqual);
+                decode::parse::<Tap, NoExt>(&mut TokenIter::new(tokens)).unwrap_err()
+            };
+            // The key count is within the decoder limit, the script is then
+            // rejected for not fitting in a block
+            let err = multi_a(125000);
+            assert_eq!(
+                err.as_context_error(),
+                Some(&ScriptContextError::MaxWitnessScriptSizeExceeded)
+            );
+            let err = multi_a(125001);
+            assert!(matches!(err, crate::Error::MultiATooManyKeys(125000)));
+        }
+
         // Test encode/decode and translation tests
         let tap_ms = tap_multi_a_ms
             .translate_pk(&mut StrXOnlyKeyTranslator::new())
@@ -1191,38 +1734,375 @@ mod tests {
     }
 
     #[test]
-    fn decode_bug_cpp_review() {
-        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
-            "and_b(1,s:and_v(v:older(9),c:pk_k(A)))",
-        )
+    fn all_satisfactions() {
+        struct SimpleSatisfier(secp256k1_zkp::schnorr::Signature);
+
+        impl<Pk: ToPublicKey> Satisfier<Pk> for SimpleSatisfier {
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                _pk: &Pk,
+                _h: &TapLeafHash,
+            ) -> Option<elements::SchnorrSig> {
+                Some(elements::SchnorrSig {
+                    sig: self.0,
+                    hash_ty: elements::SchnorrSigHashType::Default,
+                })
+            }
+        }
+
+        let ms = Miniscript::<String, Tap>::from_str("or_d(pk(A),and_v(v:pk(B),older(10)))")
+            .unwrap()
+            .translate_pk(&mut StrXOnlyKeyTranslator::new())
+            .unwrap();
+        let schnorr_sig = secp256k1_zkp::schnorr::Signature::from_str("84526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f0784526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07").unwrap();
+        let sig = schnorr_sig.as_ref().to_vec();
+
+        let satisfier = (SimpleSatisfier(schnorr_sig), Sequence::from_height(10));
+        let sats = ms.all_satisfactions(&satisfier, 10);
+        assert_eq!(sats.len(), 2);
+        assert_eq!(sats[0].stack.raw_stack(), Some(vec![sig.clone()]));
+        assert_eq!(sats[1].stack.raw_stack(), Some(vec![sig.clone(), vec![]]));
+        assert!(sats[0].stack.size() < sats[1].stack.size());
+
+        // The timelocked path is unavailable without a matching sequence
+        let sats = ms.all_satisfactions(SimpleSatisfier(schnorr_sig), 10);
+        assert_eq!(sats.len(), 1);
+        assert_eq!(sats[0].stack.raw_stack(), Some(vec![sig.clone()]));
+
+        // The number of paths is capped
+        let sats = ms.all_satisfactions(&satisfier, 1);
+        assert_eq!(sats.len(), 1);
+
+        // The cap keeps the cheapest witness, not the first one enumerated
+        let ms = Miniscript::<String, Tap>::from_str("or_i(and_v(v:pk(A),pk(B)),pk(C))")
+            .unwrap()
+            .translate_pk(&mut StrXOnlyKeyTranslator::new())
+            .unwrap();
+        let sats = ms.all_satisfactions(&satisfier, 1);
+        assert_eq!(sats.len(), 1);
+        assert_eq!(sats[0].stack.raw_stack(), Some(vec![sig.clone(), vec![]]));
+        let sats = ms.all_satisfactions(&satisfier, 10);
+        assert_eq!(sats.len(), 2);
+        assert_eq!(
+            sats[1].stack.raw_stack(),
+            Some(vec![sig.clone(), sig, vec![1]])
+        );
+    }
+
+    #[test]
+    fn satisfy_prefers_smallest() {
+        struct SimpleSatisfier(secp256k1_zkp::schnorr::Signature);
+
+        impl<Pk: ToPublicKey> Satisfier<Pk> for SimpleSatisfier {
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                _pk: &Pk,
+                _h: &TapLeafHash,
+            ) -> Option<elements::SchnorrSig> {
+                Some(elements::SchnorrSig {
+                    sig: self.0,
+                    hash_ty: elements::SchnorrSigHashType::Default,
+                })
+            }
+        }
+
+        let schnorr_sig = secp256k1_zkp::schnorr::Signature::from_str("84526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f0784526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07").unwrap();
+        let sig = schnorr_sig.as_ref().to_vec();
+        let satisfier = (SimpleSatisfier(schnorr_sig), Sequence::from_height(144));
+        let ms = |s: &str| {
+            Miniscript::<String, Tap>::from_str(s)
+                .unwrap()
+                .translate_pk(&mut StrXOnlyKeyTranslator::new())
+                .unwrap()
+        };
+
+        // Both branches are available, the one without the dissatisfaction
+        // of `pk(A)` is cheaper
+        let wit = ms("or_d(pk(A),and_v(v:older(144),pk(B)))")
+            .satisfy(&satisfier)
+            .unwrap();
+        assert_eq!(wit, vec![sig.clone()]);
+        let wit = ms("or_i(and_v(v:older(144),pk(B)),pk(A))")
+            .satisfy(&satisfier)
+            .unwrap();
+        assert_eq!(wit, vec![sig.clone(), vec![]]);
+
+        // Equal sizes are broken in favour of the leftmost sub
+        let wit = ms("thresh(1,pk(A),s:pk(B))").satisfy(&satisfier).unwrap();
+        assert_eq!(wit, vec![vec![], sig.clone()]);
+        let wit = ms("thresh(1,pk(A),s:pk(B))")
+            .satisfy_malleable(&satisfier)
+            .unwrap();
+        assert_eq!(wit, vec![vec![], sig]);
+    }
+
+    #[test]
+    fn satisfy_require_non_malleable() {
+        use std::collections::HashMap;
+
+        use crate::{Error, Legacy, SatisfyOptions};
+
+        let strict = SatisfyOptions {
+            require_non_malleable: true,
+        };
+        let keys = pubkeys(2);
+
+        // Both timelocks are satisfied, anyone may switch branches
+        let ms = Segwitv0Script::from_str_insane("or_i(older(144),older(200))").unwrap();
+        let satisfier = Sequence::from_height(200);
+        assert!(ms
+            .satisfy_with_options(satisfier, SatisfyOptions::default())
+            .is_ok());
+        assert_eq!(
+            ms.satisfy_with_options(satisfier, strict),
+            Err(Error::NoNonMalleableSatisfaction)
+        );
+        assert_eq!(
+            ms.satisfy_with_options(Sequence::from_height(100), strict),
+            Err(Error::CouldNotSatisfy)
+        );
+
+        // `or_i` is malleable under Legacy rules
+        let ms = Miniscript::<bitcoin::PublicKey, Legacy, NoExt>::from_str_insane(&format!(
+            "or_i(pk({}),pk({}))",
+            keys[0], keys[1]
+        ))
         .unwrap();
-        let ms_trans = ms.translate_pk(&mut StrKeyTranslator::new()).unwrap();
-        let enc = ms_trans.encode();
-        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(&enc).unwrap();
-        assert_eq!(ms_trans.encode(), ms.encode());
+        let mut satisfier = HashMap::new();
+        satisfier.insert(
+            keys[0],
+            (
+                secp256k1_zkp::ecdsa::Signature::from_compact(&[1; 64]).unwrap(),
+                elements::EcdsaSigHashType::All,
+            ),
+        );
+        assert!(ms.satisfy(&satisfier).is_ok());
+        assert_eq!(
+            ms.satisfy_with_options(&satisfier, strict),
+            Err(Error::NoNonMalleableSatisfaction)
+        );
+        let ms = Segwitv0Script::from_str_insane(&format!("or_i(pk({}),pk({}))", keys[0], keys[1]))
+            .unwrap();
+        assert_eq!(
+            ms.satisfy_with_options(&satisfier, strict),
+            ms.satisfy(&satisfier)
+        );
     }
 
     #[test]
-    fn expr_features() {
-        // test that parsing raw hash160 does not work with
-        let hash160_str = "e9f171df53e04b270fa6271b42f66b0f4a99c5a2";
-        let ms_str = &format!("c:expr_raw_pkh({})", hash160_str);
-        type SegwitMs = Miniscript<bitcoin::PublicKey, Segwitv0>;
+    fn fn_satisfier() {
+        use crate::FnSatisfier;
 
-        // Test that parsing raw hash160 from string does not work without extra features
-        SegwitMs::from_str(ms_str).unwrap_err();
-        SegwitMs::from_str_insane(ms_str).unwrap_err();
-        let ms = SegwitMs::from_str_ext(ms_str, &ExtParams::allow_all()).unwrap();
+        let ms = Miniscript::<String, Tap>::from_str("and_v(v:pk(A),older(10))")
+            .unwrap()
+            .translate_pk(&mut StrXOnlyKeyTranslator::new())
+            .unwrap();
+        let schnorr_sig = secp256k1_zkp::schnorr::Signature::from_str("84526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f0784526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07").unwrap();
 
-        let script = ms.encode();
-        // The same test, but parsing from script
-        SegwitMs::parse(&script).unwrap_err();
-        SegwitMs::parse_insane(&script).unwrap_err();
-        SegwitMs::parse_with_ext(&script, &ExtParams::allow_all()).unwrap();
+        let mut requested = 0;
+        let wit = ms
+            .satisfy(
+                FnSatisfier::new()
+                    .with_tap_leaf_script_sig(|_, _| {
+                        requested += 1;
+                        Some(elements::SchnorrSig {
+                            sig: schnorr_sig,
+                            hash_ty: elements::SchnorrSigHashType::Default,
+                        })
+                    })
+                    .with_older(|seq| seq == Sequence::from_height(10)),
+            )
+            .unwrap();
+        assert_eq!(wit, vec![schnorr_sig.as_ref().to_vec()]);
+        assert_eq!(requested, 1);
+
+        // Unset callbacks behave like the default satisfier
+        let sat = FnSatisfier::new().with_older(|_| true);
+        assert!(ms.satisfy(sat).is_err());
     }
 
     #[test]
-    fn tr_multi_a_j_wrapper() {
+    fn multi_a_satisfaction() {
+        use crate::FnSatisfier;
+
+        let ms = Miniscript::<String, Tap>::from_str("multi_a(2,A,B,C)")
+            .unwrap()
+            .translate_pk(&mut StrXOnlyKeyTranslator::new())
+            .unwrap();
+        let keys: Vec<_> = ms.iter_pk().collect();
+        let sig = |b: u8| elements::SchnorrSig {
+            sig: secp256k1_zkp::schnorr::Signature::from_slice(&[b; 64]).unwrap(),
+            hash_ty: elements::SchnorrSigHashType::Default,
+        };
+
+        // Signatures for the first and last key only
+        let (a, c) = (keys[0], keys[2]);
+        let sat = FnSatisfier::new().with_tap_leaf_script_sig(|pk, _| {
+            if *pk == a {
+                Some(sig(1))
+            } else if *pk == c {
+                Some(sig(3))
+            } else {
+                None
+            }
+        });
+        // The witness is consumed from the top, so the last key comes first
+        // and the absent key gets an empty placeholder
+        let wit = ms.satisfy(sat).unwrap();
+        assert_eq!(wit, vec![vec![3; 64], vec![], vec![1; 64]]);
+
+        // Only one signature available
+        let sat =
+            FnSatisfier::new().with_tap_leaf_script_sig(
+                |pk, _| {
+                    if *pk == a {
+                        Some(sig(1))
+                    } else {
+                        None
+                    }
+                },
+            );
+        assert!(ms.satisfy(sat).is_err());
+    }
+
+    #[test]
+    fn multi_dummy_element() {
+        use crate::FnSatisfier;
+
+        let keys = pubkeys(3);
+        let ms = Segwitv0Script::from_str(&format!("multi(2,{},{},{})", keys[0], keys[1], keys[2]))
+            .unwrap();
+        let sig = secp256k1_zkp::ecdsa::Signature::from_compact(&[1; 64]).unwrap();
+        let sat = FnSatisfier::new().with_ecdsa_sig(|pk| {
+            if *pk != keys[1] {
+                Some((sig, elements::EcdsaSigHashType::All))
+            } else {
+                None
+            }
+        });
+        let wit = ms.satisfy(sat).unwrap();
+        // CHECKMULTISIG consumes an extra empty element below the signatures
+        assert_eq!(wit.len(), 3);
+        assert!(wit[0].is_empty());
+        // Two signatures, the dummy and the witness script
+        assert_eq!(
+            ms.max_satisfaction_witness_elements().unwrap(),
+            wit.len() + 1
+        );
+        let wit_size: usize = wit.iter().map(|elem| elem.len() + 1).sum();
+        assert!(wit_size <= ms.max_satisfaction_size().unwrap());
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 1 + 73 * 2);
+    }
+
+    #[test]
+    fn layered_satisfier() {
+        use std::collections::HashMap;
+
+        use crate::{elementssig_to_rawsig, ElementsSig, LayeredSatisfier};
+
+        let keys = pubkeys(3);
+        let ms = Segwitv0Script::from_str(&format!("multi(2,{},{},{})", keys[0], keys[1], keys[2]))
+            .unwrap();
+        let sig = |b: u8| -> ElementsSig {
+            (
+                secp256k1_zkp::ecdsa::Signature::from_compact(&[b; 64]).unwrap(),
+                elements::EcdsaSigHashType::All,
+            )
+        };
+
+        let mut first = HashMap::new();
+        first.insert(keys[0], sig(1));
+        let mut second = HashMap::new();
+        second.insert(keys[0], sig(2));
+        second.insert(keys[1], sig(3));
+
+        let mut layered = LayeredSatisfier::new(vec![first]);
+        assert!(ms.satisfy(&layered).is_err());
+        layered.push(second);
+        // The first layer takes priority for keys[0]
+        let wit = ms.satisfy(&layered).unwrap();
+        assert_eq!(
+            wit,
+            vec![
+                vec![],
+                elementssig_to_rawsig(&sig(1)),
+                elementssig_to_rawsig(&sig(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn tap_rejects_trivial_leaf() {
+        use crate::Error;
+
+        let ms = Miniscript::<String, Tap>::from_str("1").unwrap();
+        assert!(matches!(
+            Tap::top_level_checks(&ms),
+            Err(Error::TrivialTapLeaf)
+        ));
+
+        let ms = Miniscript::<String, Tap>::from_str("pk(A)").unwrap();
+        assert!(Tap::top_level_checks(&ms).is_ok());
+        // Only taproot leaves are rejected
+        let ms = Miniscript::<String, Segwitv0>::from_str("1").unwrap();
+        assert!(Segwitv0::top_level_checks(&ms).is_ok());
+    }
+
+    #[test]
+    fn bare_multi_key_limit_is_policy() {
+        use crate::{BareCtx, Error};
+
+        let ms = Miniscript::<String, BareCtx>::from_str("multi(1,A,B,C,D)").unwrap();
+        assert!(matches!(
+            BareCtx::top_level_checks(&ms),
+            Err(Error::NonStandardBareScript)
+        ));
+        assert!(matches!(
+            BareCtx::top_level_policy_checks(&ms),
+            Err(Error::NonStandardBareScript)
+        ));
+        assert!(BareCtx::top_level_consensus_checks(&ms).is_ok());
+
+        let ms = Miniscript::<String, BareCtx>::from_str("multi(1,A,B,C)").unwrap();
+        assert!(BareCtx::top_level_checks(&ms).is_ok());
+        // Other shapes are still rejected
+        let ms = Miniscript::<String, BareCtx>::from_str("or_b(pk(A),s:pk(B))").unwrap();
+        assert!(BareCtx::top_level_consensus_checks(&ms).is_err());
+    }
+
+    #[test]
+    fn decode_bug_cpp_review() {
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "and_b(1,s:and_v(v:older(9),c:pk_k(A)))",
+        )
+        .unwrap();
+        let ms_trans = ms.translate_pk(&mut StrKeyTranslator::new()).unwrap();
+        let enc = ms_trans.encode();
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(&enc).unwrap();
+        assert_eq!(ms_trans.encode(), ms.encode());
+    }
+
+    #[test]
+    fn expr_features() {
+        // test that parsing raw hash160 does not work with
+        let hash160_str = "e9f171df53e04b270fa6271b42f66b0f4a99c5a2";
+        let ms_str = &format!("c:expr_raw_pkh({})", hash160_str);
+        type SegwitMs = Miniscript<bitcoin::PublicKey, Segwitv0>;
+
+        // Test that parsing raw hash160 from string does not work without extra features
+        SegwitMs::from_str(ms_str).unwrap_err();
+        SegwitMs::from_str_insane(ms_str).unwrap_err();
+        let ms = SegwitMs::from_str_ext(ms_str, &ExtParams::allow_all()).unwrap();
+
+        let script = ms.encode();
+        // The same test, but parsing from script
+        SegwitMs::parse(&script).unwrap_err();
+        SegwitMs::parse_insane(&script).unwrap_err();
+        SegwitMs::parse_with_ext(&script, &ExtParams::allow_all()).unwrap();
+    }
+
+    #[test]
+    fn tr_multi_a_j_wrapper() {
         // Reported by darosior
         // `multi_a` fragment may require the top stack element to be the empty vector.
         // Previous version had incorrectly copied this code from multi.
@@ -1230,4 +2110,1913 @@ mod tests {
         let ms_str = TapMs::from_str_insane("j:multi_a(1,A,B,C)");
         assert!(ms_str.is_err());
     }
+
+    #[test]
+    fn upgrade_no_checks_context() {
+        use crate::miniscript::context::NoChecks;
+        use crate::{BareCtx, Error};
+
+        let key = pubkeys(1)[0];
+        let ms = Miniscript::<bitcoin::PublicKey, NoChecks>::from_str_insane(&format!(
+            "multi(1,{})",
+            key
+        ))
+        .unwrap();
+
+        let segwit_ms = ms.clone().upgrade_context::<Segwitv0>().unwrap();
+        assert_eq!(segwit_ms.to_string(), ms.to_string());
+        assert_eq!(
+            ms.upgrade_context::<Tap>().unwrap_err(),
+            Error::ContextError(ScriptContextError::TaprootMultiDisabled)
+        );
+
+        // The extra data is recomputed for the new context
+        let ms =
+            Miniscript::<bitcoin::PublicKey, NoChecks>::from_str_insane(&format!("pk({})", key))
+                .unwrap();
+        let tap_ms = ms.clone().upgrade_context::<Tap>().unwrap();
+        assert_eq!(tap_ms.ext.pk_cost, 34);
+        assert_eq!(tap_ms.ext.max_sat_size, Some((66, 66)));
+
+        // Top level checks are run on the upgraded miniscript
+        let ms = Miniscript::<bitcoin::PublicKey, NoChecks>::from_str_insane(&format!(
+            "and_v(v:pk({}),pk({}))",
+            key, key
+        ))
+        .unwrap();
+        assert_eq!(
+            ms.upgrade_context::<BareCtx>().unwrap_err(),
+            Error::NonStandardBareScript
+        );
+    }
+
+    #[test]
+    fn uncompressed_keys() {
+        use crate::Legacy;
+
+        let mut keys = pubkeys(3);
+        keys[1].compressed = false;
+        keys[2].compressed = false;
+        let ms = Miniscript::<bitcoin::PublicKey, Legacy>::from_str_insane(&format!(
+            "or_b(pk({}),a:multi(1,{},{}))",
+            keys[0], keys[1], keys[2]
+        ))
+        .unwrap();
+        assert!(ms.has_uncompressed_keys());
+        assert_eq!(ms.uncompressed_key_count(), 2);
+
+        let ms =
+            Miniscript::<bitcoin::PublicKey, Legacy>::from_str_insane(&format!("pk({})", keys[0]))
+                .unwrap();
+        assert!(!ms.has_uncompressed_keys());
+        assert_eq!(ms.uncompressed_key_count(), 0);
+    }
+
+    #[test]
+    fn pkh_witness_key_len() {
+        use crate::{Legacy, Tap};
+
+        let legacy = Miniscript::<String, Legacy>::from_str_insane("pkh(A)").unwrap();
+        let segwit = Miniscript::<String, Segwitv0>::from_str_insane("pkh(A)").unwrap();
+        let tap = Miniscript::<String, Tap>::from_str_insane("pkh(A)").unwrap();
+        // Legacy key-hashes may commit to an uncompressed key
+        assert_eq!(legacy.ext.max_sat_size, Some((66 + 73, 66 + 73)));
+        assert_eq!(segwit.ext.max_sat_size, Some((34 + 73, 34 + 73)));
+        assert_eq!(tap.ext.max_sat_size, Some((33 + 66, 33 + 66)));
+        assert_eq!(legacy.ext.max_dissat_size, Some((67, 67)));
+        assert_eq!(segwit.ext.max_dissat_size, Some((35, 35)));
+    }
+
+    #[test]
+    fn pkh_hashes_serialize_pk() {
+        let pk = pubkeys(1)[0];
+        let segwit_ser = Segwitv0::serialize_pk(&pk);
+        let tap_ser = Tap::serialize_pk(&pk);
+        assert_eq!(segwit_ser, pk.to_bytes());
+        assert_eq!(tap_ser, pk.to_x_only_pubkey().serialize().to_vec());
+
+        // The pushed hash commits to the context's serialization
+        let ms_str = format!("pkh({})", pk);
+        let segwit = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&ms_str).unwrap();
+        let tap = Miniscript::<bitcoin::PublicKey, Tap>::from_str_insane(&ms_str).unwrap();
+        let segwit_hash = hash160::Hash::hash(&segwit_ser);
+        let tap_hash = hash160::Hash::hash(&tap_ser);
+        assert_eq!(&segwit.encode().as_bytes()[3..23], &segwit_hash[..]);
+        assert_eq!(&tap.encode().as_bytes()[3..23], &tap_hash[..]);
+    }
+
+    #[test]
+    fn serialize_pk() {
+        use crate::{BareCtx, Legacy};
+
+        fn check<Ctx: ScriptContext>(pk: &bitcoin::PublicKey) {
+            let ser = Ctx::serialize_pk(pk);
+            assert_eq!(ser.len() + 1, Ctx::pk_len(pk));
+            // pk_k(A) is exactly the push of the serialized key
+            let ms = Miniscript::<_, Ctx, NoExt>::from_ast(Terminal::PkK(*pk)).unwrap();
+            let script = ms.encode();
+            assert_eq!(script.len(), Ctx::pk_len(pk));
+            assert_eq!(&script.as_bytes()[1..], &ser[..]);
+        }
+
+        let pk = pubkeys(1)[0];
+        assert_eq!(Tap::serialize_pk(&pk).len(), 32);
+        check::<Legacy>(&pk);
+        check::<BareCtx>(&pk);
+        check::<Segwitv0>(&pk);
+        check::<Tap>(&pk);
+
+        let uncompressed = bitcoin::PublicKey {
+            compressed: false,
+            ..pk
+        };
+        assert_eq!(Legacy::serialize_pk(&uncompressed).len(), 65);
+        check::<Legacy>(&uncompressed);
+        check::<BareCtx>(&uncompressed);
+    }
+
+    #[test]
+    fn max_stack_elements() {
+        let ms = Miniscript::<String, Tap>::from_str("multi_a(2,A,B,C)").unwrap();
+        // One signature or empty placeholder per key, and the two numbers
+        // compared by the final NUMEQUAL
+        assert_eq!(ms.max_stack_elements(), Some(3));
+        assert_eq!(ms.max_exec_stack_elements(), Some(2));
+        assert_eq!(ms.max_satisfaction_witness_elements().unwrap(), 4);
+
+        let ms = Miniscript::<String, Tap>::from_str_insane("and_v(v:pk(A),0)").unwrap();
+        assert_eq!(ms.max_stack_elements(), None);
+        assert_eq!(ms.max_exec_stack_elements(), None);
+    }
+
+    #[test]
+    fn max_script_size() {
+        use crate::{BareCtx, Legacy};
+
+        assert_eq!(Legacy::max_script_size(), 520);
+        assert_eq!(Segwitv0::max_script_size(), 10_000);
+        assert_eq!(BareCtx::max_script_size(), 10_000);
+        assert_eq!(Tap::max_script_size(), 4_000_000);
+    }
+
+    #[test]
+    fn fingerprint() {
+        let keys = pubkeys(2);
+        let ms =
+            Segwitv0Script::from_str(&format!("and_v(v:pk({}),pk({}))", keys[0], keys[1])).unwrap();
+        // Aliases and Arc sharing do not matter, only the script bytes
+        let alias =
+            Segwitv0Script::from_str(&format!("and_v(vc:pk_k({}),c:pk_k({}))", keys[0], keys[1]))
+                .unwrap();
+        let decoded = Segwitv0Script::parse_insane(&ms.encode()).unwrap();
+        assert_eq!(ms.fingerprint(), alias.fingerprint());
+        assert_eq!(ms.fingerprint(), decoded.fingerprint());
+        assert_eq!(
+            ms.fingerprint(),
+            sha256::Hash::hash(ms.encode().as_bytes()).into_inner()
+        );
+
+        let swapped =
+            Segwitv0Script::from_str(&format!("and_v(v:pk({}),pk({}))", keys[1], keys[0])).unwrap();
+        assert_ne!(ms.fingerprint(), swapped.fingerprint());
+    }
+
+    #[test]
+    fn decode_nonzero_wrappers() {
+        use elements::opcodes;
+
+        let key = pubkeys(1)[0];
+        // j:pk(key): SIZE 0NOTEQUAL IF <key> CHECKSIG ENDIF
+        let script = elements::script::Builder::new()
+            .push_opcode(opcodes::all::OP_SIZE)
+            .push_opcode(opcodes::all::OP_0NOTEQUAL)
+            .push_opcode(opcodes::all::OP_IF)
+            .push_key(&key)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .into_script();
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::parse_insane(&script).unwrap();
+        assert_eq!(ms.to_string(), format!("j:pk({})", key));
+        assert_eq!(ms.encode(), script);
+
+        // n:pk(key): <key> CHECKSIG 0NOTEQUAL
+        let script = elements::script::Builder::new()
+            .push_key(&key)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .push_opcode(opcodes::all::OP_0NOTEQUAL)
+            .into_script();
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::parse_insane(&script).unwrap();
+        assert_eq!(ms.to_string(), format!("n:pk({})", key));
+        assert_eq!(ms.encode(), script);
+
+        // Stacked wrappers keep their letters through script
+        for wrappers in &["jn", "nj", "jnj", "njn"] {
+            let desc = format!("{}:pk({})", wrappers, key);
+            let ms =
+                Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::from_str_insane(&desc).unwrap();
+            let decoded =
+                Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::parse_insane(&ms.encode())
+                    .unwrap();
+            assert_eq!(decoded.to_string(), desc);
+            assert_eq!(decoded, ms);
+        }
+    }
+
+    #[test]
+    fn max_dissatisfaction_size() {
+        use crate::{Error, Legacy};
+
+        // pk(A) is dissatisfied by an empty push
+        let ms = Miniscript::<String, Segwitv0>::from_str("pk(A)").unwrap();
+        assert_eq!(ms.max_dissatisfaction_size(), Ok(1));
+        let ms = Miniscript::<String, Segwitv0>::from_str("or_d(pk(A),pkh(B))").unwrap();
+        assert_eq!(ms.max_dissatisfaction_size(), Ok(1 + 1 + 34));
+        let ms = Miniscript::<String, Legacy>::from_str_insane("or_d(pk(A),pkh(B))").unwrap();
+        assert_eq!(ms.max_dissatisfaction_size(), Ok(1 + 1 + 66));
+        // v: fragments cannot be dissatisfied
+        let ms = Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),pk(B))").unwrap();
+        assert_eq!(
+            ms.max_dissatisfaction_size(),
+            Err(Error::ImpossibleDissatisfaction)
+        );
+    }
+
+    #[test]
+    fn decode_checksigverify() {
+        use elements::opcodes;
+
+        let keys = pubkeys(2);
+        let ms =
+            Segwitv0Script::from_str(&format!("and_v(v:pk({}),pk({}))", keys[0], keys[1])).unwrap();
+        let script = ms.encode();
+        // <A> CHECKSIGVERIFY <B> CHECKSIG
+        let expected = elements::script::Builder::new()
+            .push_key(&keys[0])
+            .push_opcode(opcodes::all::OP_CHECKSIGVERIFY)
+            .push_key(&keys[1])
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(script, expected);
+
+        let decoded = Segwitv0Script::parse_insane(&script).unwrap();
+        assert_eq!(decoded, ms);
+        match decoded.node {
+            Terminal::AndV(ref l, _) => match l.node {
+                Terminal::Verify(ref sub) => {
+                    assert!(matches!(sub.node, Terminal::Check(_)))
+                }
+                ref x => panic!("expected v:, got {:?}", x),
+            },
+            ref x => panic!("expected and_v, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn has_keyless_spend_path() {
+        let keyless = |s: &str| {
+            Miniscript::<String, Segwitv0>::from_str_insane(s)
+                .unwrap()
+                .has_keyless_spend_path()
+        };
+        assert!(!keyless("pk(A)"));
+        assert!(!keyless("and_v(v:pk(A),after(100))"));
+        assert!(!keyless("or_d(pk(A),pk(B))"));
+        assert!(!keyless("thresh(2,pk(A),s:pk(B),sln:older(10))"));
+        assert!(!keyless("andor(pk(A),older(10),pk(B))"));
+
+        assert!(keyless("after(100)"));
+        assert!(keyless("or_d(pk(A),older(10))"));
+        assert!(keyless(
+            "or_i(pk(A),sha256(1111111111111111111111111111111111111111111111111111111111111111))"
+        ));
+        assert!(keyless("thresh(1,pk(A),sln:older(10))"));
+        assert!(keyless(
+            "andor(sha256(1111111111111111111111111111111111111111111111111111111111111111),after(100),pk(B))"
+        ));
+    }
+
+    #[test]
+    fn is_satisfiable_at() {
+        let ms = Miniscript::<String, Segwitv0>::from_str(
+            "or_d(multi(2,A,B),and_v(v:pk(C),after(1000)))",
+        )
+        .unwrap();
+        // The multisig branch is always live
+        assert!(ms.is_satisfiable_at(0, 0, None));
+
+        let ms = Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),after(1000))").unwrap();
+        assert!(!ms.is_satisfiable_at(999, 0, None));
+        assert!(ms.is_satisfiable_at(1000, 0, None));
+        // Heights are not compared against times
+        assert!(!ms.is_satisfiable_at(999, 1_700_000_000, None));
+
+        let ms =
+            Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),after(1600000000))").unwrap();
+        assert!(!ms.is_satisfiable_at(2_000_000, 1_600_000_000, None));
+        assert!(ms.is_satisfiable_at(0, 1_600_000_001, None));
+
+        let ms = Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),older(10))").unwrap();
+        assert!(!ms.is_satisfiable_at(1000, 0, None));
+        assert!(!ms.is_satisfiable_at(1008, 0, Some((1000, 0))));
+        assert!(ms.is_satisfiable_at(1009, 0, Some((1000, 0))));
+
+        // older(4194306) is a relative lock of 2 * 512 seconds
+        let ms = Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),older(4194306))").unwrap();
+        assert!(!ms.is_satisfiable_at(2000, 1023, Some((1000, 0))));
+        assert!(ms.is_satisfiable_at(1000, 1024, Some((1000, 0))));
+
+        let ms =
+            Miniscript::<String, Segwitv0>::from_str("thresh(2,pk(A),s:pk(B),sln:after(1000))")
+                .unwrap();
+        assert!(ms.is_satisfiable_at(0, 0, None));
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "thresh(2,pk(A),sln:older(10),sln:after(1000))",
+        )
+        .unwrap();
+        assert!(!ms.is_satisfiable_at(0, 0, None));
+        assert!(ms.is_satisfiable_at(1000, 0, None));
+    }
+
+    #[test]
+    fn with_locktimes() {
+        use crate::miniscript::analyzable::AnalysisError;
+        use crate::Error;
+
+        let ms = Miniscript::<String, Segwitv0>::from_str(
+            "or_d(pk(A),and_v(v:pk(B),and_v(v:after(1),older(1))))",
+        )
+        .unwrap();
+        let sub = ms.with_locktimes(Some(800_000), Some(144)).unwrap();
+        assert_eq!(
+            sub.to_string(),
+            "or_d(pk(A),and_v(v:pk(B),and_v(v:after(800000),older(144))))"
+        );
+        // Only the requested timelocks are replaced
+        let sub = ms.with_locktimes(None, Some(144)).unwrap();
+        assert_eq!(
+            sub.to_string(),
+            "or_d(pk(A),and_v(v:pk(B),and_v(v:after(1),older(144))))"
+        );
+        // after(0) does not type check
+        assert!(ms.with_locktimes(Some(0), None).is_err());
+
+        // Height and time based timelocks cannot be combined on one path
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "and_v(v:after(1),and_v(v:pk(A),after(1)))",
+        )
+        .unwrap();
+        // A single value keeps all `after` fragments consistent
+        assert!(ms.with_locktimes(Some(500_000_001), None).is_ok());
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "and_v(v:after(1),and_v(v:pk(A),after(500000001)))",
+        )
+        .unwrap();
+        assert!(matches!(
+            ms.with_locktimes(None, None),
+            Err(Error::AnalysisError(
+                AnalysisError::HeightTimelockCombination
+            ))
+        ));
+    }
+
+    #[test]
+    fn sanity_check_all_context_malleability() {
+        use crate::Legacy;
+
+        // `or_i` passes the analysis checks, but is malleable under Legacy rules
+        let ms = Miniscript::<String, Legacy>::from_str("or_i(pk(A),pk(B))").unwrap();
+        assert_eq!(ms.sanity_check(), Ok(()));
+        assert_eq!(
+            ms.sanity_check_all(),
+            Err(vec![crate::Error::ContextError(
+                ScriptContextError::MalleableOrI
+            )])
+        );
+
+        let ms = Miniscript::<String, Segwitv0>::from_str("or_i(pk(A),pk(B))").unwrap();
+        assert_eq!(ms.sanity_check(), Ok(()));
+        assert_eq!(ms.sanity_check_all(), Ok(()));
+    }
+
+    #[test]
+    fn check_non_malleable() {
+        use crate::Legacy;
+
+        let ms = Miniscript::<String, Legacy>::from_str_insane("or_b(pk(A),a:or_i(pk(B),pk(C)))")
+            .unwrap();
+        assert_eq!(
+            ms.check_non_malleable(),
+            Err(ScriptContextError::MalleableOrI)
+        );
+        let ms = Miniscript::<String, Legacy>::from_str_insane("and_v(v:pk(A),pkh(B))").unwrap();
+        assert_eq!(
+            ms.check_non_malleable(),
+            Err(ScriptContextError::MalleablePkH)
+        );
+        let ms = Miniscript::<String, Legacy>::from_str_insane("and_v(v:pk(A),pk(B))").unwrap();
+        assert_eq!(ms.check_non_malleable(), Ok(()));
+
+        let ms =
+            Miniscript::<String, Segwitv0>::from_str("or_b(pk(A),a:or_i(pk(B),pk(C)))").unwrap();
+        assert_eq!(ms.check_non_malleable(), Ok(()));
+    }
+
+    #[test]
+    fn script_context_error_code() {
+        use crate::Legacy;
+
+        let ms = Miniscript::<String, Legacy>::from_str_insane("pkh(A)").unwrap();
+        assert_eq!(
+            ms.check_non_malleable().unwrap_err().code(),
+            "MALLEABLE_PKH"
+        );
+        assert_eq!(ScriptContextError::MaxOpCountExceeded.code(), "MAX_OPS");
+        assert_eq!(
+            ScriptContextError::XOnlyKeysNotAllowed(String::from("A"), "Segwitv0").code(),
+            "XONLY_NOT_ALLOWED"
+        );
+    }
+
+    #[test]
+    fn has_mixed_timelocks() {
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+
+        for ms in &[
+            "and_v(v:after(500),after(1700000000))",
+            "and_v(v:older(10),and_v(v:pk(A),older(4194305)))",
+            "thresh(2,ln:after(500),aln:after(1700000000),a:pk(A))",
+        ] {
+            let ms = SegwitStr::from_str_insane(ms).unwrap();
+            assert!(ms.has_mixed_timelocks());
+        }
+        for ms in &[
+            "or_i(after(500),after(1700000000))",
+            "and_v(v:after(500),older(4194305))",
+            "thresh(1,ln:after(500),aln:after(1700000000))",
+        ] {
+            let ms = SegwitStr::from_str_insane(ms).unwrap();
+            assert!(!ms.has_mixed_timelocks());
+        }
+    }
+
+    #[test]
+    fn script_context_error_same_kind() {
+        let xonly = ScriptContextError::XOnlyKeysNotAllowed(String::from("A"), "Segwitv0");
+        let err = ScriptContextError::XOnlyKeysNotAllowed(String::from("B"), "Legacy");
+        assert_ne!(err, xonly);
+        assert!(err.same_kind(&xonly));
+        assert!(!err.same_kind(&ScriptContextError::CompressedOnly(String::from("A"))));
+
+        let a = ScriptContextError::StackSizeLimitExceeded {
+            actual: 1001,
+            limit: 1000,
+        };
+        let b = ScriptContextError::StackSizeLimitExceeded {
+            actual: 2000,
+            limit: 1000,
+        };
+        assert!(a.same_kind(&b));
+        assert!(!a.same_kind(&ScriptContextError::MaxOpCountExceeded));
+    }
+
+    #[test]
+    fn context_kind() {
+        use crate::miniscript::context::{ContextKind, NoChecks};
+        use crate::{BareCtx, Legacy};
+
+        assert_eq!(Legacy::context_kind(), ContextKind::Legacy);
+        assert_eq!(Segwitv0::context_kind(), ContextKind::Segwitv0);
+        assert_eq!(Tap::context_kind(), ContextKind::Tap);
+        assert_eq!(BareCtx::context_kind(), ContextKind::Bare);
+        assert_eq!(NoChecks::context_kind(), ContextKind::NoChecks);
+
+        assert_eq!(Legacy::name_str(), "Legacy/p2sh");
+        assert_eq!(Tap::name_str(), ContextKind::Tap.to_string());
+
+        // Display round-trips, and lowercase names are accepted for configs
+        for kind in &[
+            ContextKind::Legacy,
+            ContextKind::Segwitv0,
+            ContextKind::Tap,
+            ContextKind::Bare,
+            ContextKind::NoChecks,
+        ] {
+            assert_eq!(ContextKind::from_str(&kind.to_string()).unwrap(), *kind);
+        }
+        assert_eq!(ContextKind::from_str("tap").unwrap(), ContextKind::Tap);
+        assert_eq!(
+            ContextKind::from_str("legacy").unwrap(),
+            ContextKind::Legacy
+        );
+        assert!(ContextKind::from_str("segwit").is_err());
+    }
+
+    #[test]
+    fn decode_verify_thresh() {
+        use elements::opcodes;
+
+        let keys = pubkeys(4);
+        let ms = Segwitv0Script::from_str(&format!(
+            "and_v(v:thresh(2,pk({}),s:pk({}),s:pk({})),pk({}))",
+            keys[0], keys[1], keys[2], keys[3]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        // ... ADD ADD 2 EQUALVERIFY <D> CHECKSIG
+        let tail = elements::script::Builder::new()
+            .push_opcode(opcodes::all::OP_ADD)
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_key(&keys[3])
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        assert!(script.as_bytes().ends_with(tail.as_bytes()));
+
+        let decoded = Segwitv0Script::parse_insane(&script).unwrap();
+        assert_eq!(decoded, ms);
+        match decoded.node {
+            Terminal::AndV(ref l, _) => match l.node {
+                Terminal::Verify(ref sub) => {
+                    assert!(matches!(sub.node, Terminal::Thresh(2, ref subs) if subs.len() == 3))
+                }
+                ref x => panic!("expected v:, got {:?}", x),
+            },
+            ref x => panic!("expected and_v, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn unguarded_hashlocks() {
+        use elements::opcodes;
+
+        use crate::{FnSatisfier, HashKind};
+
+        let preimage = [0x11; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let script = elements::script::Builder::new()
+            .push_opcode(opcodes::all::OP_SHA256)
+            .push_slice(&hash[..])
+            .push_opcode(opcodes::all::OP_EQUAL)
+            .into_script();
+        assert!(Segwitv0Script::parse_insane(&script).is_err());
+
+        let (ms, unguarded) =
+            Segwitv0Script::parse_with_unguarded_hashlocks(&script, &ExtParams::insane()).unwrap();
+        assert_eq!(unguarded, vec![HashKind::Sha256(hash)]);
+        assert_eq!(ms.to_string(), format!("expr_raw_sha256({})", hash));
+        assert_eq!(ms.encode(), script);
+        assert_eq!(
+            Segwitv0Script::from_str_ext(&ms.to_string(), &ExtParams::allow_all()).unwrap(),
+            ms
+        );
+        assert!(Segwitv0Script::from_str_insane(&ms.to_string()).is_err());
+        assert_eq!(ms.script_size(), script.len());
+
+        let wit = ms
+            .satisfy(FnSatisfier::new().with_sha256(|h| Some(preimage).filter(|_| *h == hash)))
+            .unwrap();
+        assert_eq!(wit, vec![preimage.to_vec()]);
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 33);
+
+        // An unguarded hashlock inside a larger script
+        let key = pubkeys(1)[0];
+        let script = elements::script::Builder::new()
+            .push_opcode(opcodes::all::OP_SHA256)
+            .push_slice(&hash[..])
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_key(&key)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let (ms, unguarded) =
+            Segwitv0Script::parse_with_unguarded_hashlocks(&script, &ExtParams::sane()).unwrap();
+        assert_eq!(unguarded, vec![HashKind::Sha256(hash)]);
+        assert_eq!(
+            ms.to_string(),
+            format!("and_v(v:expr_raw_sha256({}),pk({}))", hash, key)
+        );
+        assert_eq!(ms.encode(), script);
+
+        // Canonical scripts are read unchanged
+        let canonical = Segwitv0Script::from_str_insane(&format!("sha256({})", hash)).unwrap();
+        let (ms, unguarded) = Segwitv0Script::parse_with_unguarded_hashlocks(
+            &canonical.encode(),
+            &ExtParams::insane(),
+        )
+        .unwrap();
+        assert!(unguarded.is_empty());
+        assert_eq!(ms, canonical);
+
+        // A guard of another size is not missing, and is rejected
+        let short = elements::script::Builder::new()
+            .push_opcode(opcodes::all::OP_SIZE)
+            .push_int(20)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_SHA256)
+            .push_slice(&hash[..])
+            .push_opcode(opcodes::all::OP_EQUAL)
+            .into_script();
+        assert!(
+            Segwitv0Script::parse_with_unguarded_hashlocks(&short, &ExtParams::insane()).is_err()
+        );
+    }
+
+    #[test]
+    fn short_hashlock_guards() {
+        use elements::hashes::ripemd160;
+        use elements::opcodes;
+
+        use crate::HashKind;
+
+        let hashlock = |size: i64, hash_op| {
+            elements::script::Builder::new()
+                .push_opcode(opcodes::all::OP_SIZE)
+                .push_int(size)
+                .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                .push_opcode(hash_op)
+                .push_slice(&[0x11; 20])
+                .push_opcode(opcodes::all::OP_EQUAL)
+                .into_script()
+        };
+
+        for (hash_op, hash, expected) in &[
+            (
+                opcodes::all::OP_RIPEMD160,
+                HashKind::Ripemd160(ripemd160::Hash::from_inner([0x11; 20])),
+                "expr_raw_ripemd160(1111111111111111111111111111111111111111,20)",
+            ),
+            (
+                opcodes::all::OP_HASH160,
+                HashKind::Hash160(hash160::Hash::from_inner([0x11; 20])),
+                "expr_raw_hash160(1111111111111111111111111111111111111111,20)",
+            ),
+        ] {
+            let short = hashlock(20, *hash_op);
+            assert!(Segwitv0Script::parse_insane(&short).is_err());
+            let (ms, short_guards) =
+                Segwitv0Script::parse_with_short_hashlock_guards(&short, &ExtParams::insane())
+                    .unwrap();
+            assert_eq!(short_guards, vec![hash.clone()]);
+            assert_eq!(ms.to_string(), *expected);
+            assert_eq!(ms.encode(), short);
+            assert_eq!(ms.script_size(), short.len());
+            // Only a 20 byte preimage passes the guard, which the satisfier
+            // cannot provide
+            assert!(ms.satisfy(()).is_err());
+
+            // Canonical guards are read unchanged
+            let canonical = hashlock(32, *hash_op);
+            let (ms, short_guards) =
+                Segwitv0Script::parse_with_short_hashlock_guards(&canonical, &ExtParams::insane())
+                    .unwrap();
+            assert!(short_guards.is_empty());
+            assert_eq!(ms.encode(), canonical);
+
+            // Guards of other sizes are rejected
+            let other = hashlock(16, *hash_op);
+            assert!(
+                Segwitv0Script::parse_with_short_hashlock_guards(&other, &ExtParams::insane())
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn try_from_script() {
+        use std::convert::TryFrom;
+
+        use crate::{BareCtx, Error};
+
+        let key = pubkeys(1)[0];
+        let ms = Segwitv0Script::from_str(&format!("pk({})", key)).unwrap();
+        let script = ms.encode();
+        assert_eq!(Segwitv0Script::try_from(&script).unwrap(), ms);
+
+        // Lexing errors are reported separately from type check errors
+        let invalid_op = hex_script("50");
+        assert!(matches!(
+            Segwitv0Script::try_from(&invalid_op),
+            Err(Error::InvalidOpcode(_))
+        ));
+
+        // Bare context only allows a few standard scripts at the top level
+        type BareScript = Miniscript<bitcoin::PublicKey, BareCtx>;
+        let ms = BareScript::from_str_insane(&format!("and_v(v:1,pk({}))", key)).unwrap();
+        assert!(BareScript::try_from(&ms.encode()).is_err());
+    }
+
+    #[test]
+    fn parse_one_leaves_remaining_tokens() {
+        use crate::miniscript::decode;
+        use crate::miniscript::lex::{lex, Token, TokenIter};
+
+        // `OP_1` is a complete fragment, preceded by a dangling `OP_IF`
+        let script = hex_script("6351");
+        let tokens = lex(&script).unwrap();
+        let mut iter = TokenIter::new(tokens);
+        let (ms, rest) = decode::parse_one::<Segwitv0, NoExt>(&mut iter).unwrap();
+        assert_eq!(ms.node, Terminal::True);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest.position(), 1);
+        assert_eq!(rest.next(), Some(Token::If));
+        assert_eq!(rest.position(), 2);
+        rest.un_next(Token::If);
+        assert_eq!(rest.position(), 1);
+    }
+
+    #[test]
+    fn parse_collect_errors() {
+        use elements::opcodes;
+
+        use crate::miniscript::decode;
+        use crate::miniscript::lex::{lex, TokenIter};
+
+        // or_i(and_b(pk(A),pk(B)),and_b(pk(C),pk(D))): both and_b have a B
+        // fragment where a W fragment is expected
+        let keys = pubkeys(4);
+        let and_b = |b: elements::script::Builder, x, y| {
+            b.push_key(x)
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+                .push_key(y)
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+                .push_opcode(opcodes::all::OP_BOOLAND)
+        };
+        let b = elements::script::Builder::new().push_opcode(opcodes::all::OP_IF);
+        let b = and_b(b, &keys[0], &keys[1]).push_opcode(opcodes::all::OP_ELSE);
+        let script = and_b(b, &keys[2], &keys[3])
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .into_script();
+
+        let mut iter = TokenIter::new(lex(&script).unwrap());
+        let errs = decode::parse_collect_errors::<Segwitv0, NoExt>(&mut iter).unwrap_err();
+        // The or_i error is caused by its children and is not reported
+        assert_eq!(errs.len(), 2);
+        assert!(errs.iter().all(|e| matches!(e, crate::Error::TypeCheck(_))));
+
+        // Parsing fails fast on the first error
+        let mut iter = TokenIter::new(lex(&script).unwrap());
+        let err = decode::parse::<Segwitv0, NoExt>(&mut iter).unwrap_err();
+        assert_eq!(err.to_string(), errs[0].to_string());
+
+        // Valid scripts parse as usual
+        let ms = Segwitv0Script::from_str_insane(&format!("pk({})", keys[0])).unwrap();
+        let mut iter = TokenIter::new(lex(&ms.encode()).unwrap());
+        assert_eq!(
+            decode::parse_collect_errors::<Segwitv0, NoExt>(&mut iter).unwrap(),
+            ms
+        );
+    }
+
+    #[test]
+    fn parser_feed() {
+        use crate::miniscript::decode::{self, Parser};
+        use crate::miniscript::lex::{lex, Token, TokenIter};
+
+        let keys = pubkeys(3);
+        let policies = [
+            format!("pk({})", keys[0]),
+            format!(
+                "and_v(v:pk({}),or_d(pk({}),older(12960)))",
+                keys[0], keys[1]
+            ),
+            format!(
+                "andor(pk({}),multi(1,{},{}),after(10))",
+                keys[0], keys[1], keys[2]
+            ),
+            format!(
+                "thresh(2,pk({}),s:pk({}),a:sha256({}))",
+                keys[0],
+                keys[1],
+                "ff".repeat(32)
+            ),
+        ];
+        for policy in &policies {
+            let ms = Segwitv0Script::from_str_insane(policy).unwrap();
+            let script = ms.encode();
+            for &chunk_size in &[1, 2, 3, 5] {
+                let mut tokens = lex(&script).unwrap();
+                let mut iter = TokenIter::new(vec![]);
+                let mut parser = Parser::<Segwitv0, NoExt>::new();
+                // Feed the script from its end, a few tokens at a time
+                while !tokens.is_empty() {
+                    let split = tokens.len().saturating_sub(chunk_size);
+                    let chunk = tokens.split_off(split);
+                    parser.feed(&mut iter, chunk).unwrap();
+                }
+                assert_eq!(parser.finish(&mut iter).unwrap(), ms);
+                assert!(iter.is_empty());
+
+                let mut iter = TokenIter::new(lex(&script).unwrap());
+                assert_eq!(decode::parse::<Segwitv0, NoExt>(&mut iter).unwrap(), ms);
+            }
+        }
+
+        // A chunk boundary inside the `SIZE 32 EQUALVERIFY` guard of a
+        // hashlock must not turn it into a raw hashlock
+        let ms = Segwitv0Script::from_str_insane(&format!("sha256({})", "ff".repeat(32))).unwrap();
+        let mut tokens = lex(&ms.encode()).unwrap();
+        assert_eq!(tokens[..2], [Token::Size, Token::Num(32)]);
+        let mut iter = TokenIter::new(vec![]);
+        let mut parser = Parser::<Segwitv0, NoExt>::new();
+        let chunk = tokens.split_off(2);
+        parser.feed(&mut iter, chunk).unwrap();
+        parser.feed(&mut iter, tokens).unwrap();
+        assert_eq!(parser.finish(&mut iter).unwrap(), ms);
+
+        // Errors which do not depend on earlier tokens are reported by `feed`
+        let script = hex_script("515187"); // 1 1 EQUAL
+        let mut iter = TokenIter::new(vec![]);
+        let mut parser = Parser::<Segwitv0, NoExt>::new();
+        assert!(parser.feed(&mut iter, lex(&script).unwrap()).is_err());
+    }
+
+    #[test]
+    fn unbalanced_alt_stack() {
+        use crate::Error;
+
+        // and_b(1,a:1)
+        assert!(Segwitv0Script::parse_insane(&hex_script("516b516c9a")).is_ok());
+        for script in &[
+            "51516c9a",   // FROMALTSTACK without TOALTSTACK
+            "516b519a",   // TOALTSTACK without FROMALTSTACK
+            "516c516b9a", // FROMALTSTACK before TOALTSTACK
+        ] {
+            assert_eq!(
+                Segwitv0Script::parse_insane(&hex_script(script)).unwrap_err(),
+                Error::UnbalancedAltStack
+            );
+        }
+        // Balanced, but not of the form `TOALTSTACK <fragment> FROMALTSTACK`
+        let err = Segwitv0Script::parse_insane(&hex_script("51516b6c9a")).unwrap_err();
+        assert_ne!(err, Error::UnbalancedAltStack);
+    }
+
+    #[test]
+    fn parse_script_size_limit() {
+        use crate::miniscript::lex::lex_with_max_size;
+        use crate::miniscript::limits::{MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_SIZE};
+        use crate::{Error, Legacy};
+
+        // A script of only `OP_1`s, rejected before it is lexed
+        let script = elements::Script::from(vec![0x51; MAX_SCRIPT_SIZE + 1]);
+        assert_eq!(
+            lex_with_max_size(&script, MAX_SCRIPT_SIZE),
+            Err(Error::ScriptSizeTooLarge)
+        );
+        assert_eq!(
+            lex_with_max_size(&script, MAX_SCRIPT_SIZE + 1)
+                .unwrap()
+                .len(),
+            MAX_SCRIPT_SIZE + 1
+        );
+        assert_eq!(
+            Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::parse_insane(&script).unwrap_err(),
+            Error::ScriptSizeTooLarge
+        );
+        // Tapscript leaves are only bounded by the block weight
+        assert_ne!(
+            Miniscript::<XOnlyPublicKey, Tap, NoExt>::parse_insane(&script).unwrap_err(),
+            Error::ScriptSizeTooLarge
+        );
+
+        // Legacy redeem scripts are bounded by the stack element size
+        let script = elements::Script::from(vec![0x51; MAX_SCRIPT_ELEMENT_SIZE + 1]);
+        assert_eq!(
+            Miniscript::<bitcoin::PublicKey, Legacy, NoExt>::parse_insane(&script).unwrap_err(),
+            Error::ScriptSizeTooLarge
+        );
+    }
+
+    #[test]
+    fn parse_malformed_scripts() {
+        type TapScript = Miniscript<XOnlyPublicKey, Tap, CovenantExt<CovExtArgs>>;
+
+        // Truncations of valid scripts, cutting through pushes and fragments
+        let pks = pubkeys(3);
+        let hash = "1111111111111111111111111111111111111111111111111111111111111111";
+        for ms in &[
+            format!("or_i(and_v(v:pk({}),older(144)),pk({}))", pks[0], pks[1]),
+            format!(
+                "andor(pk({}),sha256({}),multi(1,{},{}))",
+                pks[0], hash, pks[1], pks[2]
+            ),
+            format!("thresh(2,pk({}),s:pk({}),sln:after(1000))", pks[0], pks[1]),
+            "and_v(v:ver_eq(2),outputs_pref(01020304))".to_string(),
+        ] {
+            let script = Segwitv0Script::from_str_insane(ms).unwrap().encode();
+            let bytes = script.as_bytes();
+            for i in 0..bytes.len() {
+                for part in &[&bytes[i..], &bytes[..i]] {
+                    let script = elements::Script::from(part.to_vec());
+                    let _ = Segwitv0Script::parse_insane(&script);
+                    let _ = TapScript::parse_insane(&script);
+                }
+            }
+        }
+
+        // Random sequences of the opcodes miniscript uses
+        let ops = [
+            0x00, 0x51, 0x52, 0x60, 0x63, 0x64, 0x67, 0x68, 0x69, 0x6b, 0x6c, 0x73, 0x76, 0x7c,
+            0x82, 0x87, 0x88, 0x93, 0x9a, 0x9c, 0xa8, 0xa9, 0xac, 0xad, 0xae, 0xaf, 0xb1, 0xb2,
+            0xba,
+        ];
+        let mut seed = 1u32;
+        for len in 1..200 {
+            let script = (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    ops[(seed >> 16) as usize % ops.len()]
+                })
+                .collect::<Vec<u8>>();
+            let script = elements::Script::from(script);
+            let _ = Segwitv0Script::parse_insane(&script);
+            let _ = TapScript::parse_insane(&script);
+        }
+    }
+
+    #[test]
+    fn token_iter_remaining() {
+        use crate::miniscript::lex::{lex, Token, TokenIter};
+
+        // `OP_IF OP_1 OP_ENDIF`
+        let mut iter = TokenIter::new(lex(&hex_script("635168")).unwrap());
+        assert_eq!(iter.remaining(), &[Token::If, Token::Num(1), Token::EndIf]);
+        assert_eq!(iter.next(), Some(Token::EndIf));
+        assert_eq!(iter.remaining(), &[Token::If, Token::Num(1)]);
+        assert_eq!(iter.remaining().len(), iter.len());
+    }
+
+    #[test]
+    fn token_iter_checkpoint() {
+        use crate::miniscript::lex::{lex, Token, TokenIter};
+
+        // `OP_IF OP_1 OP_ELSE OP_0 OP_ENDIF`
+        let mut iter = TokenIter::new(lex(&hex_script("6351670068")).unwrap());
+        assert_eq!(iter.next(), Some(Token::EndIf));
+        let checkpoint = iter.checkpoint();
+
+        // Consume several tokens speculatively, then roll back
+        assert_eq!(iter.next(), Some(Token::Num(0)));
+        assert_eq!(iter.next(), Some(Token::Else));
+        assert_eq!(iter.next(), Some(Token::Num(1)));
+        iter.restore(checkpoint);
+        assert_eq!(iter.position(), 1);
+        assert_eq!(
+            iter.remaining(),
+            &[Token::If, Token::Num(1), Token::Else, Token::Num(0)]
+        );
+
+        // Tokens consumed before the checkpoint and pushed back are consumed
+        // again
+        iter.un_next(Token::EndIf);
+        iter.restore(checkpoint);
+        assert_eq!(iter.position(), 1);
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next(), Some(Token::Num(0)));
+
+        // Only the tokens not consumed yet are exposed for modification
+        assert_eq!(
+            iter.as_inner_mut(),
+            &vec![Token::If, Token::Num(1), Token::Else]
+        );
+        iter.restore(checkpoint);
+        assert_eq!(iter.len(), 3);
+    }
+
+    #[test]
+    fn decode_rejects_non_minimal_pushes() {
+        use crate::Error;
+
+        // `<0x05> CSV`: a one byte push of 5 must be encoded as OP_5
+        assert!(matches!(
+            Segwitv0Script::parse_insane(&hex_script("0105b2")),
+            Err(Error::Script(elements::script::Error::NonMinimalPush))
+        ));
+        // `OP_PUSHDATA1 <0x69> CSV`: the push must use the shortest opcode
+        assert!(matches!(
+            Segwitv0Script::parse_insane(&hex_script("4c0169b2")),
+            Err(Error::Script(elements::script::Error::NonMinimalPush))
+        ));
+        // `<0x990300> CSV`: the script number has a redundant zero byte
+        assert!(matches!(
+            Segwitv0Script::parse_insane(&hex_script("03990300b2")),
+            Err(Error::InvalidPush(_))
+        ));
+        // The minimal encodings are accepted
+        assert!(Segwitv0Script::parse_insane(&hex_script("55b2")).is_ok());
+        assert!(Segwitv0Script::parse_insane(&hex_script("029903b2")).is_ok());
+    }
+
+    #[test]
+    fn extension_ctx_checks() {
+        use crate::{BareCtx, Legacy};
+
+        type LegacyCov = Miniscript<bitcoin::PublicKey, Legacy, CovenantExt<CovExtArgs>>;
+        type BareCov = Miniscript<bitcoin::PublicKey, BareCtx, CovenantExt<CovExtArgs>>;
+        type SegwitCov = Miniscript<bitcoin::PublicKey, Segwitv0, CovenantExt<CovExtArgs>>;
+
+        let err = LegacyCov::from_str_insane("ver_eq(4)").unwrap_err();
+        assert_eq!(
+            err.as_context_error(),
+            Some(&ScriptContextError::ExtensionError(String::from(
+                "No Extensions in Legacy context"
+            )))
+        );
+        let err = BareCov::from_str_insane("ver_eq(4)").unwrap_err();
+        assert_eq!(
+            err.as_context_error(),
+            Some(&ScriptContextError::ExtensionError(String::from(
+                "No Extensions in Bare context"
+            )))
+        );
+        assert!(SegwitCov::from_str_insane("ver_eq(4)").is_ok());
+
+        // The same checks run when decoding a covenant script
+        for ms in &["ver_eq(4)", "outputs_pref(aa)"] {
+            let script = SegwitCov::from_str_insane(ms).unwrap().encode();
+            let err = LegacyCov::parse_insane(&script).unwrap_err();
+            assert_eq!(
+                err.as_context_error(),
+                Some(&ScriptContextError::ExtensionError(String::from(
+                    "No Extensions in Legacy context"
+                )))
+            );
+            assert!(LegacyCov::from_str_insane(ms).is_err());
+            assert!(BareCov::parse_insane(&script).is_err());
+            assert!(BareCov::from_str_insane(ms).is_err());
+        }
+    }
+
+    #[test]
+    fn script_size_matches_pk_cost() {
+        for ms in &[
+            "pk(A)",
+            "pkh(A)",
+            "multi(2,A,B,C)",
+            "or_d(pk(A),and_v(v:pk(B),older(144)))",
+            "thresh(2,pk(A),s:pk(B),sln:after(1000000))",
+            "andor(pk(A),sha256(1111111111111111111111111111111111111111111111111111111111111111),pk(B))",
+        ] {
+            let ms = Miniscript::<String, Segwitv0>::from_str_insane(ms).unwrap();
+            assert_eq!(ms.script_size(), ms.ext.pk_cost);
+        }
+    }
+
+    #[test]
+    fn parse_error_position() {
+        // `OP_1 OP_SWAP`: the trailing swap cannot start an expression
+        let err =
+            Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::parse_insane(&hex_script("517c"))
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unexpected «Swap at token 0 from the end of the script»"
+        );
+    }
+
+    #[test]
+    fn decode_tap_multi_position() {
+        use crate::Error;
+
+        let key = "c304c3b5805eecff054c319c545dc6ac2ad44eb70f79dd9570e284c5a62c0f9e";
+        // multi(1,K)
+        let err = Tapscript::parse_insane(&hex_script(&format!("5120{}51ae", key))).unwrap_err();
+        assert_eq!(
+            err,
+            Error::FragmentContextError(ScriptContextError::TaprootMultiDisabled, 0)
+        );
+        assert_eq!(
+            err.as_context_error(),
+            Some(&ScriptContextError::TaprootMultiDisabled)
+        );
+        // and_v(v:multi(1,K),1): the VERIFY and the trailing 1 come first
+        let err = Tapscript::parse_insane(&hex_script(&format!("5120{}51af51", key))).unwrap_err();
+        assert_eq!(
+            err,
+            Error::FragmentContextError(ScriptContextError::TaprootMultiDisabled, 2)
+        );
+        assert_eq!(
+            err.to_string(),
+            "Invalid use of Multi node in taproot context at token 2 from the end of the script"
+        );
+    }
+
+    #[test]
+    fn terminal_classification() {
+        type SegwitCov = Miniscript<String, Segwitv0, CovenantExt<String>>;
+
+        let ms = SegwitCov::from_str_insane(
+            "and_v(v:pk(A),and_v(v:ver_eq(2),and_v(v:sha256(H),or_d(multi(1,B,C),older(144)))))",
+        )
+        .unwrap();
+        let classes: Vec<_> = ms
+            .iter()
+            .filter(|ms| {
+                ms.node.is_timelock()
+                    || ms.node.is_hashlock()
+                    || ms.node.is_key_check()
+                    || ms.node.is_covenant()
+            })
+            .map(|ms| {
+                (
+                    ms.node.is_timelock(),
+                    ms.node.is_hashlock(),
+                    ms.node.is_key_check(),
+                    ms.node.is_covenant(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                (false, false, true, false),
+                (false, false, false, true),
+                (false, true, false, false),
+                (false, false, true, false),
+                (true, false, false, false),
+            ]
+        );
+        assert!(!ms.node.is_key_check());
+    }
+
+    #[test]
+    fn multi_accessors() {
+        let ms = Miniscript::<String, Segwitv0, NoExt>::from_str_insane("multi(2,A,B,C)").unwrap();
+        let keys = ["A".to_owned(), "B".to_owned(), "C".to_owned()];
+        assert_eq!(ms.node.as_multi(), Some((2, &keys[..])));
+        assert_eq!(ms.node.as_multi_a(), None);
+
+        let ms = Miniscript::<String, Tap, NoExt>::from_str_insane("multi_a(1,A,B,C)").unwrap();
+        assert_eq!(ms.node.as_multi_a(), Some((1, &keys[..])));
+        assert_eq!(ms.node.as_multi(), None);
+
+        let ms = Miniscript::<String, Segwitv0, NoExt>::from_str_insane("pk(A)").unwrap();
+        assert_eq!(ms.node.as_multi(), None);
+    }
+
+    #[test]
+    fn for_each_key_short_circuits() {
+        use crate::ForEachKey;
+
+        type TapCov = Miniscript<String, Tap, CovenantExt<CovExtArgs>>;
+        for (ms, visited) in &[
+            ("multi_a(1,A,B,X,C)", 3),
+            ("thresh(2,pk(A),s:pk(X),s:pk(B))", 2),
+            ("and_v(v:ver_eq(2),or_d(pk(A),pk(X)))", 2),
+            ("and_v(v:ver_eq(2),pk(A))", 1),
+        ] {
+            let ms = TapCov::from_str_insane(ms).unwrap();
+            let mut seen = vec![];
+            let all_ours = ms.for_each_key(|pk| {
+                seen.push(pk.clone());
+                pk != "X"
+            });
+            assert_eq!(all_ours, !seen.contains(&"X".to_owned()));
+            assert_eq!(seen.len(), *visited);
+        }
+    }
+
+    #[test]
+    fn opcode_template() {
+        use elements::opcodes::all::*;
+
+        use crate::miniscript::astelem::ScriptElement::{self, Child, Hash, Key, KeyHash, Num, Op};
+
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+        let template = |s: &str| {
+            SegwitStr::from_str_insane(s)
+                .unwrap()
+                .node
+                .opcode_template()
+        };
+
+        assert_eq!(template("pk_k(A)"), vec![Key(0)]);
+        assert_eq!(
+            template("pk_h(A)"),
+            vec![Op(OP_DUP), Op(OP_HASH160), KeyHash, Op(OP_EQUALVERIFY)]
+        );
+        assert_eq!(template("older(144)"), vec![Num(144), Op(OP_CSV)]);
+        assert_eq!(
+            template("ripemd160(1111111111111111111111111111111111111111)"),
+            vec![
+                Op(OP_SIZE),
+                Num(32),
+                Op(OP_EQUALVERIFY),
+                Op(OP_RIPEMD160),
+                Hash,
+                Op(OP_EQUAL)
+            ]
+        );
+        assert_eq!(
+            template("or_d(pk(A),pk(B))"),
+            vec![Child(0), Op(OP_IFDUP), Op(OP_NOTIF), Child(1), Op(OP_ENDIF)]
+        );
+        assert_eq!(
+            template("thresh(2,pk(A),s:pk(B),s:pk(C))"),
+            vec![
+                Child(0),
+                Child(1),
+                Op(OP_ADD),
+                Child(2),
+                Op(OP_ADD),
+                Num(2),
+                Op(OP_EQUAL)
+            ]
+        );
+        assert_eq!(
+            template("multi(1,A,B)"),
+            vec![Num(1), Key(0), Key(1), Num(2), Op(OP_CHECKMULTISIG)]
+        );
+        let ms = Miniscript::<String, Tap, NoExt>::from_str_insane("multi_a(1,A,B)").unwrap();
+        assert_eq!(
+            ms.node.opcode_template(),
+            vec![
+                Key(0),
+                Op(OP_CHECKSIG),
+                Key(1),
+                Op(OP_CHECKSIGADD),
+                Num(1),
+                Op(OP_NUMEQUAL)
+            ]
+        );
+        let ms =
+            Miniscript::<String, Segwitv0, CovenantExt<CovExtArgs>>::from_str_insane("ver_eq(4)")
+                .unwrap();
+        assert_eq!(ms.node.opcode_template(), vec![ScriptElement::Ext]);
+
+        // Every child appears once, in order of appearance in the script
+        let ms = SegwitStr::from_str_insane("andor(pk(A),pk(B),pk(C))").unwrap();
+        let children: Vec<_> = ms
+            .node
+            .opcode_template()
+            .into_iter()
+            .filter_map(|e| match e {
+                Child(i) => Some(i),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(children, vec![0, 2, 1]);
+    }
+
+    /// Build the script of `ms` from its opcode template, taking from the
+    /// fragments only the keys, hashes and extensions the template leaves out
+    fn expand_opcode_template<Pk, Ctx, Ext>(
+        ms: &Miniscript<Pk, Ctx, Ext>,
+        mut builder: elements::script::Builder,
+    ) -> elements::script::Builder
+    where
+        Pk: ToPublicKey,
+        Ctx: ScriptContext,
+        Ext: crate::extensions::ParseableExt,
+    {
+        use elements::opcodes::all::OP_VERIFY;
+
+        use crate::miniscript::analyzable::HashKind;
+        use crate::miniscript::astelem::ScriptElement;
+        use crate::util::MsKeyBuilder;
+
+        for elem in ms.node.opcode_template() {
+            builder = match elem {
+                // Merged into the preceding opcode where possible, as the
+                // template documents
+                ScriptElement::Op(op) if op == OP_VERIFY => builder.push_verify(),
+                ScriptElement::Op(op) => builder.push_opcode(op),
+                ScriptElement::Num(n) => builder.push_int(n),
+                ScriptElement::Key(i) => match ms.node {
+                    Terminal::PkK(ref pk) => builder.push_ms_key::<_, Ctx>(pk),
+                    Terminal::Multi(_, ref keys) => builder.push_key(&keys[i].to_public_key()),
+                    Terminal::MultiA(_, ref keys) => builder.push_ms_key::<_, Ctx>(&keys[i]),
+                    _ => unreachable!("no keys in {}", ms),
+                },
+                ScriptElement::KeyHash => match ms.node {
+                    Terminal::PkH(ref pk) => builder.push_ms_key_hash::<_, Ctx>(pk),
+                    Terminal::RawPkH(ref hash) => builder.push_slice(hash),
+                    _ => unreachable!("no key hash in {}", ms),
+                },
+                ScriptElement::Hash => match ms.node {
+                    Terminal::Sha256(ref h) | Terminal::RawHashLock(HashKind::Sha256(ref h), _) => {
+                        builder.push_slice(&Pk::to_sha256(h))
+                    }
+                    Terminal::Hash256(ref h)
+                    | Terminal::RawHashLock(HashKind::Hash256(ref h), _) => {
+                        builder.push_slice(&Pk::to_hash256(h))
+                    }
+                    Terminal::Ripemd160(ref h)
+                    | Terminal::RawHashLock(HashKind::Ripemd160(ref h), _) => {
+                        builder.push_slice(&Pk::to_ripemd160(h))
+                    }
+                    Terminal::Hash160(ref h)
+                    | Terminal::RawHashLock(HashKind::Hash160(ref h), _) => {
+                        builder.push_slice(&Pk::to_hash160(h))
+                    }
+                    _ => unreachable!("no hash in {}", ms),
+                },
+                ScriptElement::Child(i) => expand_opcode_template(ms.branches()[i], builder),
+                ScriptElement::Ext => ms.node.encode(builder),
+            };
+        }
+        builder
+    }
+
+    /// The name of the fragment, with one arm per variant so that a new
+    /// variant has to be added to the opcode template test
+    fn terminal_variant<Pk: crate::MiniscriptKey, Ctx: ScriptContext, Ext: crate::Extension>(
+        node: &Terminal<Pk, Ctx, Ext>,
+    ) -> &'static str {
+        match *node {
+            Terminal::True => "1",
+            Terminal::False => "0",
+            Terminal::PkK(..) => "pk_k",
+            Terminal::PkH(..) => "pk_h",
+            Terminal::RawPkH(..) => "expr_raw_pkh",
+            Terminal::After(..) => "after",
+            Terminal::Older(..) => "older",
+            Terminal::Sha256(..) => "sha256",
+            Terminal::Hash256(..) => "hash256",
+            Terminal::Ripemd160(..) => "ripemd160",
+            Terminal::Hash160(..) => "hash160",
+            Terminal::RawHashLock(..) => "expr_raw_hashlock",
+            Terminal::Alt(..) => "a",
+            Terminal::Swap(..) => "s",
+            Terminal::Check(..) => "c",
+            Terminal::DupIf(..) => "d",
+            Terminal::Verify(..) => "v",
+            Terminal::NonZero(..) => "j",
+            Terminal::ZeroNotEqual(..) => "n",
+            Terminal::AndV(..) => "and_v",
+            Terminal::AndB(..) => "and_b",
+            Terminal::AndOr(..) => "andor",
+            Terminal::OrB(..) => "or_b",
+            Terminal::OrD(..) => "or_d",
+            Terminal::OrC(..) => "or_c",
+            Terminal::OrI(..) => "or_i",
+            Terminal::Thresh(..) => "thresh",
+            Terminal::Multi(..) => "multi",
+            Terminal::MultiA(..) => "multi_a",
+            Terminal::Ext(..) => "ext",
+        }
+    }
+
+    #[test]
+    fn opcode_template_matches_encode() {
+        type SegwitMs = Miniscript<bitcoin::PublicKey, Segwitv0, CovenantExt<CovExtArgs>>;
+        type TapMs = Miniscript<XOnlyPublicKey, Tap, CovenantExt<CovExtArgs>>;
+
+        let (a, b, c) = (
+            "028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa",
+            "02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e",
+            "03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729",
+        );
+        let (x, y) = (
+            "2788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99",
+            "8c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa",
+        );
+        let h32 = "1111111111111111111111111111111111111111111111111111111111111111";
+        let h20 = "2222222222222222222222222222222222222222";
+
+        let mut seen = std::collections::HashSet::new();
+        for ms in &[
+            format!("and_v(v:pk({}),pk_h({}))", a, b),
+            format!("andor(c:expr_raw_pkh({}),1,0)", h20),
+            "and_v(v:after(100),older(10))".to_owned(),
+            format!("or_b(sha256({}),a:hash256({}))", h32, h32),
+            format!("or_d(ripemd160({}),hash160({}))", h20, h20),
+            format!("or_i(dv:older(1),j:pk({}))", a),
+            format!("and_v(or_c(pk({}),v:1),1)", a),
+            format!("and_b(pk({}),s:pk({}))", a, b),
+            format!("and_v(v:pk({}),n:pk({}))", a, b),
+            format!("thresh(2,pk({}),s:pk({}),s:pk({}))", a, b, c),
+            format!("multi(1,{},{})", a, b),
+            format!(
+                "and_v(v:expr_raw_sha256({}),expr_raw_hash256({},31))",
+                h32, h32
+            ),
+            format!(
+                "or_d(expr_raw_ripemd160({},20),expr_raw_hash160({}))",
+                h20, h20
+            ),
+            format!("and_v(v:pk({}),ver_eq(4))", a),
+        ] {
+            let ms = SegwitMs::from_str_ext(ms, &ExtParams::allow_all()).unwrap();
+            let script = expand_opcode_template(&ms, elements::script::Builder::new());
+            assert_eq!(script.into_script(), ms.encode(), "{}", ms);
+            seen.extend(ms.iter().map(|sub| terminal_variant(&sub.node)));
+        }
+        for ms in &[format!("multi_a(1,{},{})", x, y), format!("pk({})", x)] {
+            let ms = TapMs::from_str_ext(ms, &ExtParams::allow_all()).unwrap();
+            let script = expand_opcode_template(&ms, elements::script::Builder::new());
+            assert_eq!(script.into_script(), ms.encode(), "{}", ms);
+            seen.extend(ms.iter().map(|sub| terminal_variant(&sub.node)));
+        }
+        // Every variant of `terminal_variant` is covered
+        assert_eq!(seen.len(), 30, "{:?}", seen);
+    }
+
+    #[test]
+    fn multi_keys_sorted() {
+        let (a, b, c) = (
+            "028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa",
+            "02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e",
+            "03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729",
+        );
+        for (ms, sorted) in &[
+            (format!("multi(1,{},{})", a, b), Some(true)),
+            (format!("multi(1,{},{})", b, a), Some(false)),
+            (
+                format!("and_v(v:multi(1,{}),multi(1,{},{}))", c, a, b),
+                Some(true),
+            ),
+            (
+                format!("and_v(v:multi(1,{},{}),multi(1,{}))", b, a, c),
+                Some(false),
+            ),
+            (format!("pk({})", a), None),
+        ] {
+            let ms =
+                Miniscript::<bitcoin::PublicKey, Segwitv0, NoExt>::from_str_insane(ms).unwrap();
+            assert_eq!(ms.multi_keys_sorted(), *sorted);
+            // The key order survives a script roundtrip
+            let ms = Miniscript::<_, Segwitv0, NoExt>::parse_insane(&ms.encode()).unwrap();
+            assert_eq!(ms.multi_keys_sorted(), *sorted);
+        }
+    }
+
+    #[test]
+    fn num_satisfaction_paths() {
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+        type TapStr = Miniscript<String, Tap, NoExt>;
+
+        for (ms, paths) in &[
+            ("pk(A)", 1),
+            ("or_d(pk(A),older(144))", 2),
+            ("and_v(v:pk(A),or_d(pk(B),older(144)))", 2),
+            ("or_i(and_v(v:pk(A),pk(B)),or_d(pk(C),older(144)))", 3),
+            ("andor(pk(A),or_i(pk(B),pk(C)),pk(D))", 3),
+            ("multi(2,A,B,C)", 3),
+            ("multi(3,A,B,C,D,E)", 10),
+            ("thresh(2,pk(A),s:pk(B),s:pk(C))", 3),
+            ("thresh(2,pk(A),s:pk(D),a:or_i(pk(B),pk(C)))", 5),
+            ("and_v(v:pk(A),0)", 0),
+        ] {
+            let ms = SegwitStr::from_str_insane(ms).unwrap();
+            assert_eq!(ms.num_satisfaction_paths(), *paths, "{}", ms);
+        }
+
+        // 100 choose 50 does not fit in a u64
+        let keys = (0..100).map(|i| format!("K{}", i)).collect::<Vec<_>>();
+        let ms = TapStr::from_str_insane(&format!("multi_a(50,{})", keys.join(","))).unwrap();
+        assert_eq!(ms.num_satisfaction_paths(), u64::MAX);
+        let ms = TapStr::from_str_insane(&format!("multi_a(3,{})", keys.join(","))).unwrap();
+        assert_eq!(ms.num_satisfaction_paths(), 161700);
+    }
+
+    #[test]
+    fn depth_and_node_count() {
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+
+        for (ms, depth, nodes) in &[
+            ("pk_k(A)", 1, 1),
+            ("pk(A)", 2, 2),
+            ("multi(2,A,B,C)", 1, 1),
+            ("and_v(v:pk(A),or_d(pk(B),older(144)))", 4, 8),
+            ("thresh(2,pk(A),s:pk(B),s:pk(C))", 4, 9),
+        ] {
+            let ms = SegwitStr::from_str_insane(ms).unwrap();
+            assert_eq!(ms.depth(), *depth, "{}", ms);
+            assert_eq!(ms.node_count(), *nodes, "{}", ms);
+        }
+    }
+
+    #[test]
+    fn find_constant_subtrees() {
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+
+        let ms = SegwitStr::from_str_insane("or_d(pk(A),0)").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![1], false)]);
+        assert_eq!(ms.get_nth_child(1).unwrap().to_string(), "0");
+
+        let ms = SegwitStr::from_str_insane("and_v(v:pk(A),or_b(pk(B),s:pk(C)))").unwrap();
+        assert!(ms.find_constant_subtrees().is_empty());
+
+        // Only the outermost constant subtree is reported
+        let ms = SegwitStr::from_str_insane("or_d(pk(A),and_v(v:1,0))").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![1], false)]);
+        let ms = SegwitStr::from_str_insane("and_v(v:pk(A),thresh(2,pk(B),a:0,a:0))").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![1], false)]);
+
+        // Either branch of an or_i may be taken
+        let ms = SegwitStr::from_str_insane("or_i(1,pk(A))").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![0], true)]);
+        let ms = SegwitStr::from_str_insane("or_i(1,1)").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![], true)]);
+        let ms = SegwitStr::from_str_insane("or_i(0,0)").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![], false)]);
+        // The `0` is that of a u: wrapper
+        let ms = SegwitStr::from_str_insane("or_i(1,0)").unwrap();
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![0], true)]);
+
+        // A sub which is always true can never be dissatisfied, so no thresh
+        // with one type checks. Build such thresholds without type checking
+        let one = Arc::new(SegwitStr::from_str_insane("1").unwrap());
+        let zero = Arc::new(SegwitStr::from_str_insane("a:0").unwrap());
+        let pk = Arc::new(SegwitStr::from_str_insane("s:pk(A)").unwrap());
+        let thresh = |k, subs: Vec<Arc<SegwitStr>>| Miniscript {
+            node: Terminal::Thresh(k, subs),
+            ty: one.ty,
+            ext: one.ext,
+            phantom: PhantomData,
+        };
+        // Exactly k subs must be true, so more true subs is always false
+        let ms = thresh(1, vec![one.clone(), one.clone(), pk.clone()]);
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![], false)]);
+        // k true subs with others undetermined depends on the others
+        let ms = thresh(1, vec![one.clone(), pk]);
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![0], true)]);
+        let ms = thresh(1, vec![one.clone(), zero]);
+        assert_eq!(ms.find_constant_subtrees(), vec![(vec![], true)]);
+
+        // The constants of the l:, u: and t: wrappers are not dead branches
+        let ms = SegwitStr::from_str_insane("or_d(pk(A),l:after(100))").unwrap();
+        assert!(ms.find_constant_subtrees().is_empty());
+        let ms = SegwitStr::from_str_insane("or_d(pk(A),u:after(100))").unwrap();
+        assert!(ms.find_constant_subtrees().is_empty());
+        let ms = SegwitStr::from_str_insane("or_d(pk(A),t:v:pk(B))").unwrap();
+        assert!(ms.find_constant_subtrees().is_empty());
+    }
+
+    #[test]
+    fn leaf_hash() {
+        use elements::taproot::LeafVersion;
+
+        let ms = Miniscript::<String, Tap, NoExt>::from_str_insane("and_v(v:pk(A),older(144))")
+            .unwrap()
+            .translate_pk(&mut StrXOnlyKeyTranslator::new())
+            .unwrap();
+        let script = ms.encode();
+        assert_eq!(
+            ms.leaf_hash(),
+            TapLeafHash::from_script(&script, LeafVersion::default())
+        );
+
+        let version = LeafVersion::from_u8(0xc0).unwrap();
+        assert_eq!(
+            ms.leaf_hash_with_version(version),
+            TapLeafHash::from_script(&script, version)
+        );
+        assert_ne!(ms.leaf_hash_with_version(version), ms.leaf_hash());
+    }
+
+    #[test]
+    fn satisfier_lookup_tx() {
+        use crate::extensions::TxEnv;
+
+        let tx = elements::Transaction {
+            version: 2,
+            lock_time: elements::PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let env = TxEnv::new(&tx, &[], 0).unwrap();
+
+        // Satisfiers that know nothing about the transaction default to `None`
+        assert_eq!(Satisfier::<bitcoin::PublicKey>::lookup_tx(&()), None);
+        assert_eq!(Satisfier::<bitcoin::PublicKey>::lookup_tx(&env), Some(&tx));
+        // Tuples of satisfiers forward to the member that has it
+        let sat = (Sequence::MAX, &env);
+        assert_eq!(Satisfier::<bitcoin::PublicKey>::lookup_tx(&sat), Some(&tx));
+    }
+
+    #[test]
+    fn hashlock_kinds() {
+        use crate::HashKind;
+
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+        let ms = SegwitStr::from_str_insane(
+            "or_d(pk(A),and_v(v:sha256(H1),or_b(hash160(H2),a:and_v(v:sha256(H1),sha256(H3)))))",
+        )
+        .unwrap();
+        let kinds = ms.hashlock_kinds();
+        assert_eq!(kinds.len(), 3);
+        assert!(kinds.contains(&HashKind::Sha256(String::from("H1"))));
+        assert!(kinds.contains(&HashKind::Sha256(String::from("H3"))));
+        assert!(kinds.contains(&HashKind::Hash160(String::from("H2"))));
+
+        let ms = SegwitStr::from_str_insane("and_v(v:pk(A),older(144))").unwrap();
+        assert!(ms.hashlock_kinds().is_empty());
+    }
+
+    #[test]
+    fn tap_script_size_limit() {
+        type TapMs = Miniscript<String, Tap>;
+
+        let keys = (0..300).map(|i| format!("K{}", i)).collect::<Vec<_>>();
+        let ms = TapMs::from_str_insane(&format!("multi_a(1,{})", keys[..250].join(","))).unwrap();
+        assert!(ms.script_size() < 10_000);
+        assert_eq!(ms.check_leaf_size(10_000), Ok(()));
+        // Larger leaves are only rejected when the caller asks for the bound
+        let ms = TapMs::from_str_insane(&format!("multi_a(1,{})", keys.join(","))).unwrap();
+        assert!(ms.script_size() > 10_000);
+        assert_eq!(
+            ms.check_leaf_size(10_000),
+            Err(ScriptContextError::MaxWitnessScriptSizeExceeded)
+        );
+        assert_eq!(ms.check_leaf_size(ms.script_size()), Ok(()));
+    }
+
+    #[test]
+    fn op_count_limit() {
+        use crate::{BareCtx, Legacy};
+
+        assert_eq!(Legacy::op_count_limit(), Some(201));
+        assert_eq!(BareCtx::op_count_limit(), Some(201));
+        assert_eq!(Segwitv0::op_count_limit(), Some(201));
+        assert_eq!(Tap::op_count_limit(), None);
+
+        // 3 opcodes per sub: SWAP CHECKSIG ADD
+        let thresh = format!("thresh(1,pk(A){})", ",s:pk(A)".repeat(100));
+        let ms = Miniscript::<String, Segwitv0, NoExt>::from_str_insane(&thresh).unwrap();
+        assert_eq!(
+            Segwitv0::check_local_consensus_validity(&ms),
+            Err(ScriptContextError::MaxOpCountExceeded)
+        );
+        let ms = Miniscript::<String, Tap, NoExt>::from_str_insane(&thresh).unwrap();
+        assert_eq!(Tap::check_local_consensus_validity(&ms), Ok(()));
+    }
+
+    #[test]
+    fn tap_unsatisfiable_stack_check() {
+        type TapStr = Miniscript<String, Tap, NoExt>;
+
+        // Without a satisfaction there is no stack usage to bound
+        let ms = TapStr::from_str_insane("and_v(v:pk(A),0)").unwrap();
+        assert_eq!(ms.max_stack_elements(), None);
+        assert_eq!(Tap::check_local_consensus_validity(&ms), Ok(()));
+        assert_eq!(
+            Tap::check_local_policy_validity(&ms),
+            Err(ScriptContextError::ImpossibleSatisfaction)
+        );
+        assert!(ms.sanity_check().is_err());
+
+        let ms = TapStr::from_str_insane("or_d(pk(A),0)").unwrap();
+        assert_eq!(Tap::check_local_consensus_validity(&ms), Ok(()));
+        assert_eq!(Tap::check_local_policy_validity(&ms), Ok(()));
+    }
+
+    #[test]
+    fn min_satisfaction_size() {
+        use crate::Legacy;
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+        type LegacyStr = Miniscript<String, Legacy, NoExt>;
+        type TapStr = Miniscript<String, Tap, NoExt>;
+
+        // The timelock path only needs the dissatisfaction of the multisig
+        let ms = SegwitStr::from_str_insane("or_d(multi(2,A,B,C),older(144))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 1 + 2);
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 1 + 73 * 2);
+        let ms = LegacyStr::from_str_insane("or_d(multi(2,A,B,C),older(144))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 1 + 2);
+        let ms = TapStr::from_str_insane("or_d(pk(A),older(144))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 1);
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 66);
+
+        // Selecting the left branch costs a 2 byte witness element
+        let ms = SegwitStr::from_str_insane("or_i(pk(A),and_v(v:pk(B),pk(C)))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 73 + 2);
+        let ms = LegacyStr::from_str_insane("or_i(pk(A),and_v(v:pk(B),pk(C)))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 73 + 1);
+
+        // Satisfy the two cheapest subs and dissatisfy the others. Satisfying
+        // the timelock is cheaper than dissatisfying it
+        let ms = SegwitStr::from_str_insane("thresh(2,pk(A),s:pk(B),sln:older(144))").unwrap();
+        assert_eq!(ms.min_satisfaction_size().unwrap(), 73 + 1 + 1);
+
+        let ms = SegwitStr::from_str_insane("and_v(v:pk(A),0)").unwrap();
+        assert_eq!(
+            ms.min_satisfaction_size(),
+            Err(crate::Error::ImpossibleSatisfaction)
+        );
+    }
+
+    #[test]
+    fn script_hex() {
+        // c:pk_k(key) with a compressed key
+        let hex = "21028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caaac";
+        let ms = Segwitv0Script::from_script_hex(hex).unwrap();
+        assert_eq!(ms.to_script_hex(), hex);
+        assert_eq!(ms, Segwitv0Script::parse(&hex_script(hex)).unwrap());
+
+        assert!(Segwitv0Script::from_script_hex("21028c").is_err());
+        assert!(Segwitv0Script::from_script_hex("not hex").is_err());
+        // 1, not sane as it requires no signature
+        assert!(Segwitv0Script::from_script_hex("51").is_err());
+    }
+
+    #[test]
+    fn repeated_keys() {
+        use crate::{AnalysisError, Error};
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+        type TapStr = Miniscript<String, Tap, NoExt>;
+
+        let repeated = Err(Error::AnalysisError(AnalysisError::RepeatedPubkeys));
+        // Within a single multi/multi_a and across sibling key fragments
+        assert_eq!(SegwitStr::from_str("multi(2,A,A,B)"), repeated);
+        assert_eq!(TapStr::from_str("multi_a(2,A,A,B)"), repeated);
+        assert_eq!(
+            SegwitStr::from_str("thresh(2,pk(A),s:pk(B),s:pk(A))"),
+            repeated
+        );
+        assert_eq!(SegwitStr::from_str("or_d(pk(A),pkh(A))"), repeated);
+
+        // Repeated keys are opt-in
+        let allow = ExtParams::new().repeated_pk();
+        assert!(SegwitStr::from_str_ext("multi(2,A,A,B)", &allow).is_ok());
+        assert!(TapStr::from_str_ext("multi_a(2,A,A,B)", &allow).is_ok());
+    }
+
+    #[test]
+    fn normalize() {
+        type SegwitStr = Miniscript<String, Segwitv0, NoExt>;
+
+        let right = SegwitStr::from_str_insane("and_v(v:pk(A),and_v(v:pk(B),pk(C)))").unwrap();
+        let left = SegwitStr::from_str_insane("and_v(and_v(v:pk(A),v:pk(B)),pk(C))").unwrap();
+        assert_ne!(right, left);
+        assert_eq!(right.normalize(), left);
+        assert_eq!(left.normalize(), left);
+
+        // Chains nested in other fragments are rewritten too
+        let ms = SegwitStr::from_str_insane(
+            "or_d(pk(A),and_v(v:pk(B),and_v(v:pk(C),and_v(v:pk(D),older(1)))))",
+        )
+        .unwrap();
+        assert_eq!(
+            ms.normalize().to_string(),
+            "or_d(pk(A),and_v(and_v(and_v(v:pk(B),v:pk(C)),v:pk(D)),older(1)))"
+        );
+
+        // The normalized form is the one read back from the script
+        let keys = pubkeys(4);
+        let ms = Segwitv0Script::from_str_insane(&format!(
+            "and_v(v:pk({}),and_v(and_v(v:pk({}),v:pk({})),pk({})))",
+            keys[0], keys[1], keys[2], keys[3]
+        ))
+        .unwrap();
+        let normalized = ms.normalize();
+        assert_eq!(normalized.encode(), ms.encode());
+        assert_eq!(normalized.ty, ms.ty);
+        assert_eq!(
+            normalized,
+            Segwitv0Script::parse_insane(&ms.encode()).unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_never_nests() {
+        use elements::opcodes::all::OP_VERIFY;
+        use elements::script::Instruction;
+
+        let keys = pubkeys(2);
+        let ms =
+            Segwitv0Script::from_str_insane(&format!("and_v(v:pk({}),pk({}))", keys[0], keys[1]))
+                .unwrap();
+
+        // `v:` only wraps B fragments and is itself V, so wrapping a
+        // `Verify` in another one does not type check
+        let v = match ms.node {
+            Terminal::AndV(ref v, _) => Arc::clone(v),
+            _ => unreachable!(),
+        };
+        assert!(Segwitv0Script::from_ast(Terminal::Verify(v)).is_err());
+        let s = format!("and_v(v:v:pk({}),pk({}))", keys[0], keys[1]);
+        assert!(Segwitv0Script::from_str_insane(&s).is_err());
+
+        // The `VERIFY` of `v:pk` is merged into `CHECKSIGVERIFY`
+        let script = ms.encode();
+        assert!(!script
+            .instructions()
+            .any(|ins| matches!(ins, Ok(Instruction::Op(op)) if op == OP_VERIFY)));
+
+        // A redundant `VERIFY` after `CHECKSIGVERIFY` is not decoded
+        let mut bytes = script.to_bytes();
+        bytes.insert(35, OP_VERIFY.into_u8());
+        assert!(Segwitv0Script::parse_insane(&elements::Script::from(bytes)).is_err());
+    }
+
+    #[test]
+    fn map_ext() {
+        use crate::extensions::{CheckSigFromStack, LegacyVerEq};
+        type TapStr = Miniscript<String, Tap, CovenantExt<String>>;
+        type SegwitStr = Miniscript<String, Segwitv0, CovenantExt<String>>;
+
+        let csfs = match TapStr::from_str_insane("csfs(A,msg)").unwrap().node {
+            Terminal::Ext(CovenantExt::Csfs(csfs)) => csfs,
+            _ => unreachable!(),
+        };
+        let mut to_csfs = |e: CovenantExt<String>| match e {
+            CovenantExt::LegacyVerEq(_) => CovenantExt::Csfs(csfs.clone()),
+            e => e,
+        };
+
+        // Same extension type, the fragment is replaced
+        let ms = TapStr::from_str_insane("and_v(v:pk(B),ver_eq(2))").unwrap();
+        let bumped = ms
+            .map_ext(|e| match e {
+                CovenantExt::LegacyVerEq(v) => CovenantExt::LegacyVerEq(LegacyVerEq::new(v.n + 1)),
+                e => e,
+            })
+            .unwrap();
+        assert_eq!(bumped.to_string(), "and_v(v:pk(B),ver_eq(3))");
+
+        // Types are recomputed: csfs consumes a signature from the stack
+        let mapped = ms.map_ext(&mut to_csfs).unwrap();
+        assert_eq!(mapped.to_string(), "and_v(v:pk(B),csfs(A,msg))");
+        assert_ne!(mapped.ty, ms.ty);
+        assert_eq!(
+            mapped,
+            TapStr::from_str_insane(&mapped.to_string()).unwrap()
+        );
+
+        // Context checks are rerun: csfs is only available in Taproot
+        let ms = SegwitStr::from_str_insane("and_v(v:pk(B),ver_eq(2))").unwrap();
+        assert!(ms.map_ext(&mut to_csfs).is_err());
+
+        // The extension type itself can change
+        let no_ext = Miniscript::<String, Tap, NoExt>::from_str_insane("pk(A)").unwrap();
+        let ms: Miniscript<String, Tap, CheckSigFromStack<String>> =
+            no_ext.map_ext(|e| match e {}).unwrap();
+        assert_eq!(ms.to_string(), "pk(A)");
+    }
+
+    #[test]
+    fn locktime_out_of_range() {
+        use crate::Error;
+
+        // Timelocks of 2^31 and above are encoded as 5-byte pushes
+        let ms = Segwitv0Script::from_str_insane("after(3000000000)").unwrap();
+        assert_eq!(Segwitv0Script::parse_insane(&ms.encode()).unwrap(), ms);
+        let ms = Segwitv0Script::from_str_insane("after(4294967295)").unwrap();
+        assert_eq!(Segwitv0Script::parse_insane(&ms.encode()).unwrap(), ms);
+
+        assert_eq!(
+            Segwitv0Script::from_str_insane("after(4294967296)").unwrap_err(),
+            Error::LocktimeOutOfRange(4294967296)
+        );
+        assert_eq!(
+            Segwitv0Script::from_str_insane("older(-1)").unwrap_err(),
+            Error::LocktimeOutOfRange(-1)
+        );
+        // <2^32> CHECKLOCKTIMEVERIFY
+        assert_eq!(
+            Segwitv0Script::parse_insane(&hex_script("050000000001b1")).unwrap_err(),
+            Error::LocktimeOutOfRange(4294967296)
+        );
+        // <-2^31> CHECKSEQUENCEVERIFY
+        assert_eq!(
+            Segwitv0Script::parse_insane(&hex_script("050000008080b2")).unwrap_err(),
+            Error::LocktimeOutOfRange(-2147483648)
+        );
+    }
+
+    #[test]
+    fn decode_degenerate_thresh() {
+        let key = "028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa";
+        // <key> CHECKSIG <k> EQUAL
+        let thresh_hex = |k: &str| format!("21{}ac{}87", key, k);
+
+        // A single child threshold is valid
+        let ms = Segwitv0Script::parse_insane(&hex_script(&thresh_hex("51"))).unwrap();
+        assert_eq!(ms.to_string(), format!("thresh(1,pk({}))", key));
+
+        // The decoder always pops at least one sub, and the type check of
+        // the reduced thresh rejects k == 0 and k > n
+        match Segwitv0Script::parse_insane(&hex_script(&thresh_hex("00"))) {
+            Err(crate::Error::TypeCheck(s)) => assert!(s.contains("threshold value of 0")),
+            x => panic!("unexpected result {:?}", x),
+        }
+        match Segwitv0Script::parse_insane(&hex_script(&thresh_hex("52"))) {
+            Err(crate::Error::TypeCheck(s)) => assert!(s.contains("2-of-1 threshold")),
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_else_without_if() {
+        // SWAP 1 ELSE 1 ENDIF
+        match Segwitv0Script::parse_insane(&hex_script("7c51675168")) {
+            Err(crate::Error::Unexpected(s)) => assert!(s.contains("expected IF or NOTIF")),
+            x => panic!("unexpected result {:?}", x),
+        }
+        // 1 ELSE 1 ENDIF
+        assert_eq!(
+            Segwitv0Script::parse_insane(&hex_script("51675168")),
+            Err(crate::Error::UnexpectedStart)
+        );
+    }
+
+    #[test]
+    fn parse_malformed_scripts_does_not_panic() {
+        for hex in &[
+            "", "68", "6768", "9a", "64", "676868", "7c", "6b6c", "636768", "9387",
+        ] {
+            assert!(Segwitv0Script::parse_insane(&hex_script(hex)).is_err());
+        }
+    }
 }
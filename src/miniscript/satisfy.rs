@@ -7,6 +7,7 @@
 //! scriptpubkeys.
 //!
 
+use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::{cmp, i64, mem};
@@ -19,9 +20,10 @@ use elements::taproot::{ControlBlock, LeafVersion, TapLeafHash};
 use elements::{self, confidential, secp256k1_zkp, LockTime, OutPoint, Script, Sequence};
 
 use super::context::SigType;
+use super::limits::MAX_SCRIPT_ELEMENT_SIZE;
 use crate::extensions::{CsfsMsg, ParseableExt};
-use crate::util::witness_size;
-use crate::{Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
+use crate::util::varint_len;
+use crate::{HashKind, Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
 
 /// Type alias for a signature/hashtype pair
 pub type ElementsSig = (secp256k1_zkp::ecdsa::Signature, elements::EcdsaSigHashType);
@@ -594,6 +596,326 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
     }
 }
 
+/// A [`Satisfier`] that draws signatures, preimages and timelock checks from
+/// callbacks instead of pre-populated maps. This allows signing lazily, e.g.
+/// only asking a hardware device for the signatures that are actually needed.
+///
+/// Callbacks that are not set behave like the default [`Satisfier`] methods.
+#[allow(clippy::type_complexity)]
+pub struct FnSatisfier<'a, Pk: MiniscriptKey> {
+    ecdsa_sig: Option<RefCell<Box<dyn FnMut(&Pk) -> Option<ElementsSig> + 'a>>>,
+    tap_leaf_script_sig:
+        Option<RefCell<Box<dyn FnMut(&Pk, &TapLeafHash) -> Option<elements::SchnorrSig> + 'a>>>,
+    sha256: Option<RefCell<Box<dyn FnMut(&Pk::Sha256) -> Option<Preimage32> + 'a>>>,
+    hash256: Option<RefCell<Box<dyn FnMut(&Pk::Hash256) -> Option<Preimage32> + 'a>>>,
+    ripemd160: Option<RefCell<Box<dyn FnMut(&Pk::Ripemd160) -> Option<Preimage32> + 'a>>>,
+    hash160: Option<RefCell<Box<dyn FnMut(&Pk::Hash160) -> Option<Preimage32> + 'a>>>,
+    older: Option<RefCell<Box<dyn FnMut(Sequence) -> bool + 'a>>>,
+    after: Option<RefCell<Box<dyn FnMut(LockTime) -> bool + 'a>>>,
+}
+
+impl<'a, Pk: MiniscriptKey> Default for FnSatisfier<'a, Pk> {
+    fn default() -> Self {
+        FnSatisfier {
+            ecdsa_sig: None,
+            tap_leaf_script_sig: None,
+            sha256: None,
+            hash256: None,
+            ripemd160: None,
+            hash160: None,
+            older: None,
+            after: None,
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey> FnSatisfier<'a, Pk> {
+    /// Create a satisfier without any callbacks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up ecdsa signatures with the given callback
+    pub fn with_ecdsa_sig<F: FnMut(&Pk) -> Option<ElementsSig> + 'a>(mut self, f: F) -> Self {
+        self.ecdsa_sig = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Look up tapscript signatures with the given callback
+    pub fn with_tap_leaf_script_sig<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Pk, &TapLeafHash) -> Option<elements::SchnorrSig> + 'a,
+    {
+        self.tap_leaf_script_sig = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Look up sha256 preimages with the given callback
+    pub fn with_sha256<F: FnMut(&Pk::Sha256) -> Option<Preimage32> + 'a>(mut self, f: F) -> Self {
+        self.sha256 = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Look up hash256 preimages with the given callback
+    pub fn with_hash256<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Pk::Hash256) -> Option<Preimage32> + 'a,
+    {
+        self.hash256 = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Look up ripemd160 preimages with the given callback
+    pub fn with_ripemd160<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Pk::Ripemd160) -> Option<Preimage32> + 'a,
+    {
+        self.ripemd160 = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Look up hash160 preimages with the given callback
+    pub fn with_hash160<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Pk::Hash160) -> Option<Preimage32> + 'a,
+    {
+        self.hash160 = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Check relative timelocks with the given callback
+    pub fn with_older<F: FnMut(Sequence) -> bool + 'a>(mut self, f: F) -> Self {
+        self.older = Some(RefCell::new(Box::new(f)));
+        self
+    }
+
+    /// Check absolute timelocks with the given callback
+    pub fn with_after<F: FnMut(LockTime) -> bool + 'a>(mut self, f: F) -> Self {
+        self.after = Some(RefCell::new(Box::new(f)));
+        self
+    }
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for FnSatisfier<'a, Pk> {
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<ElementsSig> {
+        self.ecdsa_sig.as_ref().and_then(|f| (f.borrow_mut())(pk))
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, h: &TapLeafHash) -> Option<elements::SchnorrSig> {
+        self.tap_leaf_script_sig
+            .as_ref()
+            .and_then(|f| (f.borrow_mut())(pk, h))
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.sha256.as_ref().and_then(|f| (f.borrow_mut())(h))
+    }
+
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
+        self.hash256.as_ref().and_then(|f| (f.borrow_mut())(h))
+    }
+
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
+        self.ripemd160.as_ref().and_then(|f| (f.borrow_mut())(h))
+    }
+
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
+        self.hash160.as_ref().and_then(|f| (f.borrow_mut())(h))
+    }
+
+    fn check_older(&self, t: Sequence) -> bool {
+        self.older.as_ref().map_or(false, |f| (f.borrow_mut())(t))
+    }
+
+    fn check_after(&self, n: LockTime) -> bool {
+        self.after.as_ref().map_or(false, |f| (f.borrow_mut())(n))
+    }
+}
+
+/// A [`Satisfier`] made of an ordered list of satisfiers. Every lookup is
+/// answered by the first layer which has the requested data, so earlier
+/// layers take priority over later ones. Timelock checks pass if any layer
+/// accepts them.
+///
+/// This allows e.g. passing the signature maps of several signers without
+/// merging them first. Use a tuple of satisfiers to combine satisfiers of
+/// different types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayeredSatisfier<S> {
+    layers: Vec<S>,
+}
+
+impl<S> LayeredSatisfier<S> {
+    /// Create a satisfier from layers in decreasing priority
+    pub fn new(layers: Vec<S>) -> Self {
+        LayeredSatisfier { layers }
+    }
+
+    /// Add a layer with lower priority than all existing layers
+    pub fn push(&mut self, layer: S) {
+        self.layers.push(layer)
+    }
+
+    /// The layers, in decreasing priority
+    pub fn layers(&self) -> &[S] {
+        &self.layers
+    }
+}
+
+impl<S> Default for LayeredSatisfier<S> {
+    fn default() -> Self {
+        LayeredSatisfier { layers: vec![] }
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for LayeredSatisfier<S> {
+    fn lookup_ecdsa_sig(&self, p: &Pk) -> Option<ElementsSig> {
+        self.layers.iter().find_map(|s| s.lookup_ecdsa_sig(p))
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, p: &Pk, h: &TapLeafHash) -> Option<elements::SchnorrSig> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_tap_leaf_script_sig(p, h))
+    }
+
+    fn lookup_raw_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.layers.iter().find_map(|s| s.lookup_raw_pkh_pk(pkh))
+    }
+
+    fn lookup_raw_pkh_x_only_pk(&self, pkh: &hash160::Hash) -> Option<XOnlyPublicKey> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_raw_pkh_x_only_pk(pkh))
+    }
+
+    fn lookup_raw_pkh_ecdsa_sig(
+        &self,
+        pkh: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, ElementsSig)> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_raw_pkh_ecdsa_sig(pkh))
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<elements::SchnorrSig> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_tap_key_spend_sig())
+    }
+
+    fn lookup_raw_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(hash160::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, elements::SchnorrSig)> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_raw_pkh_tap_leaf_script_sig(pkh))
+    }
+
+    fn lookup_tap_control_block_map(
+        &self,
+    ) -> Option<&BTreeMap<ControlBlock, (elements::Script, LeafVersion)>> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_tap_control_block_map())
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.layers.iter().find_map(|s| s.lookup_sha256(h))
+    }
+
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
+        self.layers.iter().find_map(|s| s.lookup_hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
+        self.layers.iter().find_map(|s| s.lookup_ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
+        self.layers.iter().find_map(|s| s.lookup_hash160(h))
+    }
+
+    fn check_older(&self, t: Sequence) -> bool {
+        self.layers.iter().any(|s| s.check_older(t))
+    }
+
+    fn check_after(&self, n: LockTime) -> bool {
+        self.layers.iter().any(|s| s.check_after(n))
+    }
+
+    fn lookup_nversion(&self) -> Option<u32> {
+        self.layers.iter().find_map(|s| s.lookup_nversion())
+    }
+
+    fn lookup_hashprevouts(&self) -> Option<sha256d::Hash> {
+        self.layers.iter().find_map(|s| s.lookup_hashprevouts())
+    }
+
+    fn lookup_hashsequence(&self) -> Option<sha256d::Hash> {
+        self.layers.iter().find_map(|s| s.lookup_hashsequence())
+    }
+
+    fn lookup_hashissuances(&self) -> Option<sha256d::Hash> {
+        self.layers.iter().find_map(|s| s.lookup_hashissuances())
+    }
+
+    fn lookup_outpoint(&self) -> Option<OutPoint> {
+        self.layers.iter().find_map(|s| s.lookup_outpoint())
+    }
+
+    fn lookup_scriptcode(&self) -> Option<&Script> {
+        self.layers.iter().find_map(|s| s.lookup_scriptcode())
+    }
+
+    fn lookup_value(&self) -> Option<confidential::Value> {
+        self.layers.iter().find_map(|s| s.lookup_value())
+    }
+
+    fn lookup_nsequence(&self) -> Option<u32> {
+        self.layers.iter().find_map(|s| s.lookup_nsequence())
+    }
+
+    fn lookup_outputs(&self) -> Option<&[elements::TxOut]> {
+        self.layers.iter().find_map(|s| s.lookup_outputs())
+    }
+
+    fn lookup_nlocktime(&self) -> Option<u32> {
+        self.layers.iter().find_map(|s| s.lookup_nlocktime())
+    }
+
+    fn lookup_sighashu32(&self) -> Option<u32> {
+        self.layers.iter().find_map(|s| s.lookup_sighashu32())
+    }
+
+    fn lookup_spent_utxos(&self) -> Option<&[elements::TxOut]> {
+        self.layers.iter().find_map(|s| s.lookup_spent_utxos())
+    }
+
+    fn lookup_tx(&self) -> Option<&elements::Transaction> {
+        self.layers.iter().find_map(|s| s.lookup_tx())
+    }
+
+    fn lookup_curr_inp(&self) -> Option<usize> {
+        self.layers.iter().find_map(|s| s.lookup_curr_inp())
+    }
+
+    fn lookup_csfs_sig(&self, pk: &XOnlyPublicKey, msg: &CsfsMsg) -> Option<schnorr::Signature> {
+        self.layers.iter().find_map(|s| s.lookup_csfs_sig(pk, msg))
+    }
+
+    fn lookup_price_oracle_sig(
+        &self,
+        pk: &XOnlyPublicKey,
+        time: u64,
+    ) -> Option<(schnorr::Signature, i64, u64)> {
+        self.layers
+            .iter()
+            .find_map(|s| s.lookup_price_oracle_sig(pk, time))
+    }
+}
+
 macro_rules! impl_tuple_satisfier {
     ($($ty:ident),*) => {
         #[allow(non_snake_case)]
@@ -928,11 +1250,55 @@ impl_tuple_satisfier!(A, B, C, D, E, F);
 impl_tuple_satisfier!(A, B, C, D, E, F, G);
 impl_tuple_satisfier!(A, B, C, D, E, F, G, H);
 
+/// The role played by an item of a satisfaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WitnessRole {
+    /// An ECDSA or Schnorr signature returned by the satisfier, including
+    /// its sighash suffix
+    Signature,
+    /// A public key, either of the descriptor or returned by the satisfier
+    /// for a raw key-hash
+    PublicKey,
+    /// A hashlock preimage returned by the satisfier
+    Preimage,
+    /// The witness script, redeem script or taproot leaf script
+    Script,
+    /// A taproot control block
+    ControlBlock,
+    /// An empty item: a dissatisfaction, a false branch selector or the
+    /// dummy element consumed by `CHECKMULTISIG`
+    Empty,
+    /// Any other item, such as a branch selector or extension data
+    Other,
+}
+
+/// A single item of a satisfaction, tagged with its role
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WitnessItem {
+    /// The role of the item
+    pub role: WitnessRole,
+    /// The raw item
+    pub data: Vec<u8>,
+}
+
+impl WitnessItem {
+    /// Tag the raw item `data` with `role`
+    pub fn new(role: WitnessRole, data: Vec<u8>) -> Self {
+        WitnessItem { role, data }
+    }
+}
+
+// Helper function to calculate the size of a stack of witness items
+fn stack_size(stack: &[WitnessItem]) -> usize {
+    stack.iter().map(|item| item.data.len()).sum::<usize>() + varint_len(stack.len())
+}
+
 /// A witness, if available, for a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Witness {
-    /// Witness Available and the value of the witness
-    Stack(Vec<Vec<u8>>),
+    /// Witness Available and the items of the witness, tagged with the
+    /// role they play
+    Stack(Vec<WitnessItem>),
     /// Third party can possibly satisfy the fragment but we cannot
     /// Witness Unavailable
     Unavailable,
@@ -951,8 +1317,8 @@ impl Ord for Witness {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
         match (self, other) {
             (&Witness::Stack(ref v1), &Witness::Stack(ref v2)) => {
-                let w1 = witness_size(v1);
-                let w2 = witness_size(v2);
+                let w1 = stack_size(v1);
+                let w2 = stack_size(v2);
                 w1.cmp(&w2)
             }
             (&Witness::Stack(_), _) => cmp::Ordering::Less,
@@ -974,13 +1340,15 @@ impl Witness {
     ) -> Self {
         match Ctx::sig_type() {
             super::context::SigType::Ecdsa => match sat.lookup_ecdsa_sig(pk) {
-                Some(sig) => Witness::Stack(vec![elementssig_to_rawsig(&sig)]),
+                Some(sig) => {
+                    Witness::with_role(WitnessRole::Signature, vec![elementssig_to_rawsig(&sig)])
+                }
                 // Signatures cannot be forged
                 None => Witness::Impossible,
             },
             super::context::SigType::Schnorr => match sat.lookup_tap_leaf_script_sig(pk, leaf_hash)
             {
-                Some(sig) => Witness::Stack(vec![sig.to_vec()]),
+                Some(sig) => Witness::with_role(WitnessRole::Signature, vec![sig.to_vec()]),
                 // Signatures cannot be forged
                 None => Witness::Impossible,
             },
@@ -996,11 +1364,13 @@ impl Witness {
         // instead of impossible since it is the same as pub-key hashes
         match Ctx::sig_type() {
             SigType::Ecdsa => match sat.lookup_raw_pkh_pk(pkh) {
-                Some(pk) => Witness::Stack(vec![pk.to_bytes()]),
+                Some(pk) => Witness::with_role(WitnessRole::PublicKey, vec![pk.to_bytes()]),
                 None => Witness::Unavailable,
             },
             SigType::Schnorr => match sat.lookup_raw_pkh_x_only_pk(pkh) {
-                Some(pk) => Witness::Stack(vec![pk.serialize().to_vec()]),
+                Some(pk) => {
+                    Witness::with_role(WitnessRole::PublicKey, vec![pk.serialize().to_vec()])
+                }
                 None => Witness::Unavailable,
             },
         }
@@ -1016,14 +1386,20 @@ impl Witness {
             SigType::Ecdsa => match sat.lookup_raw_pkh_ecdsa_sig(pkh) {
                 Some((pk, sig)) => {
                     let ser_sig = elementssig_to_rawsig(&sig);
-                    Witness::Stack(vec![ser_sig, pk.to_public_key().to_bytes()])
+                    Witness::Stack(vec![
+                        WitnessItem::new(WitnessRole::Signature, ser_sig),
+                        WitnessItem::new(WitnessRole::PublicKey, pk.to_public_key().to_bytes()),
+                    ])
                 }
                 None => Witness::Impossible,
             },
             SigType::Schnorr => match sat.lookup_raw_pkh_tap_leaf_script_sig(&(*pkh, *leaf_hash)) {
                 Some((pk, sig)) => Witness::Stack(vec![
-                    sig.to_vec(),
-                    pk.to_x_only_pubkey().serialize().to_vec(),
+                    WitnessItem::new(WitnessRole::Signature, sig.to_vec()),
+                    WitnessItem::new(
+                        WitnessRole::PublicKey,
+                        pk.to_x_only_pubkey().serialize().to_vec(),
+                    ),
                 ]),
                 None => Witness::Impossible,
             },
@@ -1036,7 +1412,7 @@ impl Witness {
         h: &Pk::Ripemd160,
     ) -> Self {
         match sat.lookup_ripemd160(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
+            Some(pre) => Witness::with_role(WitnessRole::Preimage, vec![pre.to_vec()]),
             // Note hash preimages are unavailable instead of impossible
             None => Witness::Unavailable,
         }
@@ -1045,7 +1421,7 @@ impl Witness {
     /// Turn a hash preimage into (part of) a satisfaction
     pub fn hash160_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: &Pk::Hash160) -> Self {
         match sat.lookup_hash160(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
+            Some(pre) => Witness::with_role(WitnessRole::Preimage, vec![pre.to_vec()]),
             // Note hash preimages are unavailable instead of impossible
             None => Witness::Unavailable,
         }
@@ -1054,7 +1430,7 @@ impl Witness {
     /// Turn a hash preimage into (part of) a satisfaction
     pub fn sha256_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: &Pk::Sha256) -> Self {
         match sat.lookup_sha256(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
+            Some(pre) => Witness::with_role(WitnessRole::Preimage, vec![pre.to_vec()]),
             // Note hash preimages are unavailable instead of impossible
             None => Witness::Unavailable,
         }
@@ -1063,7 +1439,7 @@ impl Witness {
     /// Turn a hash preimage into (part of) a satisfaction
     pub fn hash256_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: &Pk::Hash256) -> Self {
         match sat.lookup_hash256(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
+            Some(pre) => Witness::with_role(WitnessRole::Preimage, vec![pre.to_vec()]),
             // Note hash preimages are unavailable instead of impossible
             None => Witness::Unavailable,
         }
@@ -1071,9 +1447,36 @@ impl Witness {
 }
 
 impl Witness {
+    /// Size of the witness stack in bytes, including the element count and
+    /// per-element length prefixes. `None` if the witness is not available.
+    pub fn size(&self) -> Option<usize> {
+        match *self {
+            Witness::Stack(ref stack) => Some(stack_size(stack)),
+            Witness::Unavailable | Witness::Impossible => None,
+        }
+    }
+
+    /// Construct a satisfaction from raw items which all play `role`
+    pub fn with_role(role: WitnessRole, stack: Vec<Vec<u8>>) -> Self {
+        Witness::Stack(
+            stack
+                .into_iter()
+                .map(|data| WitnessItem::new(role, data))
+                .collect(),
+        )
+    }
+
+    /// The raw items of the witness stack, if it is available
+    pub fn raw_stack(&self) -> Option<Vec<Vec<u8>>> {
+        match *self {
+            Witness::Stack(ref stack) => Some(stack.iter().map(|item| item.data.clone()).collect()),
+            Witness::Unavailable | Witness::Impossible => None,
+        }
+    }
+
     /// Produce something like a 32-byte 0 push
     pub fn hash_dissatisfaction() -> Self {
-        Witness::Stack(vec![vec![0; 32]])
+        Witness::with_role(WitnessRole::Other, vec![vec![0; 32]])
     }
 
     /// Construct a satisfaction equivalent to an empty stack
@@ -1083,12 +1486,12 @@ impl Witness {
 
     /// Construct a satisfaction equivalent to `OP_1`
     pub fn push_1() -> Self {
-        Witness::Stack(vec![vec![1]])
+        Witness::with_role(WitnessRole::Other, vec![vec![1]])
     }
 
     /// Construct a satisfaction equivalent to a single empty push
     pub fn push_0() -> Self {
-        Witness::Stack(vec![vec![]])
+        Witness::with_role(WitnessRole::Empty, vec![vec![]])
     }
 
     /// Concatenate, or otherwise combine, two satisfactions
@@ -1104,6 +1507,16 @@ impl Witness {
     }
 }
 
+/// Options for [`Miniscript::satisfy_with_options`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SatisfyOptions {
+    /// Only produce a non-malleable witness. Fail with
+    /// [`crate::Error::NoNonMalleableSatisfaction`] if the only available
+    /// satisfactions are malleable, or if the script breaks the context
+    /// specific malleability rules, such as `or_i` under Legacy.
+    pub require_non_malleable: bool,
+}
+
 /// A (dis)satisfaction of a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Satisfaction {
@@ -1194,7 +1607,7 @@ impl Satisfaction {
                 // Pubkey.
                 (_, &Witness::Unavailable) | (_, &Witness::Impossible) => i64::MIN,
                 (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
-                    witness_size(s) as i64 - witness_size(d) as i64
+                    stack_size(s) as i64 - stack_size(d) as i64
                 }
             };
             let is_impossible = sats[i].stack == Witness::Impossible;
@@ -1313,7 +1726,7 @@ impl Satisfaction {
                 // This is only possible when one of the branches has PkH
                 (_, &Witness::Unavailable) | (_, &Witness::Impossible) => i64::MIN,
                 (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
-                    witness_size(s) as i64 - witness_size(d) as i64
+                    stack_size(s) as i64 - stack_size(d) as i64
                 }
             }
         });
@@ -1418,12 +1831,12 @@ impl Satisfaction {
             },
             Terminal::PkH(ref pk) => {
                 let wit = Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash);
-                let pk_bytes = match Ctx::sig_type() {
-                    SigType::Ecdsa => pk.to_public_key().to_bytes(),
-                    SigType::Schnorr => pk.to_x_only_pubkey().serialize().to_vec(),
-                };
+                let pk_bytes = Ctx::serialize_pk(pk);
                 Satisfaction {
-                    stack: Witness::combine(wit, Witness::Stack(vec![pk_bytes])),
+                    stack: Witness::combine(
+                        wit,
+                        Witness::with_role(WitnessRole::PublicKey, vec![pk_bytes]),
+                    ),
                     has_sig: true,
                 }
             }
@@ -1478,6 +1891,18 @@ impl Satisfaction {
                 stack: Witness::hash256_preimage(stfr, h),
                 has_sig: false,
             },
+            Terminal::RawHashLock(ref hash, size_guard) => Satisfaction {
+                // Preimages are 32 bytes, so a guard checking any other
+                // size cannot be satisfied with them
+                stack: match (hash, size_guard) {
+                    (_, Some(_)) => Witness::Unavailable,
+                    (&HashKind::Sha256(ref h), None) => Witness::sha256_preimage(stfr, h),
+                    (&HashKind::Hash256(ref h), None) => Witness::hash256_preimage(stfr, h),
+                    (&HashKind::Ripemd160(ref h), None) => Witness::ripemd160_preimage(stfr, h),
+                    (&HashKind::Hash160(ref h), None) => Witness::hash160_preimage(stfr, h),
+                },
+                has_sig: false,
+            },
             Terminal::True => Satisfaction {
                 stack: Witness::empty(),
                 has_sig: false,
@@ -1669,7 +2094,7 @@ impl Satisfaction {
             Terminal::MultiA(k, ref keys) => {
                 // Collect all available signatures
                 let mut sig_count = 0;
-                let mut sigs = vec![vec![vec![]]; keys.len()];
+                let mut sigs = vec![vec![WitnessItem::new(WitnessRole::Empty, vec![])]; keys.len()];
                 for (i, pk) in keys.iter().rev().enumerate() {
                     match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
                         Witness::Stack(sig) => {
@@ -1738,12 +2163,12 @@ impl Satisfaction {
                 has_sig: false,
             },
             Terminal::PkH(ref pk) => {
-                let pk_bytes = match Ctx::sig_type() {
-                    SigType::Ecdsa => pk.to_public_key().to_bytes(),
-                    SigType::Schnorr => pk.to_x_only_pubkey().serialize().to_vec(),
-                };
+                let pk_bytes = Ctx::serialize_pk(pk);
                 Satisfaction {
-                    stack: Witness::combine(Witness::push_0(), Witness::Stack(vec![pk_bytes])),
+                    stack: Witness::combine(
+                        Witness::push_0(),
+                        Witness::with_role(WitnessRole::PublicKey, vec![pk_bytes]),
+                    ),
                     has_sig: false,
                 }
             }
@@ -1777,6 +2202,16 @@ impl Satisfaction {
                 stack: Witness::hash_dissatisfaction(),
                 has_sig: false,
             },
+            Terminal::RawHashLock(_, size_guard) => Satisfaction {
+                stack: match size_guard {
+                    None => Witness::hash_dissatisfaction(),
+                    Some(n) if n as usize <= MAX_SCRIPT_ELEMENT_SIZE => {
+                        Witness::with_role(WitnessRole::Other, vec![vec![0; n as usize]])
+                    }
+                    Some(_) => Witness::Impossible,
+                },
+                has_sig: false,
+            },
             Terminal::Alt(ref sub)
             | Terminal::Swap(ref sub)
             | Terminal::Check(ref sub)
@@ -1882,11 +2317,11 @@ impl Satisfaction {
                 has_sig: false,
             },
             Terminal::Multi(k, _) => Satisfaction {
-                stack: Witness::Stack(vec![vec![]; k + 1]),
+                stack: Witness::with_role(WitnessRole::Empty, vec![vec![]; k + 1]),
                 has_sig: false,
             },
             Terminal::MultiA(_, ref pks) => Satisfaction {
-                stack: Witness::Stack(vec![vec![]; pks.len()]),
+                stack: Witness::with_role(WitnessRole::Empty, vec![vec![]; pks.len()]),
                 has_sig: false,
             },
             Terminal::Ext(ref e) => e.dissatisfy(stfr),
@@ -1916,6 +2351,179 @@ impl Satisfaction {
         )
     }
 
+    // Keep the witness only if it is actually available
+    fn available(sat: Self) -> Vec<Self> {
+        match sat.stack {
+            Witness::Stack(_) => vec![sat],
+            Witness::Unavailable | Witness::Impossible => vec![],
+        }
+    }
+
+    // Keep the `max` cheapest distinct witnesses of `sats`, cheapest first.
+    // Combining witnesses adds up their sizes, up to the stack length prefix,
+    // so the cheapest witnesses of a fragment are made of the cheapest
+    // witnesses of its children
+    fn cheapest(mut sats: Vec<Self>, max: usize) -> Vec<Self> {
+        sats.sort_by(|a, b| a.stack.cmp(&b.stack));
+        let mut ret: Vec<Self> = Vec::with_capacity(cmp::min(sats.len(), max));
+        for sat in sats {
+            if ret.len() >= max {
+                break;
+            }
+            if !ret.iter().any(|s| Self::same_items(&s.stack, &sat.stack)) {
+                ret.push(sat);
+            }
+        }
+        ret
+    }
+
+    // Whether two witnesses push the same items, whatever their roles
+    fn same_items(one: &Witness, two: &Witness) -> bool {
+        match (one, two) {
+            (&Witness::Stack(ref one), &Witness::Stack(ref two)) => {
+                one.len() == two.len() && one.iter().zip(two).all(|(a, b)| a.data == b.data)
+            }
+            _ => one == two,
+        }
+    }
+
+    // Combine every pair of (dis)satisfactions, keeping the `max` cheapest
+    fn cross(ones: &[Self], twos: &[Self], max: usize) -> Vec<Self> {
+        let mut ret = Vec::with_capacity(ones.len() * twos.len());
+        for one in ones {
+            for two in twos {
+                ret.push(Satisfaction::combine(one.clone(), two.clone()));
+            }
+        }
+        Self::cheapest(ret, max)
+    }
+
+    // Enumerate the `max` cheapest distinct available satisfactions of a
+    // fragment, cheapest first. Dissatisfactions of sub-fragments are not
+    // enumerated, only the cheapest one is used.
+    fn all_satisfy_helper<Pk, Ctx, Sat, Ext>(
+        term: &Terminal<Pk, Ctx, Ext>,
+        stfr: &Sat,
+        root_has_sig: bool,
+        leaf_hash: &TapLeafHash,
+        max: usize,
+    ) -> Vec<Self>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+        Ext: ParseableExt,
+    {
+        let all = |node: &Terminal<Pk, Ctx, Ext>| {
+            Self::all_satisfy_helper(node, stfr, root_has_sig, leaf_hash, max)
+        };
+        let dissat = |node: &Terminal<Pk, Ctx, Ext>| {
+            Self::available(Self::dissatisfy_helper(
+                node,
+                stfr,
+                root_has_sig,
+                leaf_hash,
+                &mut Satisfaction::minimum_mall,
+                &mut Satisfaction::thresh_mall,
+            ))
+        };
+        let push_1 = [Satisfaction {
+            stack: Witness::push_1(),
+            has_sig: false,
+        }];
+        let push_0 = [Satisfaction {
+            stack: Witness::push_0(),
+            has_sig: false,
+        }];
+
+        let mut ret = match *term {
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => all(&sub.node),
+            Terminal::DupIf(ref sub) => Self::cross(&all(&sub.node), &push_1, max),
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                Self::cross(&all(&r.node), &all(&l.node), max)
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                let mut ret = Self::cross(&all(&b.node), &all(&a.node), max);
+                ret.extend(Self::cross(&all(&c.node), &dissat(&a.node), max));
+                ret
+            }
+            Terminal::OrB(ref l, ref r) => {
+                let mut ret = Self::cross(&all(&r.node), &dissat(&l.node), max);
+                ret.extend(Self::cross(&dissat(&r.node), &all(&l.node), max));
+                ret
+            }
+            Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
+                let mut ret = all(&l.node);
+                ret.extend(Self::cross(&all(&r.node), &dissat(&l.node), max));
+                ret
+            }
+            Terminal::OrI(ref l, ref r) => {
+                let mut ret = Self::cross(&all(&l.node), &push_1, max);
+                ret.extend(Self::cross(&all(&r.node), &push_0, max));
+                ret
+            }
+            Terminal::Thresh(k, ref subs) => {
+                // by_count[c] holds the witnesses of the subs seen so far
+                // with exactly `c` of them satisfied
+                let mut by_count = vec![Vec::new(); k + 1];
+                by_count[0].push(Satisfaction::empty());
+                for sub in subs {
+                    let sats = all(&sub.node);
+                    let nsat = dissat(&sub.node);
+                    for c in (0..=k).rev() {
+                        let mut next = Self::cross(&nsat, &by_count[c], max);
+                        if c > 0 {
+                            next.extend(Self::cross(&sats, &by_count[c - 1], max));
+                        }
+                        by_count[c] = Self::cheapest(next, max);
+                    }
+                }
+                by_count.pop().expect("k + 1 > 0")
+            }
+            Terminal::PkK(..)
+            | Terminal::PkH(..)
+            | Terminal::RawPkH(..)
+            | Terminal::After(..)
+            | Terminal::Older(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..)
+            | Terminal::RawHashLock(..)
+            | Terminal::True
+            | Terminal::False
+            | Terminal::Multi(..)
+            | Terminal::MultiA(..)
+            | Terminal::Ext(..) => {
+                Self::available(Self::satisfy_mall(term, stfr, root_has_sig, leaf_hash))
+            }
+        };
+        Self::cheapest(ret, max)
+    }
+
+    /// Produce the `max` cheapest distinct available satisfactions(possibly
+    /// malleable), sorted by witness size
+    pub(super) fn all_satisfactions<Pk, Ctx, Sat, Ext>(
+        term: &Terminal<Pk, Ctx, Ext>,
+        stfr: &Sat,
+        root_has_sig: bool,
+        leaf_hash: &TapLeafHash,
+        max: usize,
+    ) -> Vec<Self>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+        Ext: ParseableExt,
+    {
+        Self::all_satisfy_helper(term, stfr, root_has_sig, leaf_hash, max)
+    }
+
     /// Produce a satisfaction(possibly malleable)
     pub(super) fn satisfy_mall<
         Pk: MiniscriptKey + ToPublicKey,
@@ -177,6 +177,18 @@ impl Property for Correctness {
         }
     }
 
+    fn from_raw_hashlock(_: usize, size_guard: Option<u32>) -> Self {
+        // Without a guard rejecting it, the empty input is a dissatisfaction
+        let input = match size_guard {
+            Some(0) | None => Input::One,
+            Some(_) => Input::OneNonZero,
+        };
+        Correctness {
+            input,
+            ..Self::from_hash()
+        }
+    }
+
     fn from_time(_: u32) -> Self {
         Correctness {
             base: Base::B,
@@ -10,7 +10,8 @@ use elements::{LockTime, PackedLockTime, Sequence};
 
 use super::{Error, ErrorKind, Property, ScriptContext};
 use crate::miniscript::context::SigType;
-use crate::{script_num_size, Extension, MiniscriptKey, Terminal};
+use crate::util::varint_len;
+use crate::{push_opcode_size, script_num_size, Extension, MiniscriptKey, Terminal};
 
 /// Timelock information for satisfaction of a fragment.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
@@ -204,6 +205,7 @@ impl Property for ExtData {
     }
 
     fn from_pk_h<Ctx: ScriptContext>() -> Self {
+        let key_len = Ctx::pkh_witness_key_len();
         ExtData {
             pk_cost: 24,
             has_free_verify: false,
@@ -211,13 +213,10 @@ impl Property for ExtData {
             stack_elem_count_sat: Some(2),
             stack_elem_count_dissat: Some(2),
             max_sat_size: match Ctx::sig_type() {
-                SigType::Ecdsa => Some((34 + 73, 34 + 73)),
-                SigType::Schnorr => Some((66 + 33, 33 + 66)),
-            },
-            max_dissat_size: match Ctx::sig_type() {
-                SigType::Ecdsa => Some((35, 35)),
-                SigType::Schnorr => Some((34, 34)),
+                SigType::Ecdsa => Some((key_len + 73, key_len + 73)),
+                SigType::Schnorr => Some((66 + key_len, key_len + 66)),
             },
+            max_dissat_size: Some((key_len + 1, key_len + 1)),
             timelock_info: TimelockInfo::default(),
             exec_stack_elem_count_sat: Some(2), // dup and hash push
             exec_stack_elem_count_dissat: Some(2),
@@ -334,6 +333,44 @@ impl Property for ExtData {
         }
     }
 
+    fn from_raw_hashlock(hash_len: usize, size_guard: Option<u32>) -> Self {
+        let hash_cost = 1 + hash_len + 1 + 1;
+        match size_guard {
+            // Only an `n` byte preimage passes the guard, and `n` zero bytes
+            // dissatisfy it
+            Some(n) => {
+                let n = n as usize;
+                let witness_size = (varint_len(n) + n, push_opcode_size(n) + n);
+                ExtData {
+                    pk_cost: 1 + script_num_size(n) + 1 + hash_cost,
+                    has_free_verify: true,
+                    ops: OpLimits::new(4, Some(0), Some(0)),
+                    stack_elem_count_sat: Some(1),
+                    stack_elem_count_dissat: Some(1),
+                    max_sat_size: Some(witness_size),
+                    max_dissat_size: Some(witness_size),
+                    timelock_info: TimelockInfo::default(),
+                    exec_stack_elem_count_sat: Some(2),
+                    exec_stack_elem_count_dissat: Some(2),
+                }
+            }
+            // Satisfied with a 32 byte preimage and dissatisfied with 32
+            // zero bytes, like the guarded fragments
+            None => ExtData {
+                pk_cost: hash_cost,
+                has_free_verify: true,
+                ops: OpLimits::new(2, Some(0), Some(0)),
+                stack_elem_count_sat: Some(1),
+                stack_elem_count_dissat: Some(1),
+                max_sat_size: Some((33, 33)),
+                max_dissat_size: Some((33, 33)),
+                timelock_info: TimelockInfo::default(),
+                exec_stack_elem_count_sat: Some(2), // <preimage hash> <hash>
+                exec_stack_elem_count_dissat: Some(2),
+            },
+        }
+    }
+
     fn from_time(_t: u32) -> Self {
         unreachable!()
     }
@@ -888,7 +925,13 @@ impl Property for ExtData {
     }
 
     fn from_ext<E: Extension>(e: &E) -> Self {
-        e.extra_prop()
+        // The context size checks all go through `pk_cost`, so take it from
+        // the extension's script size rather than trusting `extra_prop` to
+        // repeat it
+        ExtData {
+            pk_cost: e.script_size(),
+            ..e.extra_prop()
+        }
     }
 
     /// Compute the type of a fragment assuming all the children of
@@ -959,6 +1002,9 @@ impl Property for ExtData {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                Ok(Self::from_raw_hashlock(hash.hash_len(), size_guard))
+            }
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(sub.ext)),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(sub.ext)),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(sub.ext)),
@@ -287,6 +287,13 @@ pub trait Property: Sized {
         Self::from_hash()
     }
 
+    /// Type property of a hashlock with a `hash_len` byte hash, whose
+    /// preimage size guard is missing or checks a size other than 32.
+    /// Default implementation simply passes through to `from_hash`
+    fn from_raw_hashlock(_hash_len: usize, _size_guard: Option<u32>) -> Self {
+        Self::from_hash()
+    }
+
     /// Type property of a timelock
     fn from_time(t: u32) -> Self;
 
@@ -453,6 +460,9 @@ pub trait Property: Sized {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                Ok(Self::from_raw_hashlock(hash.hash_len(), size_guard))
+            }
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(get_child(&sub.node, 0)?)),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(get_child(&sub.node, 0)?)),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(get_child(&sub.node, 0)?)),
@@ -620,6 +630,13 @@ impl Property for Type {
         }
     }
 
+    fn from_raw_hashlock(hash_len: usize, size_guard: Option<u32>) -> Self {
+        Type {
+            corr: Property::from_raw_hashlock(hash_len, size_guard),
+            mall: Property::from_raw_hashlock(hash_len, size_guard),
+        }
+    }
+
     fn from_time(t: u32) -> Self {
         Type {
             corr: Property::from_time(t),
@@ -852,6 +869,9 @@ impl Property for Type {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::RawHashLock(ref hash, size_guard) => {
+                Ok(Self::from_raw_hashlock(hash.hash_len(), size_guard))
+            }
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(sub.ty)),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(sub.ty)),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(sub.ty)),
@@ -123,7 +123,7 @@ where
     fn lift(&self) -> Result<Semantic<Pk>, Error> {
         let ret = match *self {
             Terminal::PkK(ref pk) | Terminal::PkH(ref pk) => Semantic::Key(pk.clone()),
-            Terminal::RawPkH(ref _pkh) => {
+            Terminal::RawPkH(ref _pkh) | Terminal::RawHashLock(..) => {
                 return Err(Error::LiftError(LiftError::RawDescriptorLift))
             }
             Terminal::After(t) => Semantic::After(t),
@@ -164,7 +164,7 @@ where
             Terminal::Multi(k, ref keys) | Terminal::MultiA(k, ref keys) => {
                 Semantic::Threshold(k, keys.iter().map(|k| Semantic::Key(k.clone())).collect())
             }
-            Terminal::Ext(ref _e) => Err(Error::CovError(CovError::CovenantLift))?,
+            Terminal::Ext(ref e) => e.lift()?,
         }
         .normalized();
         Ok(ret)
@@ -394,6 +394,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lift_ext() {
+        use crate::descriptor::CovError;
+        use crate::{CovenantExt, Error};
+
+        // Covenant extensions do not lift to a semantic policy
+        let ms = Miniscript::<String, Segwitv0, CovenantExt<String>>::from_str_insane(
+            "and_v(v:pk(A),ver_eq(2))",
+        )
+        .unwrap();
+        assert_eq!(ms.lift(), Err(Error::CovError(CovError::CovenantLift)));
+    }
+
     #[test]
     #[cfg(feature = "compiler")]
     fn taproot_compile() {
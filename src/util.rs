@@ -2,19 +2,34 @@
 use bitcoin::hashes::Hash;
 use elements::{self, opcodes, script, PubkeyHash, Script};
 
-use crate::miniscript::context;
 use crate::{ScriptContext, ToPublicKey};
 
 pub(crate) fn varint_len(n: usize) -> usize {
     elements::VarInt(n as u64).len()
 }
 
+// Helper function to calculate the weight added to an input by a scriptSig
+// of `script_sig_size` bytes and a witness of `witness_weight`
+pub(crate) fn satisfaction_weight(script_sig_size: usize, witness_weight: usize) -> usize {
+    // scriptSig varint difference between non-satisfied (0) and satisfied
+    let scriptsig_varint_diff = varint_len(script_sig_size) - varint_len(0);
+    4 * (scriptsig_varint_diff + script_sig_size) + witness_weight
+}
+
 // Helper function to calculate witness size
 pub(crate) fn witness_size(wit: &[Vec<u8>]) -> usize {
     wit.iter().map(Vec::len).sum::<usize>() + varint_len(wit.len())
 }
 
-pub(crate) fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
+/// Convert a satisfaction witness into the `scriptSig` pushing it, for
+/// spending a `Legacy` or `BareCtx` script.
+///
+/// Elements which are valid script numbers are pushed with the smallest
+/// opcode for that number, such as `OP_0` for an empty element and `OP_1`
+/// for `[1]`, and all other elements are pushed as data. This is the
+/// `scriptSig` whose length `Legacy::check_witness` measures. For P2SH,
+/// the redeem script still has to be pushed after it.
+pub fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
     let mut b = script::Builder::new();
     for wit in witness {
         if let Ok(n) = script::read_scriptint(wit) {
@@ -103,10 +118,7 @@ impl MsKeyBuilder for script::Builder {
         Pk: ToPublicKey,
         Ctx: ScriptContext,
     {
-        match Ctx::sig_type() {
-            context::SigType::Ecdsa => self.push_key(&key.to_public_key()),
-            context::SigType::Schnorr => self.push_slice(&key.to_x_only_pubkey().serialize()),
-        }
+        self.push_slice(&Ctx::serialize_pk(key))
     }
 
     fn push_ms_key_hash<Pk, Ctx>(self, key: &Pk) -> Self
@@ -114,12 +126,7 @@ impl MsKeyBuilder for script::Builder {
         Pk: ToPublicKey,
         Ctx: ScriptContext,
     {
-        match Ctx::sig_type() {
-            context::SigType::Ecdsa => self.push_slice(&key.to_public_key().pubkey_hash()),
-            context::SigType::Schnorr => {
-                self.push_slice(&PubkeyHash::hash(&key.to_x_only_pubkey().serialize()))
-            }
-        }
+        self.push_slice(&PubkeyHash::hash(&Ctx::serialize_pk(key)))
     }
 }
 